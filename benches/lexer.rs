@@ -0,0 +1,156 @@
+use criterion::{ black_box, criterion_group, criterion_main, Criterion, Throughput };
+use fancy_regex::Regex as FancyRegex;
+use std::borrow::Cow;
+use waygum::{ Lexer, Spanned, Token };
+
+const SAMPLE: &str = r#"@ Learning C++
+/ Learning Objectives: Learn the basics of C++ /
+
+@@ History of C++
+C++ was developed by Bjarne Stroustrup at Bell Labs in the 1980s. It was originally called *C with Classes*, but was renamed C++ in 1983.
+
+> C++ was developed by Bjarne Stroustrup
+
+#[Bjarne Stroustrup at the 2012 C++Now conference](bjarne-stroustrup.jpg)
+
+@@@ Characteristics
+- Object oriented
+- Compiled
+-- Translated into machine code
+- Statically typed
+
+$$$
+#include <iostream>
+
+int main() {
+	std::cout << "Hello, World!" << std::endl;
+	return 0;
+}
+$$$
+
+| name | description |
+| int | integer |
+| float | floating-point number |
+
+#![Read more]{https://isocpp.org}
+"#;
+
+/// The regex cascade the lexer used before the line scanner, kept here as a baseline.
+/// It builds the same tokens as the scanner does for `SAMPLE`, so both do the same work.
+struct RegexLexer {
+	section: FancyRegex,
+	subsection: FancyRegex,
+	subsubsection: FancyRegex,
+	list: FancyRegex,
+	quote: FancyRegex,
+	table: FancyRegex,
+	image: FancyRegex,
+	codeblock: FancyRegex,
+	button: FancyRegex,
+	text: FancyRegex,
+}
+
+impl RegexLexer {
+	fn new() -> Self {
+		let regex = |pattern| FancyRegex::new(pattern).unwrap();
+		Self {
+			section: regex(r#"^@(?!@)\s*(\S.*)$"#),
+			subsection: regex(r#"^@@(?!@)\s*(\S.*)$"#),
+			subsubsection: regex(r#"^@@@(?!@)\s*(\S.*)$"#),
+			list: regex(r#"(^-+)\s*(\S.*)$"#),
+			quote: regex(r#"^>(?!>)\s*(\S.*)$"#),
+			table: regex(r#"^\|(.*)\|$"#),
+			image: regex(r#"^#\[(.*)\]\((.*)\)$"#),
+			codeblock: regex(r"^\$\$\$"),
+			button: regex(r#"^#!\[([^\]]*)\]\{([^\}]*)\}$"#),
+			text: regex(r#"^(.*)$"#),
+		}
+	}
+
+	fn tokenize<'a>(&self, input: &'a str) -> Vec<Spanned<Token<'a>>> {
+		let capture = |regex: &FancyRegex, line: &'a str| regex.captures(line).unwrap();
+		let text = |captures: &fancy_regex::Captures<'a>, group| Cow::Borrowed(captures.get(group).unwrap().as_str());
+		let mut tokens = vec![];
+		let mut lines = input.split_inclusive('\n');
+		while let Some(full_line) = lines.next() {
+			let line = full_line.trim_end_matches(['\n', '\r']);
+			let token = if let Some(captures) = capture(&self.section, line) {
+				Token::Section(text(&captures, 1), None)
+			} else if let Some(captures) = capture(&self.subsection, line) {
+				Token::Subsection(text(&captures, 1), None)
+			} else if let Some(captures) = capture(&self.subsubsection, line) {
+				Token::Subsubsection(text(&captures, 1), None)
+			} else if let Some(captures) = capture(&self.list, line) {
+				Token::List {
+					depth: captures.get(1).unwrap().as_str().len(),
+					text: text(&captures, 2),
+				}
+			} else if let Some(captures) = capture(&self.quote, line) {
+				Token::Quote(text(&captures, 1))
+			} else if let Some(captures) = capture(&self.table, line) {
+				let columns = captures.get(1).unwrap().as_str().split('|');
+				Token::Table(columns.map(|column| Spanned::from(Cow::Borrowed(column.trim()))).collect())
+			} else if let Some(captures) = capture(&self.image, line) {
+				Token::Image {
+					caption: text(&captures, 1).into(),
+					path: text(&captures, 2),
+				}
+			} else if capture(&self.codeblock, line).is_some() {
+				// Codeblocks borrow their lines, each with its newline, from the input
+				let start = full_line.as_ptr() as usize - input.as_ptr() as usize + full_line.len();
+				let mut end = start;
+				for line in lines.by_ref() {
+					if line.trim_end_matches(['\n', '\r']) == "$$$" {
+						break;
+					}
+					end += line.len();
+				}
+				Token::Codeblock(Cow::Borrowed(&input[start..end]))
+			} else if let Some(captures) = capture(&self.button, line) {
+				Token::Button {
+					text: text(&captures, 1).into(),
+					link: text(&captures, 2),
+				}
+			} else if let Some(captures) = capture(&self.text, line) {
+				match captures.get(1).unwrap().as_str().trim() {
+					// Blank lines have no token
+					"" => continue,
+					line => Token::Text(Cow::Borrowed(line)),
+				}
+			} else {
+				continue;
+			};
+			tokens.push(Spanned::from(token));
+		}
+		tokens.push(Spanned::from(Token::EOF));
+		tokens
+	}
+}
+
+fn large_document() -> String {
+	let mut input = String::new();
+	while input.len() < 4 * 1024 * 1024 {
+		input.push_str(SAMPLE);
+	}
+	input
+}
+
+fn lexer(c: &mut Criterion) {
+	let input = large_document();
+	let regex_lexer = RegexLexer::new();
+	// Spans aren't compared, so this checks that both build the same tokens
+	assert_eq!(regex_lexer.tokenize(&input), Lexer::new(&input).tokenize());
+	let mut group = c.benchmark_group("tokenize 4MiB");
+	group.throughput(Throughput::Bytes(input.len() as u64));
+	group.sample_size(10);
+	group.bench_function("line scanner", |b| {
+		b.iter(|| Lexer::new(black_box(&input)).tokenize())
+	});
+	group.bench_function("regex cascade (previous lexer)", |b| {
+		b.iter(|| regex_lexer.tokenize(black_box(&input)))
+	});
+	group.finish();
+}
+
+criterion_group!(benches, lexer);
+criterion_main!(benches);
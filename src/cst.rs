@@ -0,0 +1,669 @@
+//! A lossless concrete syntax tree. Every byte of the input belongs to exactly one
+//! token, so markers, indentation, trailing whitespace, blank lines and line endings
+//! are all kept, and printing the tree gives back the input unchanged.
+//!
+//! The tree has one node per line, except that a codeblock or display equation is a
+//! single node holding its fences and lines. The lexer's `Token`s are derived from it.
+
+use std::fmt;
+use super::diagnostic::Diagnostic;
+use super::span::Span;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyntaxKind {
+    // Nodes
+    Document,
+    Section,
+    Subsection,
+    Subsubsection,
+    ListItem,
+    Quote,
+    TableRow,
+    TableCell,
+    Image,
+    Button,
+    Codeblock,
+    /// A display equation, fenced by `%%%` like a codeblock.
+    Equation,
+    /// A footnote definition, `[^label]: text`.
+    Footnote,
+    /// A link definition, `[label]: url "title"`.
+    LinkDefinition,
+    CodeFence,
+    CodeLine,
+    TextLine,
+    BlankLine,
+    /// A line that looks like a block but is malformed.
+    ErrorLine,
+
+    // Tokens
+    /// The leading marker of a block, e.g. `@@`, `--`, `>`, `#[`, `#![` or `[^`.
+    Marker,
+    /// The punctuation separating the parts of a block, e.g. `|`, `](`, `}` or `]:`.
+    Punct,
+    /// The label of a footnote or link definition.
+    Label,
+    Whitespace,
+    Text,
+    /// The path of an image, or the link of a button or link definition.
+    Url,
+    /// The `$$$` or `%%%` that opens or closes a codeblock or display equation.
+    Fence,
+    /// A line inside a codeblock or display equation, without its line ending.
+    Code,
+    /// `\n` or `\r\n`.
+    LineEnding,
+}
+
+/// An interior node: a line, a codeblock, a display equation, a table cell or the
+/// whole document.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxNode<'a> {
+    pub kind: SyntaxKind,
+    pub span: Span,
+    pub children: Vec<SyntaxElement<'a>>,
+}
+
+/// A leaf of the tree, borrowing its text from the input. Tokens are never empty.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxToken<'a> {
+    pub kind: SyntaxKind,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SyntaxElement<'a> {
+    Node(SyntaxNode<'a>),
+    Token(SyntaxToken<'a>),
+}
+
+impl<'a> SyntaxNode<'a> {
+    /// The child nodes, skipping tokens.
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode<'a>> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// Every token under this node, in source order.
+    pub fn tokens(&self) -> Tokens<'_, 'a> {
+        Tokens { stack: vec![self.children.iter()] }
+    }
+
+    /// The first direct child token of the given kind.
+    pub fn token(&self, kind: SyntaxKind) -> Option<&SyntaxToken<'a>> {
+        self.children.iter().find_map(|child| match child {
+            SyntaxElement::Token(token) if token.kind == kind => Some(token),
+            _ => None,
+        })
+    }
+
+    /// The span of the node without its line ending.
+    pub fn content_span(&self) -> Span {
+        match self.token(SyntaxKind::LineEnding) {
+            Some(ending) => Span { end: ending.span.start, ..self.span },
+            None => self.span,
+        }
+    }
+
+    /// The empty span just after the node's content.
+    pub fn content_end(&self) -> Span {
+        let span = self.content_span();
+        let width = self
+            .tokens()
+            .take_while(|token| token.kind != SyntaxKind::LineEnding)
+            .map(|token| token.text.chars().count())
+            .sum::<usize>();
+        Span { start: span.end, column: span.column + width, ..span }
+    }
+}
+
+/// Prints the source text the node was built from.
+impl fmt::Display for SyntaxNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.tokens().try_for_each(|token| f.write_str(token.text))
+    }
+}
+
+/// Depth-first iterator over the tokens under a node.
+pub struct Tokens<'n, 'a> {
+    stack: Vec<std::slice::Iter<'n, SyntaxElement<'a>>>,
+}
+
+impl<'n, 'a> Iterator for Tokens<'n, 'a> {
+    type Item = &'n SyntaxToken<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(SyntaxElement::Token(token)) => return Some(token),
+                Some(SyntaxElement::Node(node)) => self.stack.push(node.children.iter()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Builds the concrete syntax tree of `input`, reporting malformed lines and
+/// unclosed codeblocks and equations along the way.
+pub fn parse(input: &str) -> (SyntaxNode<'_>, Vec<Diagnostic>) {
+    let mut children = vec![];
+    let mut diagnostics = vec![];
+    let mut lines = lines(input);
+    while let Some(line) = lines.next() {
+        let node = if line.text.starts_with("$$$") {
+            fenced_block(SyntaxKind::Codeblock, line, &mut lines, &mut diagnostics)
+        } else if line.text.starts_with("%%%") {
+            fenced_block(SyntaxKind::Equation, line, &mut lines, &mut diagnostics)
+        } else {
+            scan_block(&line, &mut diagnostics).unwrap_or_else(|| text_line(&line))
+        };
+        children.push(SyntaxElement::Node(node));
+    }
+    let span = Span::new(0, input.len(), 1, 1);
+    (SyntaxNode { kind: SyntaxKind::Document, span, children }, diagnostics)
+}
+
+struct Line<'a> {
+    text: &'a str,
+    /// The line with its line ending.
+    raw: &'a str,
+    offset: usize,
+    number: usize,
+}
+
+impl Line<'_> {
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span::new(
+            self.offset + start,
+            self.offset + end,
+            self.number,
+            self.text[..start].chars().count() + 1
+        )
+    }
+
+    /// Returns the byte index where the content after a `marker_len` byte marker
+    /// starts, skipping whitespace, or `None` if nothing but whitespace follows.
+    fn content_after(&self, marker_len: usize) -> Option<usize> {
+        let rest = &self.text[marker_len..];
+        let content = rest.trim_start();
+        if content.is_empty() {
+            None
+        } else {
+            Some(marker_len + rest.len() - content.len())
+        }
+    }
+
+    fn count_leading(&self, marker: u8) -> usize {
+        self.text.bytes().take_while(|&byte| byte == marker).count()
+    }
+}
+
+fn lines(input: &str) -> impl Iterator<Item = Line<'_>> {
+    let mut offset = 0;
+    input
+        .split_inclusive('\n')
+        .enumerate()
+        .map(move |(index, raw)| {
+            let text = raw.strip_suffix('\n').map_or(raw, |l| l.strip_suffix('\r').unwrap_or(l));
+            let line = Line { text, raw, offset, number: index + 1 };
+            offset += raw.len();
+            line
+        })
+}
+
+/// Cuts a line into consecutive tokens.
+struct LineBuilder<'l, 'a> {
+    line: &'l Line<'a>,
+    pos: usize,
+    /// The column of `pos`, counted in characters as the tokens are added.
+    column: usize,
+    children: Vec<SyntaxElement<'a>>,
+}
+
+impl<'l, 'a> LineBuilder<'l, 'a> {
+    fn new(line: &'l Line<'a>) -> Self {
+        LineBuilder { line, pos: 0, column: 1, children: vec![] }
+    }
+
+    /// Returns the span from the current position to byte `end` of the line.
+    fn span_to(&self, end: usize) -> Span {
+        Span::new(self.line.offset + self.pos, self.line.offset + end, self.line.number, self.column)
+    }
+
+    /// Adds the text up to byte `end` of the line as a token, unless it is empty.
+    fn token(&mut self, kind: SyntaxKind, end: usize) -> &mut Self {
+        if end > self.pos {
+            let text = &self.line.raw[self.pos..end];
+            self.children.push(SyntaxElement::Token(SyntaxToken { kind, text, span: self.span_to(end) }));
+            self.pos = end;
+            self.column += text.chars().count();
+        }
+        self
+    }
+
+    /// Splits the text up to byte `end` into leading whitespace, content and trailing whitespace.
+    fn trimmed(&mut self, kind: SyntaxKind, end: usize) -> &mut Self {
+        let text = &self.line.raw[self.pos..end];
+        let start = self.pos + text.len() - text.trim_start().len();
+        let content_end = self.pos + text.trim_end().len();
+        self.token(SyntaxKind::Whitespace, start)
+            .token(kind, content_end.max(start))
+            .token(SyntaxKind::Whitespace, end)
+    }
+
+    /// Turns the tokens from index `from` on into a node covering `start..self.pos`.
+    fn finish(&mut self, kind: SyntaxKind, from: usize) -> SyntaxNode<'a> {
+        let children = self.children.split_off(from);
+        let end = self.span_to(self.pos);
+        let span = match children.first() {
+            Some(SyntaxElement::Token(token)) => token.span.to(end),
+            Some(SyntaxElement::Node(node)) => node.span.to(end),
+            None => end,
+        };
+        SyntaxNode { kind, span, children }
+    }
+
+    /// Adds the rest of the line and its line ending, and wraps the whole line in a node.
+    fn line(mut self, kind: SyntaxKind, rest: SyntaxKind) -> SyntaxNode<'a> {
+        self.token(rest, self.line.text.len());
+        let end = self.line.raw.len();
+        self.token(SyntaxKind::LineEnding, end);
+        let span = Span::new(self.line.offset, self.line.offset + end, self.line.number, 1);
+        SyntaxNode { kind, span, children: self.children }
+    }
+}
+
+fn text_line<'a>(line: &Line<'a>) -> SyntaxNode<'a> {
+    let mut builder = LineBuilder::new(line);
+    builder.trimmed(SyntaxKind::Text, line.text.len());
+    let blank = builder.children.iter().all(|child| match child {
+        SyntaxElement::Token(token) => token.kind == SyntaxKind::Whitespace,
+        SyntaxElement::Node(_) => false,
+    });
+    builder.line(if blank { SyntaxKind::BlankLine } else { SyntaxKind::TextLine }, SyntaxKind::Whitespace)
+}
+
+/// Builds a codeblock or display equation, from its opening fence up to the closing
+/// fence or the end of the input.
+fn fenced_block<'a>(
+    kind: SyntaxKind,
+    open: Line<'a>,
+    lines: &mut impl Iterator<Item = Line<'a>>,
+    diagnostics: &mut Vec<Diagnostic>
+) -> SyntaxNode<'a> {
+    let (marker, name) = match kind {
+        SyntaxKind::Equation => ("%%%", "display equation"),
+        _ => ("$$$", "codeblock"),
+    };
+    let fence = |line: &Line<'a>| {
+        let mut builder = LineBuilder::new(line);
+        builder.token(SyntaxKind::Fence, 3);
+        builder.line(SyntaxKind::CodeFence, SyntaxKind::Text)
+    };
+    let mut children = vec![SyntaxElement::Node(fence(&open))];
+    let mut closed = false;
+    for line in lines {
+        if line.text == marker {
+            children.push(SyntaxElement::Node(fence(&line)));
+            closed = true;
+            break;
+        }
+        children.push(SyntaxElement::Node(LineBuilder::new(&line).line(SyntaxKind::CodeLine, SyntaxKind::Code)));
+    }
+    if !closed {
+        diagnostics.push(
+            Diagnostic::error("E0006", format!("{} is never closed", name), open.span(0, 3)).with_note(
+                format!("{}s end with a line containing only `{}`", name, marker)
+            )
+        );
+    }
+    let span = match children.last() {
+        Some(SyntaxElement::Node(last)) => open.span(0, 0).to(last.span),
+        _ => unreachable!("a fenced block has an opening fence"),
+    };
+    SyntaxNode { kind, span, children }
+}
+
+/// Whether `text` can label a footnote or link: letters, digits, `-` and `_`, so
+/// that it can be used in an html id as it is.
+pub(crate) fn is_label(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|ch| ch.is_alphanumeric() || ch == '-' || ch == '_')
+}
+
+/// Returns the byte indices of the `|`s in a table row that separate cells, skipping
+/// escaped ones like the one in `| a \| b |`.
+fn cell_separators(row: &str) -> Vec<usize> {
+    let mut pipes = vec![];
+    let mut bytes = row.bytes().enumerate();
+    while let Some((index, byte)) = bytes.next() {
+        match byte {
+            b'\\' => {
+                bytes.next();
+            }
+            b'|' => pipes.push(index),
+            _ => {}
+        }
+    }
+    pipes
+}
+
+/// Finishes a link definition from its url, which starts at byte `start`: the url
+/// is followed by nothing or by a title in double quotes.
+fn link_definition<'a>(text: &str, mut builder: LineBuilder<'_, 'a>, start: usize) -> Option<SyntaxNode<'a>> {
+    let url_end = text[start..].find(char::is_whitespace).map_or(text.len(), |end| start + end);
+    builder.token(SyntaxKind::Url, url_end);
+    let title = text[url_end..].trim();
+    if !title.is_empty() {
+        let quoted = title.len() > 1 && title.starts_with('"') && title.ends_with('"');
+        if !quoted {
+            return None;
+        }
+        let title_start = url_end + text[url_end..].find('"')?;
+        let title_end = title_start + title.len();
+        builder
+            .token(SyntaxKind::Whitespace, title_start)
+            .token(SyntaxKind::Punct, title_start + 1)
+            .token(SyntaxKind::Text, title_end - 1)
+            .token(SyntaxKind::Punct, title_end);
+    }
+    Some(builder.line(SyntaxKind::LinkDefinition, SyntaxKind::Whitespace))
+}
+
+/// Finds where the label of a heading, ` {#label}` at the end of its title, starts.
+/// The title starts at byte `start` and can't be left empty.
+pub(crate) fn heading_label(text: &str, start: usize) -> Option<usize> {
+    let (title, label) = text[start..].trim_end().strip_suffix('}')?.rsplit_once("{#")?;
+    let labelled = is_label(label) && title.ends_with(char::is_whitespace) && !title.trim().is_empty();
+    labelled.then_some(start + title.len())
+}
+
+/// Whether a line reading `text` would start a block instead of continuing a paragraph.
+pub(crate) fn starts_block(text: &str) -> bool {
+    let line = Line { text, raw: text, offset: 0, number: 1 };
+    text.starts_with("$$$") || text.starts_with("%%%") || scan_block(&line, &mut vec![]).is_some()
+}
+
+/// Classifies a line by its leading bytes. Returns `None` for lines that are plain text.
+fn scan_block<'a>(line: &Line<'a>, diagnostics: &mut Vec<Diagnostic>) -> Option<SyntaxNode<'a>> {
+    let text = line.text;
+    let mut builder = LineBuilder::new(line);
+    let kind = match text.as_bytes().first()? {
+        b'@' => {
+            let depth = line.count_leading(b'@');
+            let start = line.content_after(depth)?;
+            let kind = match depth {
+                1 => SyntaxKind::Section,
+                2 => SyntaxKind::Subsection,
+                3 => SyntaxKind::Subsubsection,
+                _ => {
+                    return None;
+                }
+            };
+            builder.token(SyntaxKind::Marker, depth).token(SyntaxKind::Whitespace, start);
+            if let Some(label_start) = heading_label(text, start) {
+                let label_end = text.trim_end().len() - 1;
+                builder
+                    .trimmed(SyntaxKind::Text, label_start)
+                    .token(SyntaxKind::Punct, label_start + 2)
+                    .token(SyntaxKind::Label, label_end)
+                    .token(SyntaxKind::Punct, label_end + 1);
+                return Some(builder.line(kind, SyntaxKind::Whitespace));
+            }
+            kind
+        }
+        b'-' => {
+            let dashes = line.count_leading(b'-');
+            // A line of only dashes reads its last dash as the item text.
+            match line.content_after(dashes) {
+                Some(start) => builder.token(SyntaxKind::Marker, dashes).token(SyntaxKind::Whitespace, start),
+                None if dashes > 1 => builder.token(SyntaxKind::Marker, dashes - 1),
+                None => {
+                    return None;
+                }
+            };
+            SyntaxKind::ListItem
+        }
+        b'>' => {
+            if text[1..].starts_with('>') {
+                return None;
+            }
+            let start = line.content_after(1)?;
+            builder.token(SyntaxKind::Marker, 1).token(SyntaxKind::Whitespace, start);
+            SyntaxKind::Quote
+        }
+        b'|' => {
+            let pipes = cell_separators(text);
+            if pipes.len() < 2 || pipes.last() != Some(&(text.len() - 1)) {
+                diagnostics.push(
+                    Diagnostic::error("E0007", "table row is missing its closing `|`", line.span(0, text.len())).with_note(
+                        "table rows are written as `| cell | cell |`"
+                    )
+                );
+                return Some(builder.line(SyntaxKind::ErrorLine, SyntaxKind::Text));
+            }
+            builder.token(SyntaxKind::Punct, 1);
+            for &end in &pipes[1..] {
+                let from = builder.children.len();
+                builder.trimmed(SyntaxKind::Text, end);
+                let cell = builder.finish(SyntaxKind::TableCell, from);
+                builder.children.push(SyntaxElement::Node(cell));
+                builder.token(SyntaxKind::Punct, end + 1);
+            }
+            return Some(builder.line(SyntaxKind::TableRow, SyntaxKind::Text));
+        }
+        b'#' => {
+            if let Some(rest) = text.strip_prefix("#[") {
+                let body = rest.strip_suffix(')')?;
+                let split = 2 + body.rfind("](")?;
+                builder
+                    .token(SyntaxKind::Marker, 2)
+                    .token(SyntaxKind::Text, split)
+                    .token(SyntaxKind::Punct, split + 2)
+                    .token(SyntaxKind::Url, text.len() - 1)
+                    .token(SyntaxKind::Punct, text.len());
+                SyntaxKind::Image
+            } else if let Some(rest) = text.strip_prefix("#![") {
+                let (button_text, rest) = rest.split_once(']')?;
+                let link = rest.strip_prefix('{')?.strip_suffix('}')?;
+                if link.contains('}') {
+                    return None;
+                }
+                let split = 3 + button_text.len();
+                builder
+                    .token(SyntaxKind::Marker, 3)
+                    .token(SyntaxKind::Text, split)
+                    .token(SyntaxKind::Punct, split + 2)
+                    .token(SyntaxKind::Url, text.len() - 1)
+                    .token(SyntaxKind::Punct, text.len());
+                SyntaxKind::Button
+            } else {
+                return None;
+            }
+        }
+        b'[' => {
+            let marker = if text.starts_with("[^") { 2 } else { 1 };
+            let (label, _) = text[marker..].split_once("]:")?;
+            if !is_label(label) {
+                return None;
+            }
+            let punct = marker + label.len();
+            let start = line.content_after(punct + 2)?;
+            builder
+                .token(SyntaxKind::Marker, marker)
+                .token(SyntaxKind::Label, punct)
+                .token(SyntaxKind::Punct, punct + 2)
+                .token(SyntaxKind::Whitespace, start);
+            if marker == 2 {
+                SyntaxKind::Footnote
+            } else {
+                return link_definition(text, builder, start);
+            }
+        }
+        _ => {
+            return None;
+        }
+    };
+    Some(builder.line(kind, SyntaxKind::Text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds<'a>(node: &SyntaxNode<'a>) -> Vec<(SyntaxKind, &'a str)> {
+        node.tokens().map(|token| (token.kind, token.text)).collect()
+    }
+
+    #[test]
+    fn cst_is_lossless() {
+        for input in [
+            "",
+            "\n\n\n",
+            "@   Title  \r\n\r\n  indented text\t\n",
+            "--- \n-\n-- item\n>> no\n> quote  ",
+            "|  | a |b  |\n| open\n||\n",
+            "#[cap](a](b)\n#[](x)\n#![go]{}\n#![a]{b}c}\n#nope",
+            "$$$ rust\r\nfn main() {}\r\n\r\n$$$\r\ntext after",
+            "$$$\nnever closed\n",
+            "héllo *wörld* ![x](y)",
+            "[^1]: note\n[^a b]: no\n[^]: no\n[^x]:\n[^x] text",
+            "[a]: url\n[b]:  url  \"A title\"  \n[c]: url junk\n[d]: url \"\"\n[e]:",
+            "@ Intro  {#intro}  \n@@ {#x}\n@@@ A{#y}\n@ B {#a b}\n@ C {#}",
+        ] {
+            let (tree, _) = parse(input);
+            assert_eq!(tree.to_string(), input);
+            let mut offset = 0;
+            for token in tree.tokens() {
+                assert!(!token.text.is_empty());
+                assert_eq!(token.span.start, offset, "{:?} in {:?}", token, input);
+                assert_eq!(&input[token.span.start..token.span.end], token.text);
+                offset = token.span.end;
+            }
+        }
+    }
+
+    #[test]
+    fn cst_lines() {
+        let (tree, diagnostics) = parse("@@ Title \n\n  text \n| a |  |\n");
+        assert!(diagnostics.is_empty());
+        let lines = tree.nodes().map(|node| node.kind).collect::<Vec<_>>();
+        assert_eq!(lines, vec![SyntaxKind::Subsection, SyntaxKind::BlankLine, SyntaxKind::TextLine, SyntaxKind::TableRow]);
+        assert_eq!(
+            kinds(&tree),
+            vec![
+                (SyntaxKind::Marker, "@@"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Text, "Title "),
+                (SyntaxKind::LineEnding, "\n"),
+                (SyntaxKind::LineEnding, "\n"),
+                (SyntaxKind::Whitespace, "  "),
+                (SyntaxKind::Text, "text"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::LineEnding, "\n"),
+                (SyntaxKind::Punct, "|"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Text, "a"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Punct, "|"),
+                (SyntaxKind::Whitespace, "  "),
+                (SyntaxKind::Punct, "|"),
+                (SyntaxKind::LineEnding, "\n"),
+            ]
+        );
+        let row = tree.nodes().last().unwrap();
+        let cells = row.nodes().map(|cell| (cell.span.start, cell.span.end)).collect::<Vec<_>>();
+        assert_eq!(cells, vec![(20, 23), (24, 26)]);
+        assert_eq!(row.nodes().last().unwrap().content_end(), Span::new(26, 26, 4, 8));
+    }
+
+    #[test]
+    fn cst_codeblock() {
+        let (tree, diagnostics) = parse("$$$ info\ncode\n$$$\n");
+        assert!(diagnostics.is_empty());
+        let codeblock = tree.nodes().next().unwrap();
+        assert_eq!(codeblock.kind, SyntaxKind::Codeblock);
+        assert_eq!(codeblock.span, Span::new(0, 18, 1, 1));
+        let parts = codeblock.nodes().map(|node| node.kind).collect::<Vec<_>>();
+        assert_eq!(parts, vec![SyntaxKind::CodeFence, SyntaxKind::CodeLine, SyntaxKind::CodeFence]);
+        assert_eq!(
+            kinds(codeblock)[..3],
+            [(SyntaxKind::Fence, "$$$"), (SyntaxKind::Text, " info"), (SyntaxKind::LineEnding, "\n")]
+        );
+    }
+
+    #[test]
+    fn cst_equation() {
+        let (tree, diagnostics) = parse("%%%\nx^2 % $$$\n%%%\n%%%\nopen");
+        let equation = tree.nodes().next().unwrap();
+        assert_eq!(equation.kind, SyntaxKind::Equation);
+        assert_eq!(equation.span, Span::new(0, 18, 1, 1));
+        assert_eq!(tree.to_string(), "%%%\nx^2 % $$$\n%%%\n%%%\nopen");
+        let codes = diagnostics.iter().map(|diagnostic| (diagnostic.message.as_str(), diagnostic.span.line)).collect::<Vec<_>>();
+        assert_eq!(codes, vec![("display equation is never closed", 4)]);
+    }
+
+    #[test]
+    fn cst_link_definition() {
+        let (tree, _) = parse("[docs]: https://a.org \"The docs\"\n[x]: url title\n[y]: url");
+        let lines = tree.nodes().map(|node| node.kind).collect::<Vec<_>>();
+        assert_eq!(lines, vec![SyntaxKind::LinkDefinition, SyntaxKind::TextLine, SyntaxKind::LinkDefinition]);
+        assert_eq!(
+            kinds(tree.nodes().next().unwrap()),
+            vec![
+                (SyntaxKind::Marker, "["),
+                (SyntaxKind::Label, "docs"),
+                (SyntaxKind::Punct, "]:"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Url, "https://a.org"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Punct, "\""),
+                (SyntaxKind::Text, "The docs"),
+                (SyntaxKind::Punct, "\""),
+                (SyntaxKind::LineEnding, "\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn cst_heading_label() {
+        let (tree, _) = parse("@@ Getting *started* {#start}\n@ {#alone}\n@@@ No{#label}");
+        let titles = tree.nodes().map(|node| node.token(SyntaxKind::Text).unwrap().text).collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Getting *started*", "{#alone}", "No{#label}"]);
+        assert_eq!(
+            kinds(tree.nodes().next().unwrap()),
+            vec![
+                (SyntaxKind::Marker, "@@"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Text, "Getting *started*"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Punct, "{#"),
+                (SyntaxKind::Label, "start"),
+                (SyntaxKind::Punct, "}"),
+                (SyntaxKind::LineEnding, "\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn cst_footnote() {
+        let (tree, _) = parse("[^note-1]:  A *note*\n[^bad label]: text\n[^empty]:");
+        let lines = tree.nodes().map(|node| node.kind).collect::<Vec<_>>();
+        assert_eq!(lines, vec![SyntaxKind::Footnote, SyntaxKind::TextLine, SyntaxKind::TextLine]);
+        assert_eq!(
+            kinds(tree.nodes().next().unwrap()),
+            vec![
+                (SyntaxKind::Marker, "[^"),
+                (SyntaxKind::Label, "note-1"),
+                (SyntaxKind::Punct, "]:"),
+                (SyntaxKind::Whitespace, "  "),
+                (SyntaxKind::Text, "A *note*"),
+                (SyntaxKind::LineEnding, "\n"),
+            ]
+        );
+    }
+}
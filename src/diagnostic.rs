@@ -0,0 +1,124 @@
+use std::fmt;
+use super::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+	Error,
+	Warning,
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Severity::Error => write!(f, "error"),
+			Severity::Warning => write!(f, "warning"),
+		}
+	}
+}
+
+/// A problem found in a document, pointing at the source it refers to.
+///
+/// `code` is stable across releases so tools can match on it; `message` and
+/// `notes` are meant for people and may be reworded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub code: &'static str,
+	pub message: String,
+	pub span: Span,
+	pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+	pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+		Self {
+			severity: Severity::Error,
+			code,
+			message: message.into(),
+			span,
+			notes: vec![],
+		}
+	}
+
+	pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+		Self {
+			severity: Severity::Warning,
+			..Self::error(code, message, span)
+		}
+	}
+
+	pub fn with_note(mut self, note: impl Into<String>) -> Self {
+		self.notes.push(note.into());
+		self
+	}
+
+	/// Renders the diagnostic in the style of rustc, quoting the offending
+	/// line of `source` and underlining the span with carets.
+	pub fn render(&self, source: &str, file_name: &str) -> String {
+		let line_number = self.span.line.to_string();
+		let gutter = " ".repeat(line_number.len());
+		let mut output = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+		output.push_str(&format!("{gutter}--> {file_name}:{}\n", self.span));
+		if let Some(line) = source.lines().nth(self.span.line.saturating_sub(1)) {
+			let column = self.span.column.max(1);
+			let underline = source
+				.get(self.span.start..self.span.end)
+				.map_or(0, |text| text.lines().next().unwrap_or("").chars().count())
+				.max(1);
+			output.push_str(&format!("{gutter} |\n"));
+			output.push_str(&format!("{line_number} | {line}\n"));
+			output.push_str(
+				&format!("{gutter} | {}{}\n", " ".repeat(column - 1), "^".repeat(underline))
+			);
+		}
+		for note in &self.notes {
+			output.push_str(&format!("{gutter} = note: {note}\n"));
+		}
+		output
+	}
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}[{}] at {}: {}", self.severity, self.code, self.span, self.message)
+	}
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn render_with_snippet() {
+		let source = "Intro\nSee ![text]x";
+		let diagnostic = Diagnostic::error("E0002", "expected `(`, found `x`", Span::new(17, 18, 2, 12))
+			.with_note("inline links are written as `![text](url)`");
+		assert_eq!(
+			diagnostic.render(source, "post.wg"),
+			"error[E0002]: expected `(`, found `x`
+ --> post.wg:2:12
+  |
+2 | See ![text]x
+  |            ^
+  = note: inline links are written as `![text](url)`
+"
+		);
+	}
+
+	#[test]
+	fn render_underlines_whole_span() {
+		let source = "@ Title";
+		let diagnostic = Diagnostic::warning("W0001", "something odd", Span::new(2, 7, 1, 3));
+		assert_eq!(
+			diagnostic.render(source, "post.wg"),
+			"warning[W0001]: something odd
+ --> post.wg:1:3
+  |
+1 | @ Title
+  |   ^^^^^
+"
+		);
+	}
+}
@@ -1,73 +1,145 @@
 use super::html::ToHtml;
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::prelude::*;
 use regex::Regex;
+use super::diagnostic::{ Diagnostic, Severity };
+use super::error::Error;
+use super::filter;
 use super::lexer;
-use super::parser;
+use super::parser::{ self, ParseArtefact };
+use super::span::Spanned;
+
+/// Settings for rendering a document from an in-memory string.
+#[derive(Debug, Clone)]
+pub struct Options {
+	pub title: String,
+	pub style: Option<String>,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Self {
+			title: String::from("Untitled"),
+			style: None,
+		}
+	}
+}
 
 pub struct Document<'a> {
-	input: String,
-	converted: String,
+	input: Cow<'a, str>,
+	body: Vec<Spanned<ParseArtefact<'a>>>,
 	title: &'a str,
 	style: Option<String>,
+	diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> Document<'a> {
-	pub fn load(path: &'a str, style: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
-		let mut inpfile = File::open(path)?;
-		let mut file_contents = String::new();
-		inpfile.read_to_string(&mut file_contents)?;
-		let file_re = Regex::new(r"([^/]*)(?:\.)[^.]*?$").unwrap();
-		let file_name = file_re.captures(path).unwrap().get(1).unwrap().as_str();
-		let style_contents = match style {
-			Some(style) => {
-				let mut stylefile = File::open(style)?;
-				let mut style_contents = String::new();
-				stylefile.read_to_string(&mut style_contents)?;
-				Some(style_contents)
+	/// `input` can be borrowed, in which case the document is converted without copying it.
+	pub fn new(input: impl Into<Cow<'a, str>>, title: &'a str, style: Option<String>) -> Self {
+		Self {
+			input: input.into(),
+			body: vec![],
+			title,
+			style,
+			diagnostics: vec![],
+		}
+	}
+
+	/// Creates a document from an already parsed tree, such as one read back from JSON.
+	pub fn from_body(body: Vec<Spanned<ParseArtefact<'a>>>, title: &'a str, style: Option<String>) -> Self {
+		Self { body, ..Self::new("", title, style) }
+	}
+
+	pub fn load(path: &'a str, style: Option<&str>) -> Result<Self, Error> {
+		let file_contents = read_file(path)?;
+		let style_contents = style.map(read_file).transpose()?;
+		Ok(Self::new(file_contents, file_title(path), style_contents))
+	}
+
+	/// Loads a parse tree previously saved as JSON, ready to render.
+	pub fn load_ast(path: &'a str, style: Option<&str>) -> Result<Self, Error> {
+		let body = serde_json::from_str(&read_file(path)?)?;
+		let style_contents = style.map(read_file).transpose()?;
+		Ok(Self::from_body(body, file_title(path), style_contents))
+	}
+
+	pub fn source(&self) -> &str {
+		&self.input
+	}
+
+	pub fn title(&self) -> &str {
+		self.title
+	}
+
+	pub fn style(&self) -> Option<&str> {
+		self.style.as_deref()
+	}
+
+	/// The tree built by the last successful call to `parse`.
+	pub fn body(&self) -> &[Spanned<ParseArtefact<'a>>] {
+		&self.body
+	}
+
+	/// Mutable access to the parsed tree, for running a `VisitorMut` pass
+	/// before rendering.
+	pub fn body_mut(&mut self) -> &mut Vec<Spanned<ParseArtefact<'a>>> {
+		&mut self.body
+	}
+
+	/// Warnings and errors found by the last call to `parse`, in source order.
+	pub fn diagnostics(&self) -> &[Diagnostic] {
+		&self.diagnostics
+	}
+
+	/// Lexes and parses the input, keeping the tree for rendering. Fails if any
+	/// errors were found; warnings are left in `diagnostics`.
+	pub fn parse(&mut self) -> Result<(), Error> {
+		let (body, mut diagnostics) = match &self.input {
+			Cow::Borrowed(input) => parse(input),
+			Cow::Owned(input) => {
+				let (body, diagnostics) = parse(input);
+				(body.into_iter().map(|artefact| artefact.map(ParseArtefact::into_owned)).collect(), diagnostics)
 			}
-			None => None,
 		};
-		Ok(Self {
-			input: file_contents,
-			converted: String::new(),
-			title: file_name,
-			style: style_contents,
-		})
+		diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+		self.diagnostics = diagnostics;
+		if self.diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+			return Err(Error::Parse(self.diagnostics.clone()));
+		}
+		self.body = body;
+		Ok(())
 	}
 
-	pub fn convert_to_html(&mut self) -> Result<String, Box<dyn std::error::Error>> {
-		let html_contents = lexer::Lexer::new(self.input.clone()).tokenize()?;
-		let mut html_contents = parser::Parser::new(html_contents);
-		let html_contents = html_contents.parse()?;
-		self.converted = html_contents.iter().map(|x| x.to_html()).collect::<Vec<String>>().join("");
+	/// Replaces the tree with the one written back by the external filter `command`.
+	pub fn apply_filter(&mut self, command: &str) -> Result<(), Error> {
+		self.body = filter::run_filter(command, &self.body)?;
+		Ok(())
+	}
+
+	pub fn convert_to_html(&mut self) -> Result<String, Error> {
+		self.parse()?;
 		Ok(self.to_html())
 	}
 }
 
-impl<'a> ToHtml for Document<'a> {
-	fn to_html(&self) -> String {
-		let mut html = String::new();
-		html.push_str(
-			r#"<!DOCTYPE html>
-<html>
-	<head>
-		<title>"#,
-		);
-		html.push_str(self.title);
-		html.push_str(r#"</title>
-	<style>"#);
-		if let Some(style) = &self.style {
-			html.push_str(style);
-		}
-		html.push_str(r#"</style>
-	</head>
-	<body>
-		<div id="content">"#);
-		html.push_str(&self.converted);
-		html.push_str(r#"</div>
-	</body>
-</html>"#);
-		html
-	}
-}
\ No newline at end of file
+fn read_file(path: &str) -> Result<String, Error> {
+	let mut file = File::open(path)?;
+	let mut contents = String::new();
+	file.read_to_string(&mut contents)?;
+	Ok(contents)
+}
+
+/// The file name without its directory or extension, used as the page title.
+/// A file name without an extension is used as it is.
+fn file_title(path: &str) -> &str {
+	let file_re = Regex::new(r"([^/]*?)(?:\.[^./]*)?$").unwrap();
+	file_re.captures(path).and_then(|captures| captures.get(1)).map_or(path, |title| title.as_str())
+}
+
+fn parse(input: &str) -> (Vec<Spanned<ParseArtefact<'_>>>, Vec<Diagnostic>) {
+	let (tokens, mut diagnostics) = lexer::Lexer::new(input).tokenize_with_diagnostics();
+	let (body, parse_diagnostics) = parser::Parser::new(tokens).parse_with_diagnostics();
+	diagnostics.extend(parse_diagnostics);
+	(body, diagnostics)
+}
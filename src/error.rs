@@ -0,0 +1,79 @@
+use std::fmt;
+use super::diagnostic::Diagnostic;
+use super::filter::FilterError;
+
+#[derive(Debug)]
+pub enum Error {
+	Io(std::io::Error),
+	Parse(Vec<Diagnostic>),
+	Json(serde_json::Error),
+	Filter {
+		command: String,
+		error: FilterError,
+	},
+}
+
+impl Error {
+	/// Renders the error for display to a writer, quoting `source` where the
+	/// error points into the document.
+	pub fn render(&self, source: &str, file_name: &str) -> String {
+		match self {
+			Error::Parse(diagnostics) =>
+				diagnostics
+					.iter()
+					.map(|diagnostic| diagnostic.render(source, file_name))
+					.collect::<Vec<String>>()
+					.join("\n"),
+			_ => format!("error: {}\n", self),
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(err) => write!(f, "I/O error: {}", err),
+			Error::Json(err) => write!(f, "invalid JSON: {}", err),
+			Error::Filter { command, error } => write!(f, "filter `{}` {}", command, error),
+			Error::Parse(diagnostics) =>
+				write!(
+					f,
+					"{}",
+					diagnostics
+						.iter()
+						.map(|diagnostic| diagnostic.to_string())
+						.collect::<Vec<String>>()
+						.join("\n")
+				),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(err) => Some(err),
+			Error::Json(err) => Some(err),
+			Error::Filter { error, .. } => Some(error),
+			Error::Parse(diagnostics) => diagnostics.first().map(|diagnostic| diagnostic as _),
+		}
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Self {
+		Error::Io(err)
+	}
+}
+
+impl From<serde_json::Error> for Error {
+	fn from(err: serde_json::Error) -> Self {
+		Error::Json(err)
+	}
+}
+
+impl From<Diagnostic> for Error {
+	fn from(diagnostic: Diagnostic) -> Self {
+		Error::Parse(vec![diagnostic])
+	}
+}
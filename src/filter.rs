@@ -0,0 +1,86 @@
+//! External filters: programs that read a parse tree as JSON on stdin and write
+//! the transformed tree, in the same format, to stdout.
+
+use std::fmt;
+use std::io::{ self, Write };
+use std::process::{ Command, ExitStatus, Stdio };
+use std::thread;
+use super::error::Error;
+use super::parser::ParseArtefact;
+use super::span::Spanned;
+
+/// Why a filter could not transform the tree.
+#[derive(Debug)]
+pub enum FilterError {
+	/// The command line is empty or its quotes are unbalanced.
+	Command,
+	/// The program could not be started.
+	Spawn(io::Error),
+	/// The tree could not be written to the program or its output read back.
+	Pipe(io::Error),
+	/// The program exited unsuccessfully.
+	Status(ExitStatus),
+	/// The program's output is not a valid parse tree.
+	Output(serde_json::Error),
+}
+
+impl fmt::Display for FilterError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			FilterError::Command => write!(f, "is not a valid command line"),
+			FilterError::Spawn(err) => write!(f, "could not be started: {}", err),
+			FilterError::Pipe(err) => write!(f, "could not be sent the parse tree: {}", err),
+			FilterError::Status(status) => write!(f, "failed with {}", status),
+			FilterError::Output(err) => write!(f, "wrote an invalid parse tree: {}", err),
+		}
+	}
+}
+
+impl std::error::Error for FilterError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			FilterError::Spawn(err) | FilterError::Pipe(err) => Some(err),
+			FilterError::Command | FilterError::Status(_) => None,
+			FilterError::Output(err) => Some(err),
+		}
+	}
+}
+
+/// Pipes `body` through `command` and returns the tree it writes back.
+/// `command` is split into a program and its arguments the way a shell would,
+/// so `python3 "my filter.py" --strict` works. The program's stderr is passed
+/// through, so it can report its own problems.
+pub fn run_filter(
+	command: &str,
+	body: &[Spanned<ParseArtefact>]
+) -> Result<Vec<Spanned<ParseArtefact<'static>>>, Error> {
+	let error = |error| Error::Filter { command: command.to_string(), error };
+	let words = shlex::split(command).filter(|words| !words.is_empty());
+	let (program, args) = words
+		.as_deref()
+		.and_then(<[String]>::split_first)
+		.ok_or_else(|| error(FilterError::Command))?;
+	let input = serde_json::to_vec(body)?;
+	let mut child = Command::new(program)
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::inherit())
+		.spawn()
+		.map_err(|err| error(FilterError::Spawn(err)))?;
+	let mut stdin = child.stdin.take().expect("stdin is piped");
+	// Feed the tree from another thread, so a filter that starts writing
+	// before it has read all of its input can't fill both pipes and hang.
+	let writer = thread::spawn(move || stdin.write_all(&input));
+	let output = child.wait_with_output().map_err(|err| error(FilterError::Pipe(err)))?;
+	let written = writer.join().expect("filter input thread panicked");
+	if !output.status.success() {
+		return Err(error(FilterError::Status(output.status)));
+	}
+	// A filter that succeeds without reading all of its input is judged on its output alone
+	match written {
+		Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(error(FilterError::Pipe(err))),
+		_ => {}
+	}
+	serde_json::from_slice(&output.stdout).map_err(|err| error(FilterError::Output(err)))
+}
@@ -0,0 +1,564 @@
+//! Prints waygum source back in canonical form.
+//!
+//! Blocks are separated by exactly one blank line, markers are followed by a
+//! single space, table columns are padded to a common width and codeblocks are
+//! always closed. Parsing the output gives back the tree that was formatted.
+//!
+//! `format_source` takes the layout from the lossless syntax tree, so codeblock
+//! fences keep their info string and definitions stay where they are written.
+//! `format_body` prints a parse tree, which has lost both.
+
+use std::fmt::Write;
+use super::cst::{ heading_label, starts_block, SyntaxKind };
+use super::inline::{ autolink, can_close, can_close_style, can_open, opens_script, starts_path, starts_word, InlineParser };
+use super::lexer::{ Lexer, Token };
+use super::parser::{ ParseArtefact, Text, TextArtefact };
+use super::span::Spanned;
+use super::visit::{ self, Visitor };
+
+/// Formats waygum source as canonical source, line by line. A blank line the author
+/// left between list items, table rows or definitions of the same kind is kept.
+pub fn format_source(input: &str) -> String {
+    let lexer = Lexer::new(input);
+    let (tree, _) = lexer.syntax_tree();
+    let mut formatter = SourceWriter::default();
+    // Inline markup is parsed again only to be written back, so its diagnostics are dropped
+    let mut diagnostics = vec![];
+    let mut table = vec![];
+    let mut previous = None;
+    let mut blank = false;
+    for node in tree.nodes() {
+        let Some(Spanned { node: token, span }) = lexer.lower(node) else {
+            blank = previous.is_some();
+            continue;
+        };
+        // Consecutive lines of these kinds belong together; a blank line only matters
+        // between lines of text, where it starts a new paragraph
+        let continues = previous == Some(node.kind)
+            && matches!(
+                node.kind,
+                SyntaxKind::TextLine
+                    | SyntaxKind::ListItem
+                    | SyntaxKind::TableRow
+                    | SyntaxKind::Footnote
+                    | SyntaxKind::LinkDefinition
+            );
+        if !continues {
+            formatter.table(std::mem::take(&mut table));
+            if previous.is_some() {
+                formatter.output.push('\n');
+            }
+        } else if blank && node.kind != SyntaxKind::TableRow {
+            formatter.output.push('\n');
+        }
+        let mut text = |text, span| Text(InlineParser::new(text, span, &mut diagnostics).parse());
+        match token {
+            Token::Section(title, label) => formatter.heading("@", &text(&title, span), label.as_deref()),
+            Token::Subsection(title, label) => formatter.heading("@@", &text(&title, span), label.as_deref()),
+            Token::Subsubsection(title, label) => formatter.heading("@@@", &text(&title, span), label.as_deref()),
+            Token::List { depth, text: item } => {
+                formatter.list_depth = depth;
+                formatter.visit_list_item(&text(&item, span));
+            }
+            Token::Quote(quote) => formatter.visit_quote(&text(&quote, span)),
+            Token::Table(columns) => {
+                let cells = columns.iter().map(|column| format_cell(&text(&column.node, column.span))).collect();
+                table.push((continues && blank, cells));
+            }
+            Token::Image { caption, path } => formatter.visit_image(&text(&caption.node, caption.span), &path),
+            Token::Button { text: label, link } => formatter.visit_button(&text(&label.node, label.span), &link),
+            Token::Codeblock(contents) | Token::Equation(contents) => {
+                // The opening fence is kept as written, with any info string after its marker
+                let fence = node.nodes().next().map(ToString::to_string).unwrap_or_default();
+                formatter.fenced(fence.trim_end(), &contents);
+            }
+            Token::Text(line) => formatter.visit_paragraph(&[text(&line, span)]),
+            Token::Footnote { label, text: note } => formatter.visit_footnote(None, &label, &text(&note, span)),
+            Token::LinkDefinition { label, url, title } =>
+                formatter.visit_link_definition(&label, &url, title.as_deref()),
+            Token::Error(line) => formatter.visit_error(&line),
+            Token::Newline | Token::EOF => {}
+        }
+        previous = Some(node.kind);
+        blank = false;
+    }
+    formatter.table(table);
+    formatter.output
+}
+
+/// Formats a parsed document as canonical source.
+pub fn format_body(body: &[Spanned<ParseArtefact>]) -> String {
+    let mut formatter = SourceWriter::default();
+    formatter.visit_artefacts(body);
+    formatter.output
+}
+
+/// Formats a single line of text, as it would appear in a paragraph.
+pub fn format_text(text: &Text) -> String {
+    let mut formatter = SourceWriter::default();
+    formatter.visit_text(text);
+    formatter.output
+}
+
+/// Formats the text of a table cell, where `|` has to be escaped too.
+fn format_cell(text: &Text) -> String {
+    let mut formatter = SourceWriter { in_cell: true, ..SourceWriter::default() };
+    formatter.visit_text(text);
+    formatter.output
+}
+
+#[derive(Default)]
+struct SourceWriter {
+    output: String,
+    list_depth: usize,
+    /// The closing markers of the styles and link texts being written, innermost last.
+    open: Vec<char>,
+    /// Length of the output right after the last opening marker.
+    opened_at: usize,
+    /// Whether the raw text being written ends its line, outside of any style.
+    ends_line: bool,
+    in_cell: bool,
+}
+
+impl SourceWriter {
+    fn wrap(&mut self, open: &str, text: &Text<'_>, close: &str) {
+        self.output.push_str(open);
+        self.opened_at = self.output.len();
+        self.open.extend(close.chars().next());
+        self.visit_text(text);
+        self.open.pop();
+        self.output.push_str(close);
+    }
+
+    /// Writes the formatted cells of a table's rows, each after a blank line if its flag is set.
+    fn table(&mut self, rows: Vec<(bool, Vec<String>)>) {
+        let mut widths = vec![];
+        for (_, row) in &rows {
+            for (index, cell) in row.iter().enumerate() {
+                let width = cell.chars().count();
+                match widths.get_mut(index) {
+                    Some(max) if *max < width => *max = width,
+                    Some(_) => {}
+                    None => widths.push(width),
+                }
+            }
+        }
+        for (blank, row) in rows {
+            if blank {
+                self.output.push('\n');
+            }
+            self.output.push('|');
+            for (cell, width) in row.iter().zip(&widths) {
+                write!(self.output, " {:width$} |", cell, width = width).unwrap();
+            }
+            self.output.push('\n');
+        }
+    }
+
+    /// Writes a codeblock or display equation after its opening `fence` line, closing
+    /// it with the fence's marker.
+    fn fenced(&mut self, fence: &str, contents: &str) {
+        self.output.push_str(fence);
+        self.output.push('\n');
+        self.output.push_str(contents);
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            self.output.push('\n');
+        }
+        self.output.push_str(&fence[..3]);
+        self.output.push('\n');
+    }
+
+    /// The character before the next one written, or `None` right after an opening marker.
+    fn prev(&self) -> Option<char> {
+        match self.output.len() == self.opened_at {
+            true => None,
+            false => self.output.chars().next_back(),
+        }
+    }
+
+    fn heading(&mut self, marker: &str, title: &Text<'_>, label: Option<&str>) {
+        write!(self.output, "{} ", marker).unwrap();
+        let start = self.output.len();
+        self.visit_text(title);
+        match label {
+            Some(label) => write!(self.output, " {{#{}}}", label).unwrap(),
+            // A title ending in what looks like a label would lose it to the label
+            None => {
+                if let Some(brace) = heading_label(&self.output[start..], 0) {
+                    self.output.insert(start + brace, '\\');
+                }
+            }
+        }
+        self.output.push('\n');
+    }
+
+    /// Whether a `/` or `_` opener, followed by the raw text `rest`, could find a closer.
+    /// Unclosed ones are plain text, so one that ends its line without a closer after
+    /// it, as in `set _PRIVATE`, needs no escaping.
+    fn could_close(&self, marker: char, rest: &str) -> bool {
+        !self.ends_line
+            || rest.char_indices().any(|(index, ch)| {
+                let prev = rest[..index].chars().next_back().or(Some(marker));
+                ch == marker && can_close_style(prev, rest[index + 1..].chars().next(), marker)
+            })
+    }
+
+    /// Whether a superscript or subscript `marker`, followed by the raw text `rest`, could open.
+    fn could_open_script(&self, marker: &str, rest: &str) -> bool {
+        !rest.contains(char::is_whitespace) && !self.ends_line || opens_script(marker, rest)
+    }
+
+    /// Writes raw text, escaping the characters that would otherwise be read as markup.
+    /// Markers are only escaped where they could open or close a style.
+    fn escape(&mut self, text: &str) {
+        let mut chars = text.chars().peekable();
+        let mut offset = 0;
+        while let Some(ch) = chars.next() {
+            offset += ch.len_utf8();
+            let prev = self.prev();
+            let next = chars.peek().copied();
+            // Doubled markers are judged by what comes after both characters
+            let after = chars.clone().nth(1);
+            let escape = match ch {
+                // Raw text ends before more markup or at the end of the line, so both are
+                // assumed there: `[` stands in for the former and `None` for the latter
+                '/' | '_' => {
+                    let rest = &text[offset..];
+                    let opens = can_open(prev, next.or(Some('[')), ch) && !starts_path(ch, rest) && self.could_close(ch, rest);
+                    opens || self.open.contains(&ch) && can_close(prev, next)
+                }
+                '*' | '~' | '$' =>
+                    can_open(prev, next.or(Some('[')), ch) || self.open.contains(&ch) && can_close(prev, next),
+                // Scripts open inside words, wherever a closer follows before the next
+                // whitespace, which may come after the end of this raw text
+                '^' => self.open.contains(&ch) || self.could_open_script("^", &text[offset..]),
+                ',' if next == Some(ch) => self.open.contains(&ch) || self.could_open_script(",,", &text[offset + 1..]),
+                '=' | '+' if next == Some(ch) =>
+                    can_open(prev, after.or(Some('[')), ch) || self.open.contains(&ch) && can_close(prev, after),
+                // Backslashes before anything but punctuation are already literal
+                '\\' => chars.peek().is_none_or(|next| next.is_ascii_punctuation()),
+                '!' | '#' => chars.peek() == Some(&'['),
+                '[' => matches!(chars.peek(), Some('^' | '@')),
+                ']' => self.open.contains(&']'),
+                '|' => self.in_cell,
+                _ => false,
+            };
+            if escape {
+                self.output.push('\\');
+            }
+            self.output.push(ch);
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for SourceWriter {
+    fn visit_text(&mut self, text: &'ast Text<'_>) {
+        for (index, artefact) in text.0.iter().enumerate() {
+            self.ends_line = self.open.is_empty() && index + 1 == text.0.len();
+            self.visit_text_artefact(&artefact.node);
+        }
+    }
+
+    fn visit_artefacts(&mut self, artefacts: &'ast [Spanned<ParseArtefact<'_>>]) {
+        for artefact in artefacts {
+            // Newlines only come from hand-built trees and have no source form
+            if let ParseArtefact::Newline = artefact.node {
+                continue;
+            }
+            if !self.output.is_empty() {
+                self.output.push('\n');
+            }
+            self.visit_artefact(&artefact.node);
+        }
+    }
+
+    fn visit_section(&mut self, _number: usize, title: &'ast Text<'_>, label: Option<&'ast str>) {
+        self.heading("@", title, label);
+    }
+
+    fn visit_subsection(&mut self, _numbers: (usize, usize), title: &'ast Text<'_>, label: Option<&'ast str>) {
+        self.heading("@@", title, label);
+    }
+
+    fn visit_subsubsection(
+        &mut self,
+        _numbers: (usize, usize, usize),
+        title: &'ast Text<'_>,
+        label: Option<&'ast str>
+    ) {
+        self.heading("@@@", title, label);
+    }
+
+    fn visit_paragraph(&mut self, lines: &'ast [Text<'_>]) {
+        for line in lines {
+            let start = self.output.len();
+            self.visit_text(line);
+            // A line starting with a block marker would no longer be text. Inline images
+            // and footnote references can start a line as long as more text follows them.
+            let raw = matches!(line.0.first().map(|artefact| &artefact.node), Some(TextArtefact::Raw(..)));
+            if raw && starts_block(&self.output[start..]) {
+                self.output.insert(start, '\\');
+            }
+            self.output.push('\n');
+        }
+    }
+
+    fn visit_list(&mut self, items: &'ast [Spanned<ParseArtefact<'_>>]) {
+        self.list_depth += 1;
+        visit::walk_artefacts(self, items);
+        self.list_depth -= 1;
+    }
+
+    fn visit_list_item(&mut self, text: &'ast Text<'_>) {
+        self.output.push_str(&"-".repeat(self.list_depth));
+        self.output.push(' ');
+        self.visit_text(text);
+        self.output.push('\n');
+    }
+
+    fn visit_quote(&mut self, text: &'ast Text<'_>) {
+        self.output.push_str("> ");
+        self.visit_text(text);
+        self.output.push('\n');
+    }
+
+    fn visit_table(&mut self, rows: &'ast [Spanned<ParseArtefact<'_>>]) {
+        let rows = rows
+            .iter()
+            .filter_map(|row| match &row.node {
+                ParseArtefact::TableRow(columns) => Some((false, columns.iter().map(format_cell).collect())),
+                _ => None,
+            })
+            .collect();
+        self.table(rows);
+    }
+
+    fn visit_button(&mut self, text: &'ast Text<'_>, link: &'ast str) {
+        self.wrap("#![", text, "]{");
+        writeln!(self.output, "{}}}", link).unwrap();
+    }
+
+    fn visit_image(&mut self, caption: &'ast Text<'_>, path: &'ast str) {
+        self.wrap("#[", caption, "](");
+        writeln!(self.output, "{})", path).unwrap();
+    }
+
+    fn visit_codeblock(&mut self, code: &'ast str) {
+        self.fenced("$$$", code);
+    }
+
+    fn visit_equation(&mut self, _number: Option<usize>, tex: &'ast str) {
+        self.fenced("%%%", tex);
+    }
+
+    fn visit_footnotes(&mut self, footnotes: &'ast [Spanned<ParseArtefact<'_>>]) {
+        visit::walk_artefacts(self, footnotes);
+    }
+
+    fn visit_footnote(&mut self, _number: Option<usize>, label: &'ast str, text: &'ast Text<'_>) {
+        write!(self.output, "[^{}]: ", label).unwrap();
+        self.visit_text(text);
+        self.output.push('\n');
+    }
+
+    fn visit_link_definition(&mut self, label: &'ast str, url: &'ast str, title: Option<&'ast str>) {
+        match title {
+            Some(title) => writeln!(self.output, "[{}]: {} \"{}\"", label, url, title).unwrap(),
+            None => writeln!(self.output, "[{}]: {}", label, url).unwrap(),
+        }
+    }
+
+    fn visit_error(&mut self, text: &'ast str) {
+        writeln!(self.output, "{}", text).unwrap();
+    }
+
+    fn visit_raw(&mut self, text: &'ast str) {
+        self.escape(text);
+    }
+
+    fn visit_bold(&mut self, text: &'ast Text<'_>) {
+        self.wrap("*", text, "*");
+    }
+
+    fn visit_italics(&mut self, text: &'ast Text<'_>) {
+        self.wrap("/", text, "/");
+    }
+
+    fn visit_link(&mut self, text: &'ast Text<'_>, url: &'ast str) {
+        // Links that would be found again as bare urls or email addresses are written bare
+        if let [Spanned { node: TextArtefact::Raw(address), .. }] = &text.0[..] {
+            if starts_word(self.prev()) && autolink(address, &self.open) == Some((address.len(), url.into())) {
+                self.output.push_str(address);
+                return;
+            }
+        }
+        self.wrap("![", text, "](");
+        write!(self.output, "{})", url).unwrap();
+    }
+
+    fn visit_link_reference(&mut self, text: &'ast Text<'_>, label: &'ast str) {
+        self.wrap("![", text, "][");
+        write!(self.output, "{}]", label).unwrap();
+    }
+
+    fn visit_strikethrough(&mut self, text: &'ast Text<'_>) {
+        self.wrap("~", text, "~");
+    }
+
+    fn visit_underline(&mut self, text: &'ast Text<'_>) {
+        self.wrap("_", text, "_");
+    }
+
+    fn visit_code(&mut self, text: &'ast str) {
+        write!(self.output, "${}$", text).unwrap();
+    }
+
+    fn visit_math(&mut self, tex: &'ast str) {
+        write!(self.output, "\\({}\\)", tex).unwrap();
+    }
+
+    fn visit_superscript(&mut self, text: &'ast Text<'_>) {
+        self.wrap("^", text, "^");
+    }
+
+    fn visit_subscript(&mut self, text: &'ast Text<'_>) {
+        self.wrap(",,", text, ",,");
+    }
+
+    fn visit_mark(&mut self, text: &'ast Text<'_>) {
+        self.wrap("==", text, "==");
+    }
+
+    fn visit_kbd(&mut self, keys: &'ast str) {
+        write!(self.output, "++{}++", keys).unwrap();
+    }
+
+    fn visit_inline_image(&mut self, alt: &'ast str, src: &'ast str) {
+        write!(self.output, "#[{}]({})", alt, src).unwrap();
+    }
+
+    fn visit_footnote_reference(&mut self, label: &'ast str) {
+        write!(self.output, "[^{}]", label).unwrap();
+    }
+
+    fn visit_heading_reference(&mut self, label: &'ast str) {
+        write!(self.output, "[@{}]", label).unwrap();
+    }
+
+    fn visit_text_error(&mut self, text: &'ast str) {
+        self.output.push_str(text);
+    }
+}
+
+/// Checks that formatting `source`, and printing its parse tree, keep the tree and
+/// that formatting is idempotent.
+#[cfg(test)]
+pub(crate) fn assert_round_trip(source: &str) {
+    use super::parser::Parser;
+    let parse = |source| Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics().0;
+    let tree = parse(source);
+    let formatted = format_source(source);
+    assert_eq!(parse(&formatted), tree, "formatting changed the tree of\n{}\nformatted as\n{}", source, formatted);
+    assert_eq!(format_source(&formatted), formatted, "formatting is not idempotent for\n{}", source);
+    let printed = format_body(&tree);
+    assert_eq!(parse(&printed), tree, "printing changed the tree of\n{}\nprinted as\n{}", source, printed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::format_source as format;
+
+    #[test]
+    fn format_normalises_layout() {
+        let source = "@Title\n@@   Sub\nSome *bold*  text\nmore\n\n\n\n-  One\n---   Deep\n--Two\n>Quote /it/\n| a | bcd |\n|long cell|x|\n#[Cap](img.png)\n#![Go]{https://example.com}\n$$$\n  code\n$$$";
+        assert_eq!(
+            format(source),
+            "@ Title\n\n@@ Sub\n\nSome *bold*  text\nmore\n\n- One\n--- Deep\n-- Two\n\n> Quote /it/\n\n| a         | bcd |\n| long cell | x   |\n\n#[Cap](img.png)\n\n#![Go]{https://example.com}\n\n$$$\n  code\n$$$\n"
+        );
+    }
+
+    #[test]
+    fn format_closes_codeblocks() {
+        assert_eq!(format("$$$\nunclosed"), "$$$\nunclosed\n$$$\n");
+        assert_eq!(format("$$$\n$$$"), "$$$\n$$$\n");
+        assert_eq!(format("%%%\nx^2"), "%%%\nx^2\n%%%\n");
+    }
+
+    #[test]
+    fn format_keeps_fence_lines() {
+        assert_eq!(format("$$$ rust  \r\nfn main() {}\r\n$$$"), "$$$ rust\nfn main() {}\n$$$\n");
+        assert_eq!(format("%%%% energy\nE = mc^2\n%%%\n$$$$\n"), "%%%% energy\nE = mc^2\n%%%\n\n$$$$\n$$$\n");
+        assert_round_trip("$$$ rust\nfn main() {}\n$$$\n%%% tex\nx\n%%%");
+    }
+
+    #[test]
+    fn format_keeps_definitions_in_place() {
+        let source = "Text[^a] and ![b][b]\n[^a]: Note\n[b]: /b\n[c]: /c\n\n\n[d]: /d\nMore ![c][c] ![d][d]\n\n[^e]: Last[^e]";
+        assert_eq!(
+            format(source),
+            "Text[^a] and ![b][b]\n\n[^a]: Note\n\n[b]: /b\n[c]: /c\n\n[d]: /d\n\nMore ![c][c] ![d][d]\n\n[^e]: Last[^e]\n"
+        );
+        assert_round_trip(source);
+    }
+
+    #[test]
+    fn format_keeps_blank_lines_within_blocks() {
+        let source = "- a\n\n\n-- b\n- c\n| x | yy |\n\n|long| z |\nOne\n\n\nTwo";
+        assert_eq!(
+            format(source),
+            "- a\n\n-- b\n- c\n\n| x    | yy |\n\n| long | z  |\n\nOne\n\nTwo\n"
+        );
+        assert_round_trip(source);
+    }
+
+    #[test]
+    fn format_round_trips_edge_cases() {
+        for source in [
+            "",
+            "@@@@ not a heading\n>> not a quote\n-\n----\n#hashtag",
+            "Text ![link](url) and ![broken]x\n*open",
+            "| a |\n| b | c |\n|  |",
+            "First\n\nSecond\n\n\nThird",
+            "- a\n\n- b\n\n\n-- c",
+            "\\@ heading\n\\- item \\*a\\* \\\\ C:\\dir\\\n| \\| | ![a\\]](u) |",
+            "%%%\nE = mc^2 \\label{e}\n%%%\nBy \\(\\eqref{e}\\), 50% or \\\\(x\\) of \\(a \\% b\\) and \\(open",
+            "\\%%% not an equation\n\\%%%\nText",
+            "[^b]: Second *b*\nText[^a] and [^b], \\[^a] [^ x]\n\n[^a]: First[^c]\n[^c]: Nested\n[^unused]: Unused",
+            "x^2^ H,,2,,O a^b,,c,,^ ==*hot* take== ++Ctrl+C++ \\^x^ \\,,y,, \\==z== \\++k++ a == b C++ 2^3 a,, b _{u}_",
+            "#[icon](i.png) starts a line\n\\#[not](an image) and ![https://a.org](b) or *https://a.org/x_y*",
+            "@ Intro {#intro}\n\n@@ Plain {#not a label}\n\nSee [@intro] or \\[@intro], \\[@x] and [@ y]",
+            "@ Using *Rust* /today/ {#rust}\n\n#[A $cat$ \\](x)](cat.png)\n\n#![Go ~now~ \\}]{/go}\n\n@@@ Not \\{#a}",
+            "See ![the *docs*][docs] or ![x][y]\n\\[not]: a definition\n[docs]: https://a.org \"The docs\"\n[y]: /y",
+            "\\$$$ not a fence\n\\$$$\n\\- a\n\\> b\n\\| c |\n\\@ d\n\\#[e](f)\n\\#![g]{h}\n\\[i]: j\n\\[^k]: l",
+        ] {
+            assert_round_trip(source);
+        }
+    }
+
+    #[test]
+    fn format_escapes_markup_in_text() {
+        assert_eq!(
+            format("\\@ at \\*x\\* C:\\dir \\![a](b)\n| a \\| b | ![\\]](u) |"),
+            "\\@ at \\*x* C:\\dir \\![a](b)\n\n| a \\| b | ![\\]](u) |\n"
+        );
+    }
+
+    #[test]
+    fn format_leaves_markers_inside_words_alone() {
+        for line in crate::inline::tests::TECHNICAL_PROSE {
+            assert_eq!(format(line), format!("{}\n", line));
+        }
+        assert_eq!(format("\\*a\\* \\/b c/ d\\_"), "\\*a* \\/b c/ d_\n");
+        assert_round_trip("*x\\* y* /a \\/b/ c\\/ d/ $e\\$ f$ _g \\_");
+        assert_round_trip("set _a and *b* _c d_ or /usr/ *x* then /e/ f/ and /g/.");
+    }
+
+    #[test]
+    fn format_writes_autolinks_bare() {
+        for (line, _) in crate::inline::tests::AUTOLINKS {
+            assert_eq!(format(line), format!("{}\n", line));
+        }
+        assert_eq!(format("![a@b.org](mailto:a@b.org)![https://c.org](https://c.org)"), "a@b.org![https://c.org](https://c.org)\n");
+    }
+}
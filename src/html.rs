@@ -1,3 +1,18 @@
+use std::io::{ self, Write };
+
 pub trait ToHtml {
 	fn to_html(&self) -> String;
-}
\ No newline at end of file
+}
+
+/// Renders straight into a writer, without building intermediate strings.
+pub trait WriteHtml {
+	fn write_html(&self, w: &mut impl Write) -> io::Result<()>;
+}
+
+impl<T: WriteHtml + ?Sized> ToHtml for T {
+	fn to_html(&self) -> String {
+		let mut html = Vec::new();
+		self.write_html(&mut html).expect("writing to a Vec never fails");
+		String::from_utf8(html).expect("rendered html is valid UTF-8")
+	}
+}
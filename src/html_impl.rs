@@ -1,273 +1,452 @@
-use super::html::ToHtml;
+use std::collections::HashMap;
+use std::io::{ self, Write };
+use super::document::Document;
+use super::html::WriteHtml;
+use super::math;
 use super::parser::*;
+use super::span::Spanned;
+use super::visit::{ self, Visitor };
 
-impl ToHtml for ParseArtefact {
-    fn to_html(&self) -> String {
-        match self {
-            ParseArtefact::Section(depth, title) => {
-                return format!("<h1>{depth} {title}</h1>");
-            }
-            ParseArtefact::Subsection(section_depth, depth, title) => {
-                return format!("<h2>{section_depth}.{depth} {title}</h2>");
-            }
-            ParseArtefact::Subsubsection(section_depth, subsection_depth, depth, title) => {
-                return format!("<h3>{section_depth}.{subsection_depth}.{depth} {title}</h3>");
-            }
-            ParseArtefact::Paragraph(text) => {
-                let mut paragraph_contents = String::new();
-                for artefact in text {
-                    paragraph_contents.push_str(&artefact.to_html());
-                }
+/// The built-in renderer: a visitor writing each node as html. The first write
+/// error stops all further output and is handed back by `finish`.
+struct HtmlWriter<'w, W: Write> {
+    w: &'w mut W,
+    result: io::Result<()>,
+    targets: Targets,
+    /// How many references to each footnote have been written, to give each its own id.
+    footnote_references: HashMap<String, usize>,
+}
 
-                return format!("<p>{}</p>", paragraph_contents);
-            }
-            ParseArtefact::List(list) => {
-                let mut list_contents = String::new();
-                for artefact in list {
-                    list_contents.push_str(&artefact.to_html());
-                }
-                return format!("<ol>{}</ol>", list_contents);
-            }
-            ParseArtefact::ListItem(text) => {
-                let list_item_contents = text.to_html();
-                return format!("<li>{}</li>", list_item_contents);
-            }
-            ParseArtefact::Quote(text) => {
-                let quote_contents = text.to_html();
-                return format!("<blockquote>{}</blockquote>", quote_contents);
-            }
-            ParseArtefact::Table(table_rows) => {
-                if let Some(ParseArtefact::TableRow(first_row_contents)) = table_rows.get(0) {
-                    let mut header_data = vec![];
-                    for artefact in first_row_contents {
-                        header_data.push(artefact.to_html());
-                    }
-                    let table_header = format!(
-                        "<thead><tr>{}</tr><thead>",
-                        header_data
-                            .iter()
-                            .map(|header| format!("<th>{}</th>", header))
-                            .collect::<Vec<String>>()
-                            .join("")
-                    );
-                    let mut table_body = String::new();
-                    for row in table_rows[1..].iter() {
-                        table_body.push_str(&row.to_html());
-                    }
-                    return format!("<table>{table_header}<tbody>{table_body}</tbody></table>");
-                } else {
-                    return String::new();
-                }
-            }
-            ParseArtefact::TableRow(row_contents) => {
-                let mut row_data = vec![];
-                for artefact in row_contents {
-                    row_data.push(artefact.to_html());
-                }
-                return format!(
-                    "<tr>{}</tr>",
-                    row_data
-                        .iter()
-                        .map(|header| format!("<td>{}</td>", header))
-                        .collect::<Vec<String>>()
-                        .join("")
-                );
-            }
-            ParseArtefact::Button(text, link) => {
-                return format!("<br><a href=\"{link}\" class=\"md-button\">{text}</a><br>");
-            }
-            ParseArtefact::Image(caption, link) => {
-                return format!(
-                    "<img src=\"{link}\" alt=\"{caption}\" /><p class=\"img-caption\">{caption}</p>"
-                );
-            }
-            ParseArtefact::Codeblock(code) => {
-                return format!("<pre><code>{code}</code></pre>");
-            }
-			ParseArtefact::Newline => {
-				return format!("<br>")
-			}
-            _ => {
-                panic!("Unkown artefact type");
-            }
+/// Collects what references in a tree can point to: the numbers of labelled
+/// equations and headings, the urls of link definitions, and the numbers of
+/// footnotes with how often each is referred to.
+#[derive(Default)]
+struct Targets {
+    equations: HashMap<String, usize>,
+    footnotes: HashMap<String, (Option<usize>, usize)>,
+    links: HashMap<String, (String, Option<String>)>,
+    /// The number and title of each labelled heading.
+    headings: HashMap<String, String>,
+}
+
+impl Targets {
+    fn heading(&mut self, number: String, title: &Text<'_>, label: Option<&str>) {
+        if let Some(label) = label {
+            self.headings.entry(label.to_string()).or_insert_with(|| format!("{number} {}", plain_text(title)));
         }
+        self.visit_text(title);
     }
 }
 
-impl<'a> ToHtml for &'a ParseArtefact {
-    fn to_html(&self) -> String {
-        match self {
-            ParseArtefact::Section(depth, title) => {
-                return format!("<h1>{depth} {title}</h1>");
-            }
-            ParseArtefact::Subsection(section_depth, depth, title) => {
-                return format!("<h2>{section_depth}.{depth} {title}</h2>");
-            }
-            ParseArtefact::Subsubsection(section_depth, subsection_depth, depth, title) => {
-                return format!("<h3>{section_depth}.{subsection_depth}.{depth} {title}</h3>");
-            }
-            ParseArtefact::Paragraph(text) => {
-                let mut paragraph_contents = String::new();
-                for artefact in text {
-                    paragraph_contents.push_str(&artefact.to_html());
-                }
-                return format!("<p>{}</p>", paragraph_contents);
-            }
-            ParseArtefact::List(list) => {
-                let mut list_contents = String::new();
-                for artefact in list {
-                    list_contents.push_str(&artefact.to_html());
-                }
-                return format!("<ol>{}</ol>", list_contents);
-            }
-            ParseArtefact::ListItem(text) => {
-                let list_item_contents = text.to_html();
-                return format!("<li>{}</li>", list_item_contents);
-            }
-            ParseArtefact::Quote(text) => {
-                let quote_contents = text.to_html();
-                return format!("<blockquote>{}</blockquote>", quote_contents);
-            }
-            ParseArtefact::Table(table_rows) => {
-                if let Some(ParseArtefact::TableRow(first_row_contents)) = table_rows.get(0) {
-                    let mut header_data = vec![];
-                    for artefact in first_row_contents {
-                        header_data.push(artefact.to_html());
-                    }
-                    let table_header = format!(
-                        "<thead><tr>{}</tr><thead>",
-                        header_data
-                            .iter()
-                            .map(|header| format!("<th>{}</th>", header))
-                            .collect::<Vec<String>>()
-                            .join("")
-                    );
-                    let mut table_body = String::new();
-                    for row in table_rows[1..].iter() {
-                        table_body.push_str(&row.to_html());
-                    }
-                    return format!(
-                        "<table>{table_header}<tbody>{table_body}</tbody></table>",
-                        table_header = table_header,
-                        table_body = table_body
-                    );
-                } else {
-                    return String::new();
-                }
-            }
-            ParseArtefact::TableRow(row_contents) => {
-                let mut row_data = vec![];
-                for artefact in row_contents {
-                    row_data.push(artefact.to_html());
-                }
-                return format!(
-                    "<tr>{}</tr>",
-                    row_data
-                        .iter()
-                        .map(|header| format!("<td>{}</td>", header))
-                        .collect::<Vec<String>>()
-                        .join("")
-                );
-            }
-            ParseArtefact::Button(text, link) => {
-                return format!("<br><a href=\"{link}\" class=\"md-button\">{text}</a><br>");
-            }
-            ParseArtefact::Image(caption, link) => {
-                return format!(
-                    "<img src=\"{link}\" alt=\"{caption}\" /><p class=\"img-caption\">{caption}</p>"
-                );
-            }
-            ParseArtefact::Codeblock(code) => {
-                return format!("<pre><code>{code}</code></pre>");
-            }
-			ParseArtefact::Newline => {
-				return format!("<br>")
-			}
-            _ => {
-                panic!("Unkown artefact type");
-            }
+impl<'ast> Visitor<'ast> for Targets {
+    fn visit_section(&mut self, number: usize, title: &'ast Text<'_>, label: Option<&'ast str>) {
+        self.heading(number.to_string(), title, label);
+    }
+
+    fn visit_subsection(&mut self, (section, number): (usize, usize), title: &'ast Text<'_>, label: Option<&'ast str>) {
+        self.heading(format!("{section}.{number}"), title, label);
+    }
+
+    fn visit_subsubsection(
+        &mut self,
+        (section, subsection, number): (usize, usize, usize),
+        title: &'ast Text<'_>,
+        label: Option<&'ast str>
+    ) {
+        self.heading(format!("{section}.{subsection}.{number}"), title, label);
+    }
+
+    fn visit_equation(&mut self, number: Option<usize>, tex: &'ast str) {
+        if let (Some(number), Ok(math::Formula { label: Some((label, _)), .. })) = (number, math::parse(tex, true)) {
+            self.equations.entry(label.to_string()).or_insert(number);
         }
     }
+
+    fn visit_footnote(&mut self, number: Option<usize>, label: &'ast str, text: &'ast Text<'_>) {
+        if number.is_some() {
+            self.footnotes.entry(label.to_string()).or_default().0 = number;
+        }
+        self.visit_text(text);
+    }
+
+    fn visit_footnote_reference(&mut self, label: &'ast str) {
+        self.footnotes.entry(label.to_string()).or_default().1 += 1;
+    }
+
+    fn visit_link_definition(&mut self, label: &'ast str, url: &'ast str, title: Option<&'ast str>) {
+        self.links.entry(label.to_string()).or_insert_with(|| (url.to_string(), title.map(str::to_string)));
+    }
 }
 
-impl ToHtml for Text {
-    fn to_html(&self) -> String {
-        let Text(contents) = self;
-        contents
-            .iter()
-            .map(|text| text.to_html())
-            .collect::<Vec<String>>()
-            .join(" ")
+/// Gathers the words of a text without its markup, for attributes and for
+/// references that repeat a heading's title.
+#[derive(Default)]
+struct PlainText(String);
+
+impl<'ast> Visitor<'ast> for PlainText {
+    fn visit_raw(&mut self, text: &'ast str) {
+        self.0.push_str(text);
+    }
+
+    fn visit_code(&mut self, text: &'ast str) {
+        self.0.push_str(text);
+    }
+
+    fn visit_math(&mut self, tex: &'ast str) {
+        self.0.push_str(tex);
+    }
+
+    fn visit_kbd(&mut self, keys: &'ast str) {
+        self.0.push_str(keys);
+    }
+
+    fn visit_inline_image(&mut self, alt: &'ast str, _src: &'ast str) {
+        self.0.push_str(alt);
     }
 }
 
-impl<'a> ToHtml for &'a Text {
-    fn to_html(&self) -> String {
-        let Text(contents) = self;
-        return contents
-            .iter()
-            .map(|text| text.to_html())
-            .collect::<Vec<String>>()
-            .join(" ");
+fn plain_text(text: &Text<'_>) -> String {
+    let mut plain = PlainText::default();
+    plain.visit_text(text);
+    plain.0
+}
+
+/// The id attribute of a heading, which only labelled headings have.
+fn heading_id(label: Option<&str>) -> String {
+    label.map(|label| format!(" id=\"sec-{label}\"")).unwrap_or_default()
+}
+
+/// The id of the `nth` reference to a footnote, counting from 1.
+fn footnote_reference_id(label: &str, nth: usize) -> String {
+    match nth {
+        1 => format!("fnref-{label}"),
+        _ => format!("fnref-{label}-{nth}"),
     }
 }
 
-impl ToHtml for TextArtefact {
-    fn to_html(&self) -> String {
-        match self {
-            TextArtefact::Bold(text) => {
-                return format!("<strong>{}</strong>", text);
-            }
-            TextArtefact::Italics(text) => {
-                return format!("<em>{}</em>", text);
-            }
-            TextArtefact::Strikethrough(text) => {
-                return format!("<del>{}</del>", text);
-            }
-            TextArtefact::Underline(text) => {
-                return format!("<u>{}</u>", text);
-            }
-            TextArtefact::Code(text) => {
-                return format!("<code>{}</code>", text);
-            }
-            TextArtefact::Link(text, link) => {
-                return format!("<a href=\"{link}\">{text}</a>");
-            }
-            TextArtefact::Raw(text) => {
-                return text.to_string();
-            }
-            _ => {
-				panic!("Unkown artefact type");
-			}
-		}
+impl<'w, W: Write> HtmlWriter<'w, W> {
+    fn new(w: &'w mut W, targets: Targets) -> Self {
+        HtmlWriter { w, result: Ok(()), targets, footnote_references: HashMap::new() }
+    }
+
+    fn write(&mut self, args: std::fmt::Arguments) {
+        if self.result.is_ok() {
+            self.result = self.w.write_fmt(args);
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        self.result
+    }
+
+    /// Writes each cell of a table row wrapped in `tag`.
+    fn write_cells(&mut self, cells: &[Text<'_>], tag: &str) {
+        self.write(format_args!("<tr>"));
+        for cell in cells {
+            self.write(format_args!("<{tag}>"));
+            self.visit_text(cell);
+            self.write(format_args!("</{tag}>"));
+        }
+        self.write(format_args!("</tr>"));
+    }
+
+    /// Converts LaTeX to MathML, showing the source as an error if it is invalid. A lone
+    /// reference to an equation becomes a link instead. Returns the equation's label too.
+    fn mathml<'t>(&self, tex: &'t str, display: bool) -> (Option<&'t str>, String) {
+        match math::parse(tex, display) {
+            Ok(formula) => {
+                let number = |label: &str| self.targets.equations.get(label).copied();
+                let mathml = formula.to_link(&number).unwrap_or_else(|| formula.to_mathml(&number));
+                (formula.label.map(|(label, _)| label), mathml)
+            }
+            Err(_) => (None, math::invalid_mathml(tex, display)),
+        }
+    }
+
+    /// Writes inline text wrapped in `tag`.
+    fn write_heading(&mut self, tag: &str, number: String, title: &Text<'_>, label: Option<&str>) {
+        let id = heading_id(label);
+        self.write(format_args!("<{tag}{id}>{number} "));
+        self.visit_text(title);
+        self.write(format_args!("</{tag}>"));
+    }
+
+    fn write_wrapped(&mut self, tag: &str, text: &Text<'_>) {
+        self.write(format_args!("<{tag}>"));
+        self.visit_text(text);
+        self.write(format_args!("</{tag}>"));
     }
 }
 
-impl<'a> ToHtml for &'a TextArtefact {
-    fn to_html(&self) -> String {
-        match self {
-            TextArtefact::Bold(text) => {
-                return format!("<strong>{}</strong>", text);
-            }
-            TextArtefact::Italics(text) => {
-                return format!("<em>{}</em>", text);
-            }
-            TextArtefact::Strikethrough(text) => {
-                return format!("<del>{}</del>", text);
-            }
-            TextArtefact::Underline(text) => {
-                return format!("<u>{}</u>", text);
-            }
-            TextArtefact::Code(text) => {
-                return format!("<code>{}</code>", text);
-            }
-            TextArtefact::Link(text, link) => {
-                return format!("<a href=\"{link}\">{text}</a>");
-            }
-            TextArtefact::Raw(text) => {
-                return text.to_string();
+impl<'ast, W: Write> Visitor<'ast> for HtmlWriter<'_, W> {
+    fn visit_section(&mut self, number: usize, title: &'ast Text<'_>, label: Option<&'ast str>) {
+        self.write_heading("h1", number.to_string(), title, label);
+    }
+
+    fn visit_subsection(&mut self, (section, number): (usize, usize), title: &'ast Text<'_>, label: Option<&'ast str>) {
+        self.write_heading("h2", format!("{section}.{number}"), title, label);
+    }
+
+    fn visit_subsubsection(
+        &mut self,
+        (section, subsection, number): (usize, usize, usize),
+        title: &'ast Text<'_>,
+        label: Option<&'ast str>
+    ) {
+        self.write_heading("h3", format!("{section}.{subsection}.{number}"), title, label);
+    }
+
+    fn visit_paragraph(&mut self, lines: &'ast [Text<'_>]) {
+        self.write(format_args!("<p>"));
+        visit::walk_texts(self, lines);
+        self.write(format_args!("</p>"));
+    }
+
+    fn visit_list(&mut self, items: &'ast [Spanned<ParseArtefact<'_>>]) {
+        self.write(format_args!("<ol>"));
+        self.visit_artefacts(items);
+        self.write(format_args!("</ol>"));
+    }
+
+    fn visit_list_item(&mut self, text: &'ast Text<'_>) {
+        self.write(format_args!("<li>"));
+        self.visit_text(text);
+        self.write(format_args!("</li>"));
+    }
+
+    fn visit_quote(&mut self, text: &'ast Text<'_>) {
+        self.write(format_args!("<blockquote>"));
+        self.visit_text(text);
+        self.write(format_args!("</blockquote>"));
+    }
+
+    fn visit_table(&mut self, rows: &'ast [Spanned<ParseArtefact<'_>>]) {
+        if let Some(ParseArtefact::TableRow(header)) = rows.first().map(|row| &row.node) {
+            self.write(format_args!("<table><thead>"));
+            self.write_cells(header, "th");
+            self.write(format_args!("<thead><tbody>"));
+            self.visit_artefacts(&rows[1..]);
+            self.write(format_args!("</tbody></table>"));
+        }
+    }
+
+    fn visit_table_row(&mut self, columns: &'ast [Text<'_>]) {
+        self.write_cells(columns, "td");
+    }
+
+    fn visit_button(&mut self, text: &'ast Text<'_>, link: &'ast str) {
+        self.write(format_args!("<br><a href=\"{link}\" class=\"md-button\">"));
+        self.visit_text(text);
+        self.write(format_args!("</a><br>"));
+    }
+
+    fn visit_image(&mut self, caption: &'ast Text<'_>, path: &'ast str) {
+        let alt = plain_text(caption);
+        self.write(format_args!("<img src=\"{path}\" alt=\"{alt}\" /><p class=\"img-caption\">"));
+        self.visit_text(caption);
+        self.write(format_args!("</p>"));
+    }
+
+    fn visit_codeblock(&mut self, code: &'ast str) {
+        self.write(format_args!("<pre><code>{code}</code></pre>"));
+    }
+
+    fn visit_equation(&mut self, number: Option<usize>, tex: &'ast str) {
+        let (label, mathml) = self.mathml(tex, true);
+        match label {
+            Some(label) => self.write(format_args!("<div class=\"equation\" id=\"eq-{label}\">{mathml}")),
+            None => self.write(format_args!("<div class=\"equation\">{mathml}")),
+        }
+        if let Some(number) = number {
+            self.write(format_args!("<span class=\"equation-number\">({number})</span>"));
+        }
+        self.write(format_args!("</div>"));
+    }
+
+    fn visit_footnotes(&mut self, footnotes: &'ast [Spanned<ParseArtefact<'_>>]) {
+        if self.targets.footnotes.values().any(|(number, _)| number.is_some()) {
+            self.write(format_args!("<section class=\"footnotes\"><ol>"));
+            self.visit_artefacts(footnotes);
+            self.write(format_args!("</ol></section>"));
+        }
+    }
+
+    fn visit_footnote(&mut self, number: Option<usize>, label: &'ast str, text: &'ast Text<'_>) {
+        if number.is_none() {
+            return;
+        }
+        self.write(format_args!("<li id=\"fn-{label}\">"));
+        self.visit_text(text);
+        let references = self.targets.footnotes.get(label).map_or(0, |&(_, references)| references);
+        for nth in 1..=references {
+            let id = footnote_reference_id(label, nth);
+            self.write(format_args!(" <a href=\"#{id}\" class=\"footnote-backref\">↩</a>"));
+        }
+        self.write(format_args!("</li>"));
+    }
+
+    fn visit_newline(&mut self) {
+        self.write(format_args!("<br>"));
+    }
+
+    fn visit_error(&mut self, text: &'ast str) {
+        self.write(format_args!("<p>{text}</p>"));
+    }
+
+    fn visit_text(&mut self, text: &'ast Text<'_>) {
+        for artefact in &text.0 {
+            self.visit_text_artefact(&artefact.node);
+        }
+    }
+
+    fn visit_raw(&mut self, text: &'ast str) {
+        self.write(format_args!("{text}"));
+    }
+
+    fn visit_bold(&mut self, text: &'ast Text<'_>) {
+        self.write_wrapped("strong", text);
+    }
+
+    fn visit_italics(&mut self, text: &'ast Text<'_>) {
+        self.write_wrapped("em", text);
+    }
+
+    fn visit_link(&mut self, text: &'ast Text<'_>, url: &'ast str) {
+        self.write(format_args!("<a href=\"{url}\">"));
+        self.visit_text(text);
+        self.write(format_args!("</a>"));
+    }
+
+    fn visit_link_reference(&mut self, text: &'ast Text<'_>, label: &'ast str) {
+        let Some((url, title)) = self.targets.links.get(label).cloned() else {
+            return self.visit_text(text);
+        };
+        let url = math::escape(&url);
+        match title {
+            Some(title) => self.write(format_args!("<a href=\"{url}\" title=\"{}\">", math::escape(&title))),
+            None => self.write(format_args!("<a href=\"{url}\">")),
+        }
+        self.visit_text(text);
+        self.write(format_args!("</a>"));
+    }
+
+    fn visit_strikethrough(&mut self, text: &'ast Text<'_>) {
+        self.write_wrapped("del", text);
+    }
+
+    fn visit_underline(&mut self, text: &'ast Text<'_>) {
+        self.write_wrapped("u", text);
+    }
+
+    fn visit_code(&mut self, text: &'ast str) {
+        self.write(format_args!("<code>{text}</code>"));
+    }
+
+    fn visit_superscript(&mut self, text: &'ast Text<'_>) {
+        self.write_wrapped("sup", text);
+    }
+
+    fn visit_subscript(&mut self, text: &'ast Text<'_>) {
+        self.write_wrapped("sub", text);
+    }
+
+    fn visit_mark(&mut self, text: &'ast Text<'_>) {
+        self.write_wrapped("mark", text);
+    }
+
+    fn visit_kbd(&mut self, keys: &'ast str) {
+        self.write(format_args!("<kbd>{keys}</kbd>"));
+    }
+
+    fn visit_inline_image(&mut self, alt: &'ast str, src: &'ast str) {
+        self.write(format_args!("<img src=\"{src}\" alt=\"{alt}\" />"));
+    }
+
+    fn visit_math(&mut self, tex: &'ast str) {
+        let (_, mathml) = self.mathml(tex, false);
+        self.write(format_args!("{mathml}"));
+    }
+
+    fn visit_footnote_reference(&mut self, label: &'ast str) {
+        match self.targets.footnotes.get(label) {
+            Some(&(Some(number), _)) => {
+                let nth = self.footnote_references.entry(label.to_string()).or_default();
+                *nth += 1;
+                let id = footnote_reference_id(label, *nth);
+                self.write(
+                    format_args!("<sup class=\"footnote-ref\"><a href=\"#fn-{label}\" id=\"{id}\">{number}</a></sup>")
+                );
             }
-            _ => { panic!("Unkown artefact type"); }
+            _ => self.write(format_args!("[^{label}]")),
+        }
+    }
+
+    fn visit_heading_reference(&mut self, label: &'ast str) {
+        match self.targets.headings.get(label).cloned() {
+            Some(heading) => self.write(format_args!("<a href=\"#sec-{label}\">{}</a>", math::escape(&heading))),
+            None => self.write(format_args!("[@{label}]")),
         }
     }
-}
\ No newline at end of file
+
+    fn visit_text_error(&mut self, text: &'ast str) {
+        self.write(format_args!("{text}"));
+    }
+}
+
+impl<T: WriteHtml> WriteHtml for Spanned<T> {
+    fn write_html(&self, w: &mut impl Write) -> io::Result<()> {
+        self.node.write_html(w)
+    }
+}
+
+impl WriteHtml for ParseArtefact<'_> {
+    fn write_html(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut targets = Targets::default();
+        targets.visit_artefact(self);
+        let mut html = HtmlWriter::new(w, targets);
+        html.visit_artefact(self);
+        html.finish()
+    }
+}
+
+impl WriteHtml for Text<'_> {
+    fn write_html(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut html = HtmlWriter::new(w, Targets::default());
+        html.visit_text(self);
+        html.finish()
+    }
+}
+
+impl WriteHtml for TextArtefact<'_> {
+    fn write_html(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut html = HtmlWriter::new(w, Targets::default());
+        html.visit_text_artefact(self);
+        html.finish()
+    }
+}
+
+impl WriteHtml for Document<'_> {
+    fn write_html(&self, w: &mut impl Write) -> io::Result<()> {
+        write!(
+            w,
+            r#"<!DOCTYPE html>
+<html>
+	<head>
+		<title>{}</title>
+	<style>{}</style>
+	</head>
+	<body>
+		<div id="content">"#,
+            self.title(),
+            self.style().unwrap_or("")
+        )?;
+        let mut targets = Targets::default();
+        targets.visit_artefacts(self.body());
+        let mut html = HtmlWriter::new(w, targets);
+        html.visit_artefacts(self.body());
+        html.finish()?;
+        write!(w, r#"</div>
+	</body>
+</html>"#)
+    }
+}
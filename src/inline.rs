@@ -0,0 +1,927 @@
+use std::borrow::Cow;
+use super::cst::is_label;
+use super::diagnostic::Diagnostic;
+use super::math;
+use super::parser::{ ParseError, Text, TextArtefact };
+use super::span::{ Span, Spanned };
+
+pub(crate) const INLINE_LINK_NOTE: &str = "inline links are written as `![text](url)` or `![text][label]`";
+
+/// Builds the artefact for a styled run from its contents.
+type Style<'a> = fn(Text<'a>) -> TextArtefact<'a>;
+
+/// Maps an inline style marker to the name used in diagnostics and the artefact it produces.
+fn inline_style<'a>(marker: char) -> Option<(&'static str, Style<'a>)> {
+    match marker {
+        '*' => Some(("bold text", TextArtefact::Bold)),
+        '/' => Some(("italic text", TextArtefact::Italics)),
+        '~' => Some(("strikethrough text", TextArtefact::Strikethrough)),
+        '_' => Some(("underlined text", TextArtefact::Underline)),
+        '=' => Some(("highlighted text", TextArtefact::Mark)),
+        _ => None,
+    }
+}
+
+/// Maps the marker of a superscript or subscript, as in `x^2^` or `H,,2,,O`, to the
+/// name used in diagnostics and the artefact it produces. Scripts open and close inside
+/// words too, but can't hold whitespace.
+fn script<'a>(marker: char) -> Option<(&'static str, Style<'a>)> {
+    match marker {
+        '^' => Some(("superscript", TextArtefact::Superscript)),
+        ',' => Some(("subscript", TextArtefact::Subscript)),
+        _ => None,
+    }
+}
+
+/// Markers that only count when doubled, as in `==highlight==`, `++Ctrl++` and `H,,2,,O`.
+fn is_doubled(marker: char) -> bool {
+    marker == '=' || marker == '+' || marker == ','
+}
+
+/// Whether a superscript or subscript `marker` followed by `rest` opens: it needs
+/// contents and a closing marker before the next whitespace, so `2^3 = 8` stays text.
+pub(crate) fn opens_script(marker: &str, rest: &str) -> bool {
+    let word = &rest[..rest.find(char::is_whitespace).unwrap_or(rest.len())];
+    word.find(marker).is_some_and(|end| end > 0)
+}
+
+/// Characters besides whitespace that may come right before an opening marker.
+const OPENING_PUNCTUATION: &str = "([{\"'-";
+
+/// Characters besides whitespace and markers that may come right after a closing marker.
+/// `[` lets a footnote reference follow a style, as in `*word*[^1]`.
+const CLOSING_PUNCTUATION: &str = ".,;:!?)[]}\"'-";
+
+/// The label of the footnote reference, `[^label]`, that `text` starts with.
+fn footnote_label(text: &str) -> Option<&str> {
+    let (label, _) = text.strip_prefix("[^")?.split_once(']')?;
+    Some(label).filter(|label| is_label(label))
+}
+
+/// The label of the heading reference, `[@label]`, that `text` starts with.
+fn heading_label(text: &str) -> Option<&str> {
+    let (label, _) = text.strip_prefix("[@")?.split_once(']')?;
+    Some(label).filter(|label| is_label(label))
+}
+
+/// The alt text and source of the inline image, `#[alt](src)`, that `text` starts with.
+fn inline_image(text: &str) -> Option<(&str, &str)> {
+    let (alt, rest) = text.strip_prefix("#[")?.split_once(']')?;
+    let (src, _) = rest.strip_prefix('(')?.split_once(')')?;
+    Some((alt, src)).filter(|_| !src.trim().is_empty())
+}
+
+/// Finds a bare url or email address at the start of `text` and returns its length
+/// and where it links to. Punctuation at its end, and the closers of the styles in
+/// `open`, are taken to be part of the sentence rather than the address.
+pub(crate) fn autolink<'t>(text: &'t str, open: &[char]) -> Option<(usize, Cow<'t, str>)> {
+    let word = &text[..text.find(char::is_whitespace).unwrap_or(text.len())];
+    let mut end = word.len();
+    while let Some(last) = word[..end].chars().next_back() {
+        let unbalanced = |open_paren, close_paren| {
+            word[..end].matches(open_paren).count() < word[..end].matches(close_paren).count()
+        };
+        let trailing = match last {
+            ')' => unbalanced('(', ')'),
+            ']' => unbalanced('[', ']'),
+            _ => ".,;:!?\"'}".contains(last) || open.contains(&last),
+        };
+        if !trailing {
+            break;
+        }
+        end -= last.len_utf8();
+    }
+    let address = &word[..end];
+    if let Some(host) = address.strip_prefix("https://").or_else(|| address.strip_prefix("http://")) {
+        return Some((end, Cow::Borrowed(address))).filter(|_| host.starts_with(|ch: char| ch.is_alphanumeric()));
+    }
+    let (local, domain) = address.split_once('@')?;
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let email = !local.is_empty()
+        && local.chars().all(|ch| ch.is_alphanumeric() || "._%+-".contains(ch))
+        && labels.len() > 1
+        && labels.iter().all(|label| !label.is_empty() && label.chars().all(|ch| ch.is_alphanumeric() || ch == '-'))
+        && labels.last().is_some_and(|tld| tld.len() > 1 && tld.chars().all(char::is_alphabetic));
+    Some((end, Cow::Owned(format!("mailto:{}", address)))).filter(|_| email)
+}
+
+fn is_marker(ch: char) -> bool {
+    inline_style(ch).is_some() || script(ch).is_some() || matches!(ch, '$' | '+')
+}
+
+/// Whether a word can start after `prev`, which is `None` at the start of the line.
+pub(crate) fn starts_word(prev: Option<char>) -> bool {
+    prev.is_none_or(|prev| prev.is_whitespace() || OPENING_PUNCTUATION.contains(prev))
+}
+
+/// Whether a marker between `prev` and `next` starts a word, so that it can open a
+/// style or code span. `None` stands for either end of the line. A `~`
+/// before `/` starts a home directory path, as in `~/bin`, rather than strikethrough.
+pub(crate) fn can_open(prev: Option<char>, next: Option<char>, marker: char) -> bool {
+    starts_word(prev)
+        && next.is_some_and(|next| !next.is_whitespace() && next != marker)
+        && !(marker == '~' && next == Some('/'))
+}
+
+/// Whether a marker between `prev` and `next` ends a word, so that it can close a
+/// style or code span. Markers inside words, as in `snake_case` or `src/main.rs`,
+/// can do neither and are plain text.
+pub(crate) fn can_close(prev: Option<char>, next: Option<char>) -> bool {
+    prev.is_some_and(|prev| !prev.is_whitespace())
+        && next.is_none_or(|next| next.is_whitespace() || CLOSING_PUNCTUATION.contains(next) || is_marker(next))
+}
+
+/// Whether a style `marker` between `prev` and `next` can close its style. Besides
+/// ending a word, a `/` or `_` can't touch another of itself, as in `__init__`.
+pub(crate) fn can_close_style(prev: Option<char>, next: Option<char>, marker: char) -> bool {
+    can_close(prev, next) && !(is_path_marker(marker) && [prev, next].contains(&Some(marker)))
+}
+
+/// Markers that are common in paths and identifiers, as in `/usr/bin` or `__init__`.
+fn is_path_marker(marker: char) -> bool {
+    marker == '/' || marker == '_'
+}
+
+/// Whether a `/` or `_` marker followed by `rest` starts a path or identifier rather
+/// than a style: either the same marker shows up again inside the word, where it can't
+/// close, as in `/usr/bin` or `_a_b`, or the word is a directory like `/etc/` followed
+/// by more text.
+pub(crate) fn starts_path(marker: char, rest: &str) -> bool {
+    let end = rest.find(char::is_whitespace);
+    let word = &rest[..end.unwrap_or(rest.len())];
+    let inner = word.char_indices().any(|(index, ch)| {
+        let prev = word[..index].chars().next_back().or(Some(marker));
+        ch == marker && !can_close_style(prev, word[index + 1..].chars().next(), marker)
+    });
+    let directory = marker == '/' && end.is_some() && word.ends_with('/');
+    is_path_marker(marker) && (inner || directory)
+}
+
+/// How a run of inline text ended.
+enum RunEnd {
+    /// At the closer of the innermost open style, which has been consumed.
+    Closed,
+    /// At the end of the line.
+    Eol,
+    /// At the closer of an enclosing style, which is left for that style to consume.
+    Interrupted(char),
+}
+
+/// Splits a single line of text into styled runs. Scans the line in place by
+/// byte index, recording diagnostics for malformed markup as it goes. Styles and
+/// link texts nest; code spans, math, link urls and footnote references are literal. Markers only open and
+/// close styles at word boundaries.
+pub(crate) struct InlineParser<'a, 'd> {
+    text: &'a str,
+    /// Byte offset of the next unread character in `text`.
+    pos: usize,
+    /// Number of characters before `pos`, to keep columns in characters.
+    chars: usize,
+    span: Span,
+    /// The closing markers of the styles being parsed, innermost last.
+    open: Vec<char>,
+    /// Byte offset where the innermost run starts, which counts as a line start.
+    run_start: usize,
+    diagnostics: &'d mut Vec<Diagnostic>,
+}
+
+impl<'a, 'd> InlineParser<'a, 'd> {
+    /// `span` is where `text` starts in the source.
+    pub(crate) fn new(text: &'a str, span: Span, diagnostics: &'d mut Vec<Diagnostic>) -> Self {
+        InlineParser { text, pos: 0, chars: 0, span, open: vec![], run_start: 0, diagnostics }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// The character before `pos`, or `None` at the start of the innermost run.
+    fn prev(&self) -> Option<char> {
+        match self.pos == self.run_start {
+            true => None,
+            false => self.text[..self.pos].chars().next_back(),
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        self.chars += 1;
+        Some(ch)
+    }
+
+    /// Consumes everything up to, but not including, the next `end`, or up to
+    /// the end of the line if there is none.
+    fn eat_until(&mut self, end: char) -> &'a str {
+        let rest = self.rest();
+        self.eat(rest.find(end).unwrap_or(rest.len()))
+    }
+
+    /// Consumes the next `len` bytes.
+    fn eat(&mut self, len: usize) -> &'a str {
+        let eaten = &self.rest()[..len];
+        self.pos += eaten.len();
+        self.chars += eaten.chars().count();
+        eaten
+    }
+
+    /// Returns the empty span at the current position.
+    fn cursor(&self) -> Span {
+        let start = self.span.start + self.pos;
+        Span { start, end: start, column: self.span.column + self.chars, ..self.span }
+    }
+
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.cursor())
+    }
+
+    pub(crate) fn parse(mut self) -> Vec<Spanned<TextArtefact<'a>>> {
+        self.parse_run().0
+    }
+
+    /// Parses styled runs until the closer of the innermost open style, or of any
+    /// enclosing style, or the end of the line.
+    fn parse_run(&mut self) -> (Vec<Spanned<TextArtefact<'a>>>, RunEnd) {
+        let mut artefacts = vec![];
+        let mut raw_start = self.cursor();
+        let end = loop {
+            let Some(ch) = self.peek() else {
+                break RunEnd::Eol;
+            };
+            let start = self.cursor();
+            let marker = self.marker(ch);
+            let closes = ch == ']'
+                || marker.is_some_and(|marker| script(ch).is_some() || can_close_style(self.prev(), self.after(marker), ch));
+            let parsed = if self.open.contains(&ch) && closes {
+                self.push_raw(&mut artefacts, raw_start, start);
+                if self.open.last() == Some(&ch) {
+                    self.eat(marker.map_or(1, str::len));
+                    break RunEnd::Closed;
+                }
+                break RunEnd::Interrupted(ch);
+            } else if let (Some(marker), Some((name, style))) = (marker.filter(|&marker| self.opens_script(marker)), script(ch)) {
+                self.parse_style(marker, name, style)
+            } else if let (Some(marker), Some((name, style))) = (marker.filter(|&marker| self.opens(marker)), inline_style(ch)) {
+                self.parse_style(marker, name, style)
+            } else if ch == '$' && self.opens("$") {
+                self.parse_code()
+            } else if self.rest().starts_with("\\(") {
+                self.parse_math()
+            } else if marker == Some("++") && self.opens("++") {
+                self.parse_kbd()
+            } else if ch == '\\' && self.rest()[1..].starts_with(|next: char| next.is_ascii_punctuation()) {
+                self.push_raw(&mut artefacts, raw_start, start);
+                self.bump();
+                let escaped = self.cursor();
+                self.bump();
+                let text = self.slice(escaped, self.cursor());
+                self.push(&mut artefacts, Spanned::new(TextArtefact::Raw(text), self.span_from(start)));
+                raw_start = self.cursor();
+                continue;
+            } else if ch == '!' && self.rest()[1..].starts_with('[') && !self.open.contains(&']') {
+                self.parse_link()
+            } else if let Some(label) = footnote_label(self.rest()) {
+                self.eat(label.len() + 3);
+                vec![Spanned::new(TextArtefact::FootnoteReference(Cow::Borrowed(label)), self.span_from(start))]
+            } else if let Some(label) = heading_label(self.rest()) {
+                self.eat(label.len() + 3);
+                vec![Spanned::new(TextArtefact::HeadingReference(Cow::Borrowed(label)), self.span_from(start))]
+            } else if let Some((alt, src)) = inline_image(self.rest()) {
+                self.eat(alt.len() + src.len() + 5);
+                let image = TextArtefact::Image(Cow::Borrowed(alt), Cow::Borrowed(src));
+                vec![Spanned::new(image, self.span_from(start))]
+            } else if let Some((len, url)) = self.autolink() {
+                let address = self.eat(len);
+                let span = self.span_from(start);
+                let text = Text(vec![Spanned::new(TextArtefact::Raw(Cow::Borrowed(address)), span)]);
+                vec![Spanned::new(TextArtefact::Link(text, url), span)]
+            } else {
+                self.bump();
+                continue;
+            };
+            self.push_raw(&mut artefacts, raw_start, start);
+            for artefact in parsed {
+                self.push(&mut artefacts, artefact);
+            }
+            raw_start = self.cursor();
+        };
+        if let RunEnd::Eol = end {
+            let end = self.cursor();
+            self.push_raw(&mut artefacts, raw_start, end);
+        }
+        (artefacts, end)
+    }
+
+    /// The marker that `ch` at `pos` starts. Doubled markers like `=` count only when
+    /// the next character is the same.
+    fn marker(&self, ch: char) -> Option<&'a str> {
+        let len = match is_doubled(ch) {
+            true if self.rest()[1..].starts_with(ch) => 2,
+            true => return None,
+            false => ch.len_utf8(),
+        };
+        Some(&self.rest()[..len])
+    }
+
+    /// The character after the `marker` at `pos`.
+    fn after(&self, marker: &str) -> Option<char> {
+        self.rest()[marker.len()..].chars().next()
+    }
+
+    /// Finds a bare url or email address starting a word at `pos`. Link texts are
+    /// left alone, since they are links already.
+    fn autolink(&self) -> Option<(usize, Cow<'a, str>)> {
+        if !starts_word(self.prev()) || self.open.contains(&']') {
+            return None;
+        }
+        autolink(self.rest(), &self.open)
+    }
+
+    /// Whether the `marker` at `pos` opens a style. A style can't open inside itself.
+    fn opens(&self, marker: &str) -> bool {
+        let ch = marker.chars().next().unwrap_or_default();
+        !self.open.contains(&ch)
+            && can_open(self.prev(), self.after(marker), ch)
+            && !starts_path(ch, &self.rest()[marker.len()..])
+    }
+
+    /// Whether the superscript or subscript `marker` at `pos` opens. Like styles,
+    /// scripts can't open inside themselves.
+    fn opens_script(&self, marker: &str) -> bool {
+        !self.open.contains(&marker.chars().next().unwrap_or_default()) && opens_script(marker, &self.rest()[marker.len()..])
+    }
+
+    /// Parses the contents of a style or link text up to its `closer`.
+    fn parse_nested(&mut self, closer: char) -> (Text<'a>, RunEnd) {
+        self.open.push(closer);
+        let outer = std::mem::replace(&mut self.run_start, self.pos);
+        let (artefacts, end) = self.parse_run();
+        self.run_start = outer;
+        self.open.pop();
+        (Text(artefacts), end)
+    }
+
+    /// Appends an artefact, merging it into a raw artefact right before it. Raw text
+    /// that isn't the verbatim source, such as an escaped character without its
+    /// backslash, makes the merged text a copy.
+    fn push(&self, artefacts: &mut Vec<Spanned<TextArtefact<'a>>>, artefact: Spanned<TextArtefact<'a>>) {
+        let verbatim = |text: &Cow<str>, span: Span| matches!(text, Cow::Borrowed(_)) && text.len() == span.len();
+        match (artefacts.last_mut(), artefact.node) {
+            (Some(Spanned { node: TextArtefact::Raw(last), span }), TextArtefact::Raw(text)) => {
+                if verbatim(last, *span) && verbatim(&text, artefact.span) {
+                    *last = self.slice(*span, artefact.span);
+                } else {
+                    last.to_mut().push_str(&text);
+                }
+                span.end = artefact.span.end;
+            }
+            (_, node) => artefacts.push(Spanned::new(node, artefact.span)),
+        }
+    }
+
+    fn push_raw(&self, artefacts: &mut Vec<Spanned<TextArtefact<'a>>>, start: Span, end: Span) {
+        if end.start > start.start {
+            self.push(artefacts, self.raw(start, end));
+        }
+    }
+
+    /// Returns the source text from the start of `start` to the end of `end`.
+    fn slice(&self, start: Span, end: Span) -> Cow<'a, str> {
+        let offset = self.span.start;
+        Cow::Borrowed(&self.text[start.start - offset..end.end - offset])
+    }
+
+    fn raw(&self, start: Span, end: Span) -> Spanned<TextArtefact<'a>> {
+        Spanned::new(TextArtefact::Raw(self.slice(start, end)), start.to(end))
+    }
+
+    fn error(&self, start: Span) -> Vec<Spanned<TextArtefact<'a>>> {
+        vec![Spanned::new(TextArtefact::Error(self.slice(start, self.cursor())), self.span_from(start))]
+    }
+
+    /// Records a warning for a style that an enclosing style closed before it was
+    /// itself closed, and keeps its opening `marker` as text, followed by what it contained.
+    fn interrupted(
+        &mut self,
+        start: Span,
+        marker: &str,
+        name: &str,
+        interrupter: char,
+        contents: Text<'a>
+    ) -> Vec<Spanned<TextArtefact<'a>>> {
+        let marker_span = Span { end: start.start + marker.len(), ..start };
+        self.diagnostics.push(
+            Diagnostic::warning(
+                "W0006",
+                format!("improperly nested {}: `{}` is closed before it, so it is kept as text", name, interrupter),
+                marker_span
+            ).with_note("inline styles close in the reverse order they were opened, as in `*bold /and italic/*`")
+        );
+        let mut artefacts = vec![Spanned::new(TextArtefact::Raw(self.slice(marker_span, marker_span)), marker_span)];
+        artefacts.extend(contents.0);
+        artefacts
+    }
+
+    /// Records a warning for a style or code span whose opening `marker` at `start`
+    /// is never closed on its line, and returns the marker as text. A lone `/` or `_`
+    /// is more likely part of a path or name than a mistake, so it isn't warned about.
+    fn unclosed(&mut self, start: Span, marker: &str, name: &str) -> Spanned<TextArtefact<'a>> {
+        let marker_span = Span { end: start.start + marker.len(), ..start };
+        if marker.chars().all(is_path_marker) {
+            return Spanned::new(TextArtefact::Raw(self.slice(marker_span, marker_span)), marker_span);
+        }
+        self.diagnostics.push(
+            Diagnostic::warning(
+                "W0002",
+                format!("unclosed {}: `{}` is never closed, so it is kept as text", name, marker),
+                marker_span
+            ).with_note(format!("write `\\{}` to mean a literal `{}`", marker, marker))
+        );
+        Spanned::new(TextArtefact::Raw(self.slice(marker_span, marker_span)), marker_span)
+    }
+
+    /// Parses a style delimited by `marker` on both sides. A style that is never
+    /// closed keeps its marker as text, followed by what it contained.
+    fn parse_style(&mut self, marker: &str, name: &str, style: Style<'a>) -> Vec<Spanned<TextArtefact<'a>>> {
+        let start = self.cursor();
+        self.eat(marker.len());
+        let (contents, end) = self.parse_nested(marker.chars().next().unwrap_or_default());
+        match end {
+            RunEnd::Closed => vec![Spanned::new(style(contents), self.span_from(start))],
+            RunEnd::Interrupted(interrupter) => self.interrupted(start, marker, name, interrupter, contents),
+            RunEnd::Eol => {
+                let mut artefacts = vec![self.unclosed(start, marker, name)];
+                artefacts.extend(contents.0);
+                artefacts
+            }
+        }
+    }
+
+    /// Reads the contents of a code span or key up to the first `marker` that ends a
+    /// word, consuming both markers. If there is no such marker, only the opening one
+    /// is consumed.
+    fn eat_verbatim(&mut self, marker: &str) -> Option<&'a str> {
+        self.eat(marker.len());
+        let rest = self.rest();
+        let closer = rest.match_indices(marker).map(|(index, _)| index).find(|&index| {
+            let prev = rest[..index].chars().next_back();
+            index > 0 && can_close(prev, rest[index + marker.len()..].chars().next())
+        })?;
+        let contents = self.eat(closer);
+        self.eat(marker.len());
+        Some(contents)
+    }
+
+    /// Parses a code span. Its contents are taken literally, so other markers inside
+    /// it don't start styles and it can't close the styles around it.
+    fn parse_code(&mut self) -> Vec<Spanned<TextArtefact<'a>>> {
+        let start = self.cursor();
+        match self.eat_verbatim("$") {
+            Some(contents) => vec![Spanned::new(TextArtefact::Code(Cow::Borrowed(contents)), self.span_from(start))],
+            // The rest of the line is parsed again as ordinary text
+            None => vec![self.unclosed(start, "$", "inline code")],
+        }
+    }
+
+    /// Parses a keyboard key such as `++Ctrl+C++`, which is taken literally like a code span.
+    fn parse_kbd(&mut self) -> Vec<Spanned<TextArtefact<'a>>> {
+        let start = self.cursor();
+        match self.eat_verbatim("++") {
+            Some(keys) => vec![Spanned::new(TextArtefact::Kbd(Cow::Borrowed(keys)), self.span_from(start))],
+            None => vec![self.unclosed(start, "++", "keyboard key")],
+        }
+    }
+
+    /// Parses inline math, `\(...\)`, checking that its LaTeX can be converted. Like
+    /// LaTeX, its contents are taken literally up to the first `\)`, wherever it is.
+    fn parse_math(&mut self) -> Vec<Spanned<TextArtefact<'a>>> {
+        let start = self.cursor();
+        self.eat(2);
+        let Some(end) = self.rest().find("\\)") else {
+            return vec![self.unclosed(start, "\\(", "inline math")];
+        };
+        let tex = self.eat(end);
+        self.eat(2);
+        if let Err(error) = math::parse(tex, false) {
+            let tex_start = Span { start: start.start + 2, column: start.column + 2, ..start };
+            self.diagnostics.push(math::diagnostic(tex, tex_start, error));
+        }
+        vec![Spanned::new(TextArtefact::Math(Cow::Borrowed(tex)), self.span_from(start))]
+    }
+
+    /// Parses an inline link starting at its leading `!`. A malformed link becomes
+    /// an error artefact holding the raw text consumed so far.
+    fn parse_link(&mut self) -> Vec<Spanned<TextArtefact<'a>>> {
+        let start = self.cursor();
+        self.bump();
+        self.bump();
+        let (contents, end) = self.parse_nested(']');
+        let error = match end {
+            RunEnd::Interrupted(interrupter) => {
+                return self.interrupted(start, "![", "inline link", interrupter, contents);
+            }
+            RunEnd::Eol => ParseError::UnexpectedEOL(self.cursor()),
+            RunEnd::Closed => {
+                let paren = self.cursor();
+                match self.peek() {
+                    Some('(') => {
+                        self.bump();
+                        let link_url = self.eat_until(')');
+                        match self.bump() {
+                            Some(_) => {
+                                return vec![
+                                    Spanned::new(
+                                        TextArtefact::Link(contents, Cow::Borrowed(link_url)),
+                                        self.span_from(start)
+                                    )
+                                ];
+                            }
+                            None => ParseError::UnexpectedEOL(self.cursor()),
+                        }
+                    }
+                    Some('[') => {
+                        self.bump();
+                        let label = self.eat_until(']');
+                        match self.bump() {
+                            Some(_) => {
+                                let link = TextArtefact::LinkReference(contents, Cow::Borrowed(label));
+                                return vec![Spanned::new(link, self.span_from(start))];
+                            }
+                            None => ParseError::UnexpectedEOL(self.cursor()),
+                        }
+                    }
+                    Some(ch) => {
+                        let found = Span { end: paren.start + ch.len_utf8(), ..paren };
+                        ParseError::UnexpectedChar(String::from("inline link"), '(', ch, found)
+                    }
+                    None => ParseError::UnexpectedEOL(paren),
+                }
+            }
+        };
+        self.diagnostics.push(Diagnostic::from(error).with_note(INLINE_LINK_NOTE));
+        self.error(start)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Technical prose whose markers sit inside words and must stay literal.
+    pub(crate) const TECHNICAL_PROSE: &[&str] = &[
+        "Build with cargo run --bin waygum -- src/main.rs -o out/index.html",
+        "Rename my_variable_name to MAX_BUFFER_SIZE and call __init__ or __del__.",
+        "The date is 2024/01/31 and the ratio is 3/4, i.e. about 0.75.",
+        "The regex a*b*c matches, and 2 * 3 * 4 = 24.",
+        "Install to C:/Program Files/app_name/bin or lib/x86_64-linux-gnu.",
+        "Prices went from 5$ to 10$ and $ 20 is too much.",
+        "Use either/or, and/or and read/write access.",
+        "Email the list about ticket_42/attachment_1.png",
+        "Both C++ and C# compile, if a == b or x==y then 2^3 = 8 and a+++b is legal.",
+        "Run ~/bin/x and ~/y~",
+        "Copy /usr/bin to /etc/ now",
+        "set _PRIVATE and __init__",
+        "x %APPDATA% y",
+        "Use %d and 50% more",
+    ];
+
+    /// Prose with bare urls and email addresses, which become links without any styles inside.
+    pub(crate) const AUTOLINKS: &[(&str, &str)] = &[
+        ("See https://example.com/docs/getting_started for details.", "https://example.com/docs/getting_started"),
+        ("Email first_last@example.org about ticket_42/attachment_1.png", "mailto:first_last@example.org"),
+        ("(see https://en.wikipedia.org/wiki/Rust_(language)).", "https://en.wikipedia.org/wiki/Rust_(language)"),
+        ("Browse http://example.com/a_b/c*d/, then log in.", "http://example.com/a_b/c*d/"),
+    ];
+
+    fn parse(text: &str) -> (Vec<Spanned<TextArtefact<'_>>>, Vec<Diagnostic>) {
+        let mut diagnostics = vec![];
+        let span = Span::new(10, 10 + text.len(), 3, 5);
+        let artefacts = InlineParser::new(text, span, &mut diagnostics).parse();
+        (artefacts, diagnostics)
+    }
+
+    #[test]
+    fn inline_spans_count_characters() {
+        let (artefacts, diagnostics) = parse("héllo *wörld* !");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("héllo ")),
+                TextArtefact::Bold(Text::from("wörld")),
+                TextArtefact::Raw(Cow::from(" !")),
+            ]
+        );
+        assert_eq!(artefacts[1].span, Span::new(17, 25, 3, 11));
+        assert_eq!(artefacts[2].span, Span::new(25, 27, 3, 18));
+    }
+
+    #[test]
+    fn inline_malformed_link() {
+        let (artefacts, diagnostics) = parse("see ![docs]x here");
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("see ")),
+                TextArtefact::Error(Cow::from("![docs]")),
+                TextArtefact::Raw(Cow::from("x here")),
+            ]
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "E0002");
+        assert_eq!(diagnostics[0].span, Span::new(21, 22, 3, 16));
+    }
+
+    #[test]
+    fn inline_long_paragraph() {
+        let text = "word *bold* ".repeat(20_000);
+        let (artefacts, diagnostics) = parse(&text);
+        assert!(diagnostics.is_empty());
+        assert_eq!(artefacts.len(), 40_001);
+    }
+
+    fn raw(text: &str) -> Spanned<TextArtefact<'_>> {
+        TextArtefact::Raw(Cow::from(text)).into()
+    }
+
+    #[test]
+    fn inline_nested_styles() {
+        let (artefacts, diagnostics) = parse("*bold /and ~both~/* and ![see _the_ docs](a*b)");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Bold(
+                    Text(
+                        vec![
+                            raw("bold "),
+                            TextArtefact::Italics(
+                                Text(vec![raw("and "), TextArtefact::Strikethrough(Text::from("both")).into()])
+                            ).into(),
+                        ]
+                    )
+                ),
+                TextArtefact::Raw(Cow::from(" and ")),
+                TextArtefact::Link(
+                    Text(vec![raw("see "), TextArtefact::Underline(Text::from("the")).into(), raw(" docs")]),
+                    Cow::from("a*b")
+                ),
+            ]
+        );
+        let TextArtefact::Bold(Text(bold)) = &artefacts[0].node else { unreachable!() };
+        assert_eq!(bold[1].span, Span::new(16, 28, 3, 11));
+    }
+
+    #[test]
+    fn inline_link_inside_style() {
+        let (artefacts, diagnostics) = parse("_![a](b)_ $*not /bold*$");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Underline(Text(vec![TextArtefact::Link(Text::from("a"), Cow::from("b")).into()])),
+                TextArtefact::Raw(Cow::from(" ")),
+                TextArtefact::Code(Cow::from("*not /bold*")),
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_interleaved_styles() {
+        let (artefacts, diagnostics) = parse("*bold /italic* rest");
+        assert_eq!(
+            artefacts,
+            vec![TextArtefact::Bold(Text::from("bold /italic")), TextArtefact::Raw(Cow::from(" rest"))]
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "W0006");
+        assert_eq!(diagnostics[0].message, "improperly nested italic text: `*` is closed before it, so it is kept as text");
+        assert_eq!(diagnostics[0].span, Span::new(16, 17, 3, 11));
+
+        let (artefacts, diagnostics) = parse("~![a~](b)");
+        assert_eq!(
+            artefacts,
+            vec![TextArtefact::Strikethrough(Text::from("![a")), TextArtefact::Raw(Cow::from("](b)"))]
+        );
+        assert_eq!(diagnostics[0].message, "improperly nested inline link: `~` is closed before it, so it is kept as text");
+    }
+
+    #[test]
+    fn inline_unclosed_markers_are_text() {
+        let (artefacts, diagnostics) = parse("a *b /c $d e");
+        assert_eq!(artefacts, vec![TextArtefact::Raw(Cow::from("a *b /c $d e"))]);
+        assert_eq!(artefacts[0].span, Span::new(10, 22, 3, 5));
+        let codes = diagnostics.iter().map(|diagnostic| (diagnostic.code, diagnostic.span.column)).collect::<Vec<_>>();
+        assert_eq!(codes, vec![("W0002", 13), ("W0002", 7)]);
+        assert_eq!(diagnostics[1].message, "unclosed bold text: `*` is never closed, so it is kept as text");
+        assert_eq!(diagnostics[1].notes, vec!["write `\\*` to mean a literal `*`"]);
+
+        let (artefacts, diagnostics) = parse("*a _b_ $c /d/");
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("*a ")),
+                TextArtefact::Underline(Text::from("b")),
+                TextArtefact::Raw(Cow::from(" $c ")),
+                TextArtefact::Italics(Text::from("d")),
+            ]
+        );
+        assert_eq!(diagnostics.len(), 2);
+        let (artefacts, diagnostics) = parse("Install to ~user/bin or /usr/local/bin");
+        assert_eq!(artefacts, vec![TextArtefact::Raw(Cow::from("Install to ~user/bin or /usr/local/bin"))]);
+        assert!(diagnostics.iter().all(|diagnostic| diagnostic.code == "W0002"));
+    }
+
+    #[test]
+    fn inline_escapes() {
+        let (artefacts, diagnostics) = parse(r"\*not bold\* C:\Users \\ \![x](y) *a\*b* $c\*d$");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from(r"*not bold* C:\Users \ ![x](y) ")),
+                TextArtefact::Bold(Text::from("a*b")),
+                TextArtefact::Raw(Cow::from(" ")),
+                TextArtefact::Code(Cow::from(r"c\*d")),
+            ]
+        );
+        assert_eq!(artefacts[0].span, Span::new(10, 44, 3, 5));
+        let (artefacts, _) = parse("plain");
+        assert!(matches!(&artefacts[0].node, TextArtefact::Raw(Cow::Borrowed("plain"))));
+    }
+
+    #[test]
+    fn inline_technical_prose_stays_literal() {
+        for line in TECHNICAL_PROSE {
+            let (artefacts, diagnostics) = parse(line);
+            assert!(diagnostics.is_empty(), "{}: {:?}", line, diagnostics);
+            assert_eq!(artefacts, vec![TextArtefact::Raw(Cow::from(*line))], "{}", line);
+        }
+    }
+
+    #[test]
+    fn inline_markers_at_word_boundaries() {
+        let (artefacts, diagnostics) = parse("(*bold*), */both/*. _a_b_ -~gone~- $x $y$ 1 * 2*");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("(")),
+                TextArtefact::Bold(Text::from("bold")),
+                TextArtefact::Raw(Cow::from("), ")),
+                TextArtefact::Bold(Text(vec![TextArtefact::Italics(Text::from("both")).into()])),
+                TextArtefact::Raw(Cow::from(". _a_b_ -")),
+                TextArtefact::Strikethrough(Text::from("gone")),
+                TextArtefact::Raw(Cow::from("- ")),
+                TextArtefact::Code(Cow::from("x $y")),
+                TextArtefact::Raw(Cow::from(" 1 * 2*")),
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_math() {
+        let (artefacts, diagnostics) = parse(r"Euler: \(e^{i\pi} + 1 = 0\), 50% and \\(x\) or \(open");
+        let codes = diagnostics.iter().map(|diagnostic| (diagnostic.code, diagnostic.span.column)).collect::<Vec<_>>();
+        assert_eq!(codes, vec![("W0002", 52)]);
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("Euler: ")),
+                TextArtefact::Math(Cow::from(r"e^{i\pi} + 1 = 0")),
+                TextArtefact::Raw(Cow::from(r", 50% and \(x) or \(open")),
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_footnote_references() {
+        let (artefacts, diagnostics) = parse("A *note*[^1], [^not a label] \\[^2] $[^3]$");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("A ")),
+                TextArtefact::Bold(Text::from("note")),
+                TextArtefact::FootnoteReference(Cow::from("1")),
+                TextArtefact::Raw(Cow::from(", [^not a label] [^2] ")),
+                TextArtefact::Code(Cow::from("[^3]")),
+            ]
+        );
+        assert_eq!(artefacts[2].span, Span::new(18, 22, 3, 13));
+    }
+
+    #[test]
+    fn inline_heading_references() {
+        let (artefacts, diagnostics) = parse("See *[@intro]*, [@not a label] and \\[@setup]");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("See ")),
+                TextArtefact::Bold(Text(vec![TextArtefact::HeadingReference(Cow::from("intro")).into()])),
+                TextArtefact::Raw(Cow::from(", [@not a label] and [@setup]")),
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_superscript_subscript_mark_and_kbd() {
+        let (artefacts, diagnostics) = parse("x^2^ H,,2,,O a^b,,c,,^ ==*hot* take== ++Ctrl+C++ ==open");
+        let codes = diagnostics.iter().map(|diagnostic| (diagnostic.code, diagnostic.span.column)).collect::<Vec<_>>();
+        assert_eq!(codes, vec![("W0002", 54)]);
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("x")),
+                TextArtefact::Superscript(Text::from("2")),
+                TextArtefact::Raw(Cow::from(" H")),
+                TextArtefact::Subscript(Text::from("2")),
+                TextArtefact::Raw(Cow::from("O a")),
+                TextArtefact::Superscript(
+                    Text(vec![TextArtefact::Raw(Cow::from("b")).into(), TextArtefact::Subscript(Text::from("c")).into()])
+                ),
+                TextArtefact::Raw(Cow::from(" ")),
+                TextArtefact::Mark(
+                    Text(vec![TextArtefact::Bold(Text::from("hot")).into(), TextArtefact::Raw(Cow::from(" take")).into()])
+                ),
+                TextArtefact::Raw(Cow::from(" ")),
+                TextArtefact::Kbd(Cow::from("Ctrl+C")),
+                TextArtefact::Raw(Cow::from(" ==open")),
+            ]
+        );
+        let (artefacts, diagnostics) = parse("_{braced}_ and 2^3 or a,, b");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![TextArtefact::Underline(Text::from("{braced}")), TextArtefact::Raw(Cow::from(" and 2^3 or a,, b"))]
+        );
+    }
+
+    #[test]
+    fn inline_autolinks() {
+        for (line, url) in AUTOLINKS {
+            let (artefacts, diagnostics) = parse(line);
+            assert!(diagnostics.is_empty(), "{}: {:?}", line, diagnostics);
+            let links = artefacts
+                .iter()
+                .filter_map(|artefact| match &artefact.node {
+                    TextArtefact::Link(text, url) => Some((text, url.as_ref())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(links.len(), 1, "{}", line);
+            assert_eq!(links[0].1, *url, "{}", line);
+            assert!(matches!(&links[0].0.0[..], [Spanned { node: TextArtefact::Raw(_), .. }]), "{}", line);
+        }
+        let (artefacts, _) = parse("*https://x.com/a* not@an-email, ![https://x.com](u) and a.https://x.com");
+        let link = TextArtefact::Link(Text::from("https://x.com/a"), Cow::from("https://x.com/a"));
+        assert_eq!(artefacts[0], TextArtefact::Bold(Text(vec![link.into()])));
+        let urls = artefacts
+            .iter()
+            .filter_map(|artefact| match &artefact.node {
+                TextArtefact::Link(_, url) => Some(url.as_ref()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(urls, vec!["u"]);
+    }
+
+    #[test]
+    fn inline_images() {
+        let (artefacts, diagnostics) = parse("A #[badge](ci.svg) and \\#[not](x) or #[broken]");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::Raw(Cow::from("A ")),
+                TextArtefact::Image(Cow::from("badge"), Cow::from("ci.svg")),
+                TextArtefact::Raw(Cow::from(" and #[not](x) or #[broken]")),
+            ]
+        );
+        assert_eq!(artefacts[1].span, Span::new(12, 28, 3, 7));
+    }
+
+    #[test]
+    fn inline_link_references() {
+        let (artefacts, diagnostics) = parse("![*a*][x] ![b][y");
+        assert_eq!(
+            artefacts,
+            vec![
+                TextArtefact::LinkReference(
+                    Text(vec![TextArtefact::Bold(Text::from("a")).into()]),
+                    Cow::from("x")
+                ),
+                TextArtefact::Raw(Cow::from(" ")),
+                TextArtefact::Error(Cow::from("![b][y")),
+            ]
+        );
+        let codes = diagnostics.iter().map(|diagnostic| diagnostic.code).collect::<Vec<_>>();
+        assert_eq!(codes, vec!["E0003"]);
+    }
+}
+
@@ -1,113 +1,230 @@
-use lazy_static::lazy_static;
-use fancy_regex::Regex as FancyRegex;
-
-lazy_static! {
-    static ref SECTION: FancyRegex = FancyRegex::new(r#"^@(?!@)\s*(\S.*)$"#).unwrap();
-    static ref SUBSECTION: FancyRegex = FancyRegex::new(r#"^@@(?!@)\s*(\S.*)$"#).unwrap();
-    static ref SUBSUBSECTION: FancyRegex = FancyRegex::new(r#"^@@@(?!@)\s*(\S.*)$"#).unwrap();
-    static ref LIST: FancyRegex = FancyRegex::new(r#"(^-+)\s*(\S.*)$"#).unwrap();
-    static ref QUOTE: FancyRegex = FancyRegex::new(r#"^>(?!>)\s*(\S.*)$"#).unwrap();
-    static ref TABLE: FancyRegex = FancyRegex::new(r#"^\|(.*)\|$"#).unwrap();
-    static ref IMAGE: FancyRegex = FancyRegex::new(r#"^#\[(.*)\]\((.*)\)$"#).unwrap();
-    static ref CODEBLOCK: FancyRegex = FancyRegex::new(r"^\$\$\$").unwrap();
-    static ref TEXT: FancyRegex = FancyRegex::new(r#"^(.*)$"#).unwrap();
-    static ref BUTTON: FancyRegex = FancyRegex::new(r#"^#!\[([^\]]*)\]\{([^\}]*)\}$"#).unwrap();
-}
-
-#[derive(Debug, PartialEq)]
-pub enum Token {
-    Section(String),
-    Subsection(String),
-    Subsubsection(String),
+use std::borrow::Cow;
+use serde::{ Deserialize, Serialize };
+use super::cst::{ self, SyntaxKind, SyntaxNode };
+use super::diagnostic::Diagnostic;
+use super::span::{ Span, Spanned };
+
+/// A single line-level token. The span attached by `Lexer::tokenize` covers the
+/// token's content: the text after the marker for headings, list items, quotes,
+/// footnotes and text, the lines between the fences for codeblocks and display equations,
+/// and the whole line for tables, images, buttons and link definitions.
+///
+/// Text borrows from the lexer's input wherever it appears verbatim in the source.
+/// Tokens are derived from the lossless syntax tree in `cst`, dropping blank lines,
+/// whitespace around the content and the markers themselves.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Token<'a> {
+    /// A heading's title and its optional label, as in `@ Title {#label}`.
+    Section(Cow<'a, str>, Option<Cow<'a, str>>),
+    Subsection(Cow<'a, str>, Option<Cow<'a, str>>),
+    Subsubsection(Cow<'a, str>, Option<Cow<'a, str>>),
     List {
         depth: usize,
-        text: String,
+        text: Cow<'a, str>,
     },
-    Quote(String),
-    Table(Vec<String>),
+    Quote(Cow<'a, str>),
+    Table(Vec<Spanned<Cow<'a, str>>>),
     Image {
-        caption: String,
-        path: String,
+        caption: Spanned<Cow<'a, str>>,
+        path: Cow<'a, str>,
     },
-    Codeblock(String),
+    Codeblock(Cow<'a, str>),
     Button {
-        text: String,
-        link: String,
+        text: Spanned<Cow<'a, str>>,
+        link: Cow<'a, str>,
+    },
+    Text(Cow<'a, str>),
+    /// The LaTeX source of a display equation.
+    Equation(Cow<'a, str>),
+    /// A footnote definition.
+    Footnote {
+        label: Cow<'a, str>,
+        text: Cow<'a, str>,
+    },
+    /// A link definition, for reference links such as `![text][label]`.
+    LinkDefinition {
+        label: Cow<'a, str>,
+        url: Cow<'a, str>,
+        title: Option<Cow<'a, str>>,
     },
-    Text(String),
-	// TODO: Add Latex Support
     Newline,
+    Error(Cow<'a, str>),
     EOF,
 }
 
-pub struct Lexer {
-    input: String,
+impl Token<'_> {
+    /// A short human readable name for the kind of token, used in diagnostics.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Token::Section(..) => "a section heading",
+            Token::Subsection(..) => "a subsection heading",
+            Token::Subsubsection(..) => "a subsubsection heading",
+            Token::List { .. } => "a list item",
+            Token::Quote(_) => "a quote",
+            Token::Table(_) => "a table row",
+            Token::Image { .. } => "an image",
+            Token::Codeblock(_) => "a codeblock",
+            Token::Button { .. } => "a button",
+            Token::Text(_) => "text",
+            Token::Equation(_) => "a display equation",
+            Token::Footnote { .. } => "a footnote definition",
+            Token::LinkDefinition { .. } => "a link definition",
+            Token::Newline => "a blank line",
+            Token::Error(_) => "a malformed line",
+            Token::EOF => "the end of the file",
+        }
+    }
+
+    /// Copies any borrowed text so the token no longer depends on the input.
+    pub fn into_owned(self) -> Token<'static> {
+        fn owned(text: Cow<str>) -> Cow<'static, str> {
+            Cow::Owned(text.into_owned())
+        }
+        match self {
+            Token::Section(title, label) => Token::Section(owned(title), label.map(owned)),
+            Token::Subsection(title, label) => Token::Subsection(owned(title), label.map(owned)),
+            Token::Subsubsection(title, label) => Token::Subsubsection(owned(title), label.map(owned)),
+            Token::List { depth, text } => Token::List { depth, text: owned(text) },
+            Token::Quote(text) => Token::Quote(owned(text)),
+            Token::Table(columns) => Token::Table(columns.into_iter().map(|column| column.map(owned)).collect()),
+            Token::Image { caption, path } => Token::Image { caption: caption.map(owned), path: owned(path) },
+            Token::Codeblock(code) => Token::Codeblock(owned(code)),
+            Token::Button { text, link } => Token::Button { text: text.map(owned), link: owned(link) },
+            Token::Text(text) => Token::Text(owned(text)),
+            Token::Equation(tex) => Token::Equation(owned(tex)),
+            Token::Footnote { label, text } => Token::Footnote { label: owned(label), text: owned(text) },
+            Token::LinkDefinition { label, url, title } =>
+                Token::LinkDefinition { label: owned(label), url: owned(url), title: title.map(owned) },
+            Token::Newline => Token::Newline,
+            Token::Error(text) => Token::Error(owned(text)),
+            Token::EOF => Token::EOF,
+        }
+    }
+}
+
+pub struct Lexer<'a> {
+    input: &'a str,
+}
+
+/// Returns a node's token of the given kind as borrowed text, or empty text just
+/// after the node's content if the node has none.
+fn token_text<'a>(node: &SyntaxNode<'a>, kind: SyntaxKind) -> Spanned<Cow<'a, str>> {
+    match node.token(kind) {
+        Some(token) => Spanned::new(Cow::Borrowed(token.text), token.span),
+        None => Spanned::new(Cow::Borrowed(""), node.content_end()),
+    }
 }
 
-impl Lexer {
-    pub fn new(input: String) -> Self {
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
         Self { input }
     }
 
-    pub fn tokenize(&self) -> Result<Vec<Token>, fancy_regex::Error> {
-        let mut tokens = Vec::new();
-        let mut lines = self.input.lines();
-        while let Some(line) = lines.next() {
-            if let Some(captures) = SECTION.captures(line)? {
-                tokens.push(Token::Section(captures.get(1).unwrap().as_str().to_string()));
-            } else if let Some(captures) = SUBSECTION.captures(line)? {
-                tokens.push(Token::Subsection(captures.get(1).unwrap().as_str().to_string()));
-            } else if let Some(captures) = SUBSUBSECTION.captures(line)? {
-                tokens.push(Token::Subsubsection(captures.get(1).unwrap().as_str().to_string()));
-            } else if let Some(captures) = LIST.captures(line)? {
-                let depth = captures.get(1).unwrap().as_str().len();
-                tokens.push(Token::List {
-                    depth,
-                    text: captures.get(2).unwrap().as_str().to_string(),
-                });
-            } else if let Some(captures) = QUOTE.captures(line)? {
-                tokens.push(Token::Quote(captures.get(1).unwrap().as_str().to_string()));
-            } else if let Some(captures) = TABLE.captures(line)? {
-                let mut line = captures.get(1).unwrap().as_str();
-                let column_items = line
-                    .split('|')
-                    .collect::<Vec<&str>>()
-                    .into_iter()
-                    .map(|s| s.trim().to_string())
-                    .collect::<Vec<String>>();
-                tokens.push(Token::Table(column_items));
-            } else if let Some(captures) = IMAGE.captures(line)? {
-                tokens.push(Token::Image {
-                    caption: captures.get(1).unwrap().as_str().to_string(),
-                    path: captures.get(2).unwrap().as_str().to_string(),
-                });
-            } else if let Some(_) = CODEBLOCK.captures(line)? {
-                let mut codeblock = String::new();
-                while let Some(line) = lines.next() {
-                    if line == "$$$" {
-                        break;
-                    }
-                    codeblock.push_str(line);
-                    codeblock.push_str("\n");
-                }
-                tokens.push(Token::Codeblock(codeblock));
-            } else if let Some(captures) = BUTTON.captures(line)? {
-                tokens.push(Token::Button {
-                    text: captures.get(1).unwrap().as_str().to_string(),
-                    link: captures.get(2).unwrap().as_str().to_string(),
-                });
-            } else if let Some(captures) = TEXT.captures(line)? {
-                let text = captures.get(1).unwrap().as_str().trim().to_string();
-                if text.is_empty() {
-                    tokens.push(Token::Newline);
+    /// Builds the lossless syntax tree the tokens are derived from.
+    pub fn syntax_tree(&self) -> (SyntaxNode<'a>, Vec<Diagnostic>) {
+        cst::parse(self.input)
+    }
+
+    pub fn tokenize(&self) -> Vec<Spanned<Token<'a>>> {
+        self.tokenize_with_diagnostics().0
+    }
+
+    /// Tokenizes the input, turning malformed lines into `Token::Error` and
+    /// reporting them alongside the tokens instead of giving up.
+    pub fn tokenize_with_diagnostics(&self) -> (Vec<Spanned<Token<'a>>>, Vec<Diagnostic>) {
+        let (tree, diagnostics) = self.syntax_tree();
+        let mut tokens = tree.nodes().filter_map(|node| self.lower(node)).collect::<Vec<_>>();
+        let end = self.input.len();
+        let last_line_start = self.input.rfind('\n').map_or(0, |index| index + 1);
+        tokens.push(
+            Spanned::new(
+                Token::EOF,
+                Span::new(
+                    end,
+                    end,
+                    self.input.matches('\n').count() + 1,
+                    self.input[last_line_start..].chars().count() + 1
+                )
+            )
+        );
+        (tokens, diagnostics)
+    }
+
+    /// Derives the token for a line of the syntax tree. Blank lines have none.
+    pub(crate) fn lower(&self, node: &SyntaxNode<'a>) -> Option<Spanned<Token<'a>>> {
+        let text = || token_text(node, SyntaxKind::Text);
+        let label = || node.token(SyntaxKind::Label).map(|label| Cow::Borrowed(label.text));
+        let token = match node.kind {
+            SyntaxKind::Section => text().map(|title| Token::Section(title, label())),
+            SyntaxKind::Subsection => text().map(|title| Token::Subsection(title, label())),
+            SyntaxKind::Subsubsection => text().map(|title| Token::Subsubsection(title, label())),
+            SyntaxKind::ListItem => {
+                let depth = node.token(SyntaxKind::Marker).map_or(0, |marker| marker.text.len());
+                text().map(|text| Token::List { depth, text })
+            }
+            SyntaxKind::Quote => text().map(Token::Quote),
+            SyntaxKind::TableRow => {
+                let columns = node.nodes().map(|cell| token_text(cell, SyntaxKind::Text)).collect();
+                Spanned::new(Token::Table(columns), node.content_span())
+            }
+            SyntaxKind::Image => {
+                let image = Token::Image {
+                    caption: text(),
+                    path: token_text(node, SyntaxKind::Url).node,
+                };
+                Spanned::new(image, node.content_span())
+            }
+            SyntaxKind::Button => {
+                let button = Token::Button {
+                    text: text(),
+                    link: token_text(node, SyntaxKind::Url).node,
+                };
+                Spanned::new(button, node.content_span())
+            }
+            SyntaxKind::Codeblock | SyntaxKind::Equation => {
+                let mut lines = node.nodes().filter(|line| line.kind == SyntaxKind::CodeLine);
+                let span = match (lines.next(), lines.last()) {
+                    (Some(first), last) => first.content_span().to(last.unwrap_or(first).content_span()),
+                    (None, _) => node.nodes().next()?.content_end(),
+                };
+                let contents = self.codeblock(span);
+                match node.kind {
+                    SyntaxKind::Equation => Spanned::new(Token::Equation(contents), span),
+                    _ => Spanned::new(Token::Codeblock(contents), span),
                 }
-                tokens.push(Token::Text(text));
-            } else {
-                tokens.push(Token::Newline);
             }
+            SyntaxKind::Footnote => {
+                let label = token_text(node, SyntaxKind::Label).node;
+                text().map(|text| Token::Footnote { label, text })
+            }
+            SyntaxKind::LinkDefinition => {
+                let definition = Token::LinkDefinition {
+                    label: token_text(node, SyntaxKind::Label).node,
+                    url: token_text(node, SyntaxKind::Url).node,
+                    title: node.token(SyntaxKind::Text).map(|title| Cow::Borrowed(title.text)),
+                };
+                Spanned::new(definition, node.content_span())
+            }
+            SyntaxKind::TextLine => text().map(Token::Text),
+            SyntaxKind::ErrorLine => Spanned::new(Token::Error(text().node), node.content_span()),
+            _ => {
+                return None;
+            }
+        };
+        Some(token)
+    }
+
+    /// Returns the contents of a codeblock or equation covering `span`, each line
+    /// followed by a newline. Borrows the source unless its line endings have to be rewritten.
+    fn codeblock(&self, span: Span) -> Cow<'a, str> {
+        let code = &self.input[span.start..span.end];
+        if code.is_empty() {
+            return Cow::Borrowed("");
+        }
+        match self.input[span.end..].strip_prefix('\n') {
+            Some(_) if !code.contains('\r') => Cow::Borrowed(&self.input[span.start..span.end + 1]),
+            _ => Cow::Owned(code.lines().flat_map(|line| [line, "\n"]).collect()),
         }
-        tokens.push(Token::EOF);
-        Ok(tokens)
     }
 }
 
@@ -115,453 +232,589 @@ impl Lexer {
 mod tests {
     use super::*;
 
+    const BASIC_ALL: &str = "@ Heading\n\nText\n\n> Quote\n\n| Col1 | Col2 | Col3 |\n\n#[Caption](path/to/image)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$";
+
+    const BASIC_ALL_WITH_NEWLINE: &str = "@ Heading\n\nText\n\n> Quote\n\n| Col1 | Col2 | Col3 |\n\n#[Caption](path/to/image)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$\n";
+
+    const COMPLEX_ALL: &str = "@ Heading\n\nText\n\n> Quote\n\n| Col1 | Col2 | Col3 |\n\n#[Caption](path/to/image)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$\n\n@@ Heading 2\n\nText 2\n\n> Quote 2\n\n| Col1 | Col2 | Col3 |\n\n#[Caption 2](path/to/image/2)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$";
+
+    const COMPLEX_ALL_WITH_NEWLINE: &str = "@ Heading\n\nText\n\n> Quote\n\n| Col1 | Col2 | Col3 |\n\n#[Caption](path/to/image)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$\n\n@@ Heading 2\n\nText 2\n\n> Quote 2\n\n| Col1 | Col2 | Col3 |\n\n#[Caption 2](path/to/image/2)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$\n";
+
+    const MULTILINE_TABLE: &str = "@ Heading\n\n| Col1 | Col2 | Col3 |\n| Col4 | Col5 | Col6 |\n";
+
+    const SAMPLE_DOCUMENT: &str = r#"@ Learning C++
+/ Learning Objectives: Learn the basics of C++ /
+
+@@ History of C++
+C++ was developed by Bjarne Stroustrup at Bell Labs in the 1980s. It was originally called C with Classes, but was renamed C++ in 1983. C++ is a superset of C, which means that all valid C programs are also valid C++ programs. C++ is an object-oriented language, which means that it is designed to allow programmers to create their own data types. C++ is a compiled language, which means that it must be translated into machine code before it can be run. C++ is a statically typed language, which means that the type of a variable must be known at compile time. C++ is a strongly typed language, which means that the type of a variable cannot be changed. C++ is a case-sensitive language, which means that the names of variables, functions, and classes are case-sensitive. C++ is a free-form language, which means that the programmer can choose how to format the code. C++ is a multi-paradigm language, which means that it supports multiple programming styles. C++ is a general-purpose language, which means that it can be used to write any type of program.
+
+> C++ was developed by Bjarne Stroustrup
+
+!bjarne-stroustrup.jpg!Bjarne Stroustrup at the 2012 C++Now conference.
+
+@@ Some Characterisitcs of C++
+- Object oriented
+- Compiled
+-- Translated into machine code
+- Statically typed
+- Strongly typed
+- Case-sensitive
+- Free-form
+- Multi-paradigm
+- General-purpose
+
+@@ Sample C++ Program
+$$$
+#include <iostream>
+
+int main() {
+	std::cout << "Hello, World!" << std::endl;
+	return 0;
+}
+$$$
+
+/ This program prints "Hello, World!" to the screen. /
+
+@@ Compiling C++ Programs
+C++ programs must be compiled before they can be run. The compiler translates the C++ code into machine code. The compiler is usually called g++ on Linux and Mac OS X, and cl on Windows. The compiler is usually invoked with the following command:
+$$$
+g++ -o program program.cpp
+$$$
+/ This command compiles the C++ program program.cpp and creates an executable program. /
+
+@@ Running C++ Programs
+C++ programs are run by executing the executable file that was created by the compiler. The executable file is usually called a.out on Linux and Mac OS X, and program.exe on Windows. The executable file is usually invoked with the following command:
+$$$
+./a.out
+$$$
+/ This command runs the C++ program a.out. /
+
+@@ Keywords
+C++ has a number of keywords that are reserved and cannot be used as variable names. The following is a list of the most commonly used keywords:
+| name | description |
+| int | integer |
+| float | floating-point number |
+| double | double-precision floating-point number |
+| char | character |
+| bool | boolean |
+| void | no return value |
+| true | boolean true |
+| false | boolean false |
+| if | if statement |
+| else | else statement |
+| while | while loop |
+| for | for loop |
+| break | break statement |
+| continue | continue statement |
+| return | return statement |"#;
+
     #[test]
-    fn section() -> Result<(), fancy_regex::Error> {
-        let input = String::from("@ Section");
+    fn section() {
+        let input = "@ Section";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Section(String::from("Section")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Section(Cow::from("Section"), None), Token::EOF]);
     }
 
     #[test]
-    fn subsection() -> Result<(), fancy_regex::Error> {
-        let input = String::from("@@ Subsection");
+    fn subsection() {
+        let input = "@@ Subsection";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Subsection(String::from("Subsection")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Subsection(Cow::from("Subsection"), None), Token::EOF]);
     }
 
     #[test]
-    fn subsubsection() -> Result<(), fancy_regex::Error> {
-        let input = String::from("@@@ Subsubsection");
+    fn subsubsection() {
+        let input = "@@@ Subsubsection";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Subsubsection(String::from("Subsubsection")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Subsubsection(Cow::from("Subsubsection"), None), Token::EOF]);
+    }
+
+    #[test]
+    fn heading_label() {
+        let tokens = Lexer::new("@@ Getting started {#start}").tokenize();
+        assert_eq!(tokens, vec![Token::Subsection(Cow::from("Getting started"), Some(Cow::from("start"))), Token::EOF]);
     }
 
     #[test]
-    fn list() -> Result<(), fancy_regex::Error> {
-        let input = String::from("- List");
+    fn list() {
+        let input = "- List";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::List { depth: 1, text: String::from("List") }, Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::List { depth: 1, text: Cow::from("List") }, Token::EOF]);
     }
 
     #[test]
-    fn quote() -> Result<(), fancy_regex::Error> {
-        let input = String::from("> Quote");
+    fn quote() {
+        let input = "> Quote";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Quote(String::from("Quote")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Quote(Cow::from("Quote")), Token::EOF]);
     }
 
     #[test]
-    fn table() -> Result<(), fancy_regex::Error> {
-        let input = String::from("| Col1 | Col2 | Col3 |");
+    fn table() {
+        let input = "| Col1 | Col2 | Col3 |";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
             vec![
                 Token::Table(
-                    vec![String::from("Col1"), String::from("Col2"), String::from("Col3")]
+                    vec![
+                        Cow::from("Col1").into(),
+                        Cow::from("Col2").into(),
+                        Cow::from("Col3").into()
+                    ]
                 ),
                 Token::EOF
             ]
         );
-        Ok(())
     }
 
     #[test]
-    fn image() -> Result<(), fancy_regex::Error> {
-        let input = String::from("#[Caption](path/to/image)");
+    fn image() {
+        let input = "#[Caption](path/to/image)";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
             vec![
                 Token::Image {
-                    caption: String::from("Caption"),
-                    path: String::from("path/to/image"),
+                    caption: Cow::from("Caption").into(),
+                    path: Cow::from("path/to/image"),
                 },
                 Token::EOF
             ]
         );
-        Ok(())
     }
 
     #[test]
-    fn codeblock() -> Result<(), fancy_regex::Error> {
-        let input = String::from(
-            "$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$"
-        );
+    fn codeblock() {
+        let input = "$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
             vec![
                 Token::Codeblock(
-                    String::from(
+                    Cow::from(
                         "#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n"
                     )
                 ),
                 Token::EOF
             ]
         );
-        Ok(())
     }
 
     #[test]
-    fn text() -> Result<(), fancy_regex::Error> {
-        let input = String::from("Text");
+    fn text() {
+        let input = "Text";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Text(String::from("Text")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Text(Cow::from("Text")), Token::EOF]);
     }
 
     #[test]
-    fn text_with_newline() -> Result<(), fancy_regex::Error> {
-        let input = String::from("Text\n");
+    fn text_with_newline() {
+        let input = "Text\n";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Text(String::from("Text")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Text(Cow::from("Text")), Token::EOF]);
     }
 
     #[test]
-    fn text_with_newline_and_space() -> Result<(), fancy_regex::Error> {
-        let input = String::from("Text\n ");
+    fn text_with_newline_and_space() {
+        let input = "Text\n ";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Text(String::from("Text")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Text(Cow::from("Text")), Token::EOF]);
     }
 
     #[test]
-    fn text_with_newline_and_tab() -> Result<(), fancy_regex::Error> {
-        let input = String::from("Text\n\t");
+    fn text_with_newline_and_tab() {
+        let input = "Text\n\t";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Text(String::from("Text")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Text(Cow::from("Text")), Token::EOF]);
     }
 
     #[test]
-    fn text_with_newline_and_space_and_tab() -> Result<(), fancy_regex::Error> {
-        let input = String::from("Text\n \t");
+    fn text_with_newline_and_space_and_tab() {
+        let input = "Text\n \t";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Text(String::from("Text")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Text(Cow::from("Text")), Token::EOF]);
     }
 
     #[test]
-    fn text_with_newline_and_tab_and_space() -> Result<(), fancy_regex::Error> {
-        let input = String::from("Text\n\t ");
+    fn text_with_newline_and_tab_and_space() {
+        let input = "Text\n\t ";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        assert_eq!(tokens, vec![Token::Text(String::from("Text")), Token::EOF]);
-        Ok(())
+        let tokens = lexer.tokenize();
+        assert_eq!(tokens, vec![Token::Text(Cow::from("Text")), Token::EOF]);
     }
 
     #[test]
-    fn text_with_newline_and_tab_and_space_and_text() -> Result<(), fancy_regex::Error> {
-        let input = String::from("Text\n\t Text");
+    fn text_with_newline_and_tab_and_space_and_text() {
+        let input = "Text\n\t Text";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
-            vec![Token::Text(String::from("Text")), Token::Text(String::from("Text")), Token::EOF]
+            vec![Token::Text(Cow::from("Text")), Token::Text(Cow::from("Text")), Token::EOF]
         );
-        Ok(())
     }
 
     #[test]
-    fn text_with_newline_and_tab_and_space_and_text_and_newline() -> Result<
-        (),
-        fancy_regex::Error
-    > {
-        let input = String::from("Text\n\t Text\n");
+    fn text_with_newline_and_tab_and_space_and_text_and_newline() {
+        let input = "Text\n\t Text\n";
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
-            vec![Token::Text(String::from("Text")), Token::Text(String::from("Text")), Token::EOF]
+            vec![Token::Text(Cow::from("Text")), Token::Text(Cow::from("Text")), Token::EOF]
         );
-        Ok(())
     }
 
     #[test]
-    fn basic_all() -> Result<(), fancy_regex::Error> {
-        let input = String::from(
-            "@ Heading\n\nText\n\n> Quote\n\n| Col1 | Col2 | Col3 |\n\n#[Caption](path/to/image)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$"
-        );
+    fn basic_all() {
+        let input = BASIC_ALL;
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
             vec![
-                Token::Section(String::from("Heading")),
-                Token::Text(String::from("Text")),
-                Token::Quote(String::from("Quote")),
+                Token::Section(Cow::from("Heading"), None),
+                Token::Text(Cow::from("Text")),
+                Token::Quote(Cow::from("Quote")),
                 Token::Table(
-                    vec![String::from("Col1"), String::from("Col2"), String::from("Col3")]
+                    vec![
+                        Cow::from("Col1").into(),
+                        Cow::from("Col2").into(),
+                        Cow::from("Col3").into()
+                    ]
                 ),
                 Token::Image {
-                    caption: String::from("Caption"),
-                    path: String::from("path/to/image"),
+                    caption: Cow::from("Caption").into(),
+                    path: Cow::from("path/to/image"),
                 },
                 Token::Codeblock(
-                    String::from(
+                    Cow::from(
                         "#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n"
                     )
                 ),
                 Token::EOF
             ]
         );
-        Ok(())
     }
 
     #[test]
-    fn basic_all_with_newline() -> Result<(), fancy_regex::Error> {
-        let input = String::from(
-            "@ Heading\n\nText\n\n> Quote\n\n| Col1 | Col2 | Col3 |\n\n#[Caption](path/to/image)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$\n"
-        );
+    fn basic_all_with_newline() {
+        let input = BASIC_ALL_WITH_NEWLINE;
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
             vec![
-                Token::Section(String::from("Heading")),
-                Token::Text(String::from("Text")),
-                Token::Quote(String::from("Quote")),
+                Token::Section(Cow::from("Heading"), None),
+                Token::Text(Cow::from("Text")),
+                Token::Quote(Cow::from("Quote")),
                 Token::Table(
-                    vec![String::from("Col1"), String::from("Col2"), String::from("Col3")]
+                    vec![
+                        Cow::from("Col1").into(),
+                        Cow::from("Col2").into(),
+                        Cow::from("Col3").into()
+                    ]
                 ),
                 Token::Image {
-                    caption: String::from("Caption"),
-                    path: String::from("path/to/image"),
+                    caption: Cow::from("Caption").into(),
+                    path: Cow::from("path/to/image"),
                 },
                 Token::Codeblock(
-                    String::from(
+                    Cow::from(
                         "#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n"
                     )
                 ),
                 Token::EOF
             ]
         );
-        Ok(())
     }
 
     #[test]
-    fn complex_all() -> Result<(), fancy_regex::Error> {
-        let input = String::from(
-            "@ Heading\n\nText\n\n> Quote\n\n| Col1 | Col2 | Col3 |\n\n#[Caption](path/to/image)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$\n\n@@ Heading 2\n\nText 2\n\n> Quote 2\n\n| Col1 | Col2 | Col3 |\n\n#[Caption 2](path/to/image/2)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$"
-        );
+    fn complex_all() {
+        let input = COMPLEX_ALL;
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
             vec![
-                Token::Section(String::from("Heading")),
-                Token::Text(String::from("Text")),
-                Token::Quote(String::from("Quote")),
+                Token::Section(Cow::from("Heading"), None),
+                Token::Text(Cow::from("Text")),
+                Token::Quote(Cow::from("Quote")),
                 Token::Table(
-                    vec![String::from("Col1"), String::from("Col2"), String::from("Col3")]
+                    vec![
+                        Cow::from("Col1").into(),
+                        Cow::from("Col2").into(),
+                        Cow::from("Col3").into()
+                    ]
                 ),
                 Token::Image {
-                    caption: String::from("Caption"),
-                    path: String::from("path/to/image"),
+                    caption: Cow::from("Caption").into(),
+                    path: Cow::from("path/to/image"),
                 },
                 Token::Codeblock(
-                    String::from(
+                    Cow::from(
                         "#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n"
                     )
                 ),
-                Token::Subsection(String::from("Heading 2")),
-                Token::Text(String::from("Text 2")),
-                Token::Quote(String::from("Quote 2")),
+                Token::Subsection(Cow::from("Heading 2"), None),
+                Token::Text(Cow::from("Text 2")),
+                Token::Quote(Cow::from("Quote 2")),
                 Token::Table(
-                    vec![String::from("Col1"), String::from("Col2"), String::from("Col3")]
+                    vec![
+                        Cow::from("Col1").into(),
+                        Cow::from("Col2").into(),
+                        Cow::from("Col3").into()
+                    ]
                 ),
                 Token::Image {
-                    caption: String::from("Caption 2"),
-                    path: String::from("path/to/image/2"),
+                    caption: Cow::from("Caption 2").into(),
+                    path: Cow::from("path/to/image/2"),
                 },
                 Token::Codeblock(
-                    String::from(
+                    Cow::from(
                         "#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n"
                     )
                 ),
                 Token::EOF
             ]
         );
-        Ok(())
     }
 	
 	#[test]
-	fn multiline_table() -> Result<(), fancy_regex::Error> {
-		let input = String::from("@ Heading\n\n| Col1 | Col2 | Col3 |\n| Col4 | Col5 | Col6 |\n");
+	fn multiline_table() {
+		let input = MULTILINE_TABLE;
 		let lexer = Lexer::new(input);
-		let tokens = lexer.tokenize()?;
+		let tokens = lexer.tokenize();
 		assert_eq!(
 			tokens,
 			vec![
-				Token::Section(String::from("Heading")),
+				Token::Section(Cow::from("Heading"), None),
 				Token::Table(
-					vec![String::from("Col1"), String::from("Col2"), String::from("Col3")]
+					vec![
+						Cow::from("Col1").into(),
+						Cow::from("Col2").into(),
+						Cow::from("Col3").into()
+					]
 				),
 				Token::Table(
-					vec![String::from("Col4"), String::from("Col5"), String::from("Col6")]
+					vec![
+						Cow::from("Col4").into(),
+						Cow::from("Col5").into(),
+						Cow::from("Col6").into()
+					]
 				),
 				Token::EOF
 			]
 		);
-		Ok(())
 	}
 
     #[test]
-    fn complex_all_with_newline() -> Result<(), fancy_regex::Error> {
-        let input = String::from(
-            "@ Heading\n\nText\n\n> Quote\n\n| Col1 | Col2 | Col3 |\n\n#[Caption](path/to/image)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$\n\n@@ Heading 2\n\nText 2\n\n> Quote 2\n\n| Col1 | Col2 | Col3 |\n\n#[Caption 2](path/to/image/2)\n\n$$$\n#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n$$$\n"
-        );
+    fn complex_all_with_newline() {
+        let input = COMPLEX_ALL_WITH_NEWLINE;
         let lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
+        let tokens = lexer.tokenize();
         assert_eq!(
             tokens,
             vec![
-                Token::Section(String::from("Heading")),
-                Token::Text(String::from("Text")),
-                Token::Quote(String::from("Quote")),
+                Token::Section(Cow::from("Heading"), None),
+                Token::Text(Cow::from("Text")),
+                Token::Quote(Cow::from("Quote")),
                 Token::Table(
-                    vec![String::from("Col1"), String::from("Col2"), String::from("Col3")]
+                    vec![
+                        Cow::from("Col1").into(),
+                        Cow::from("Col2").into(),
+                        Cow::from("Col3").into()
+                    ]
                 ),
                 Token::Image {
-                    caption: String::from("Caption"),
-                    path: String::from("path/to/image"),
+                    caption: Cow::from("Caption").into(),
+                    path: Cow::from("path/to/image"),
                 },
                 Token::Codeblock(
-                    String::from(
+                    Cow::from(
                         "#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n"
                     )
                 ),
-                Token::Subsection(String::from("Heading 2")),
-                Token::Text(String::from("Text 2")),
-                Token::Quote(String::from("Quote 2")),
+                Token::Subsection(Cow::from("Heading 2"), None),
+                Token::Text(Cow::from("Text 2")),
+                Token::Quote(Cow::from("Quote 2")),
                 Token::Table(
-                    vec![String::from("Col1"), String::from("Col2"), String::from("Col3")]
+                    vec![
+                        Cow::from("Col1").into(),
+                        Cow::from("Col2").into(),
+                        Cow::from("Col3").into()
+                    ]
                 ),
                 Token::Image {
-                    caption: String::from("Caption 2"),
-                    path: String::from("path/to/image/2"),
+                    caption: Cow::from("Caption 2").into(),
+                    path: Cow::from("path/to/image/2"),
                 },
                 Token::Codeblock(
-                    String::from(
+                    Cow::from(
                         "#include<iostream>\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n}\n"
                     )
                 ),
                 Token::EOF
             ]
         );
-        Ok(())
     }
 
     #[test]
     fn non_assert_sample_doc() {
-        let sample = String::from(
-            r#"@ Learning C++
-/ Learning Objectives: Learn the basics of C++ /
-
-@@ History of C++
-C++ was developed by Bjarne Stroustrup at Bell Labs in the 1980s. It was originally called C with Classes, but was renamed C++ in 1983. C++ is a superset of C, which means that all valid C programs are also valid C++ programs. C++ is an object-oriented language, which means that it is designed to allow programmers to create their own data types. C++ is a compiled language, which means that it must be translated into machine code before it can be run. C++ is a statically typed language, which means that the type of a variable must be known at compile time. C++ is a strongly typed language, which means that the type of a variable cannot be changed. C++ is a case-sensitive language, which means that the names of variables, functions, and classes are case-sensitive. C++ is a free-form language, which means that the programmer can choose how to format the code. C++ is a multi-paradigm language, which means that it supports multiple programming styles. C++ is a general-purpose language, which means that it can be used to write any type of program.
-
-> C++ was developed by Bjarne Stroustrup
-
-!bjarne-stroustrup.jpg!Bjarne Stroustrup at the 2012 C++Now conference.
-
-@@ Some Characterisitcs of C++
-- Object oriented
-- Compiled
--- Translated into machine code
-- Statically typed
-- Strongly typed
-- Case-sensitive
-- Free-form
-- Multi-paradigm
-- General-purpose
+        let sample = SAMPLE_DOCUMENT;
+        let lexer = Lexer::new(sample);
+        let tokens = lexer.tokenize();
+        println!("{:?}", tokens);
+    }
 
-@@ Sample C++ Program
-$$$
-#include <iostream>
+	#[test]
+	fn button() {
+		let input = "#![Button]{https://example.com}";
+		let lexer = Lexer::new(input);
+		let tokens = lexer.tokenize();
+		assert_eq!(
+			tokens,
+			vec![
+				Token::Button {
+					text: Cow::from("Button").into(),
+					link: Cow::from("https://example.com"),
+				},
+				Token::EOF
+			]
+		);
+	}
 
-int main() {
-	std::cout << "Hello, World!" << std::endl;
-	return 0;
-}
-$$$
+	#[test]
+	fn spans() {
+		let input = "@ Heading\n| a |  bc |\n$$$\ncode\n$$$";
+		let tokens = Lexer::new(input).tokenize();
+		assert_eq!(tokens[0].span, Span::new(2, 9, 1, 3));
+		if let Token::Table(columns) = &tokens[1].node {
+			assert_eq!(tokens[1].span, Span::new(10, 21, 2, 1));
+			assert_eq!(columns[0].span, Span::new(12, 13, 2, 3));
+			assert_eq!(columns[1].span, Span::new(17, 19, 2, 8));
+		} else {
+			panic!("expected a table token, found {:?}", tokens[1]);
+		}
+		assert_eq!(tokens[2].span, Span::new(26, 30, 4, 1));
+		assert_eq!(tokens[3].span, Span::new(34, 34, 5, 4));
+	}
 
-/ This program prints "Hello, World!" to the screen. /
+	#[test]
+	fn malformed_lines() {
+		let input = "| a | b\nText\n$$$\nunclosed";
+		let (tokens, diagnostics) = Lexer::new(input).tokenize_with_diagnostics();
+		assert_eq!(
+			tokens,
+			vec![
+				Token::Error(Cow::from("| a | b")),
+				Token::Text(Cow::from("Text")),
+				Token::Codeblock(Cow::from("unclosed\n")),
+				Token::EOF
+			]
+		);
+		let codes = diagnostics
+			.iter()
+			.map(|diagnostic| (diagnostic.code, diagnostic.span.line))
+			.collect::<Vec<_>>();
+		assert_eq!(codes, vec![("E0007", 1), ("E0006", 3)]);
+	}
 
-@@ Compiling C++ Programs
-C++ programs must be compiled before they can be run. The compiler translates the C++ code into machine code. The compiler is usually called g++ on Linux and Mac OS X, and cl on Windows. The compiler is usually invoked with the following command:
-$$$
-g++ -o program program.cpp
-$$$
-/ This command compiles the C++ program program.cpp and creates an executable program. /
+	#[test]
+	fn tokens_borrow_input() {
+		let input = "@ Heading\n- Item\n| a | b |\n$$$\ncode\n$$$\nText";
+		let tokens = Lexer::new(input).tokenize();
+		let borrowed = |text: &Cow<str>| matches!(text, Cow::Borrowed(_));
+		for token in &tokens {
+			match &token.node {
+				Token::Section(text, _) | Token::List { text, .. } | Token::Codeblock(text) | Token::Text(text) =>
+					assert!(borrowed(text), "{:?} copies its text", token),
+				Token::Table(columns) => assert!(columns.iter().all(|column| borrowed(column))),
+				_ => {}
+			}
+		}
+	}
 
-@@ Running C++ Programs
-C++ programs are run by executing the executable file that was created by the compiler. The executable file is usually called a.out on Linux and Mac OS X, and program.exe on Windows. The executable file is usually invoked with the following command:
-$$$
-./a.out
-$$$
-/ This command runs the C++ program a.out. /
+	#[test]
+	fn codeblock_line_endings() {
+		let tokens = Lexer::new("$$$\r\nfirst\r\nsecond\r\n$$$\r\n").tokenize();
+		assert_eq!(tokens, vec![Token::Codeblock(Cow::from("first\nsecond\n")), Token::EOF]);
+		let owned: Token<'static> = Lexer::new("> Quote").tokenize().remove(0).node.into_owned();
+		assert_eq!(owned, Token::Quote(Cow::from("Quote")));
+	}
 
-@@ Keywords
-C++ has a number of keywords that are reserved and cannot be used as variable names. The following is a list of the most commonly used keywords:
-| name | description |
-| int | integer |
-| float | floating-point number |
-| double | double-precision floating-point number |
-| char | character |
-| bool | boolean |
-| void | no return value |
-| true | boolean true |
-| false | boolean false |
-| if | if statement |
-| else | else statement |
-| while | while loop |
-| for | for loop |
-| break | break statement |
-| continue | continue statement |
-| return | return statement |"#
-        );
-        let lexer = Lexer::new(sample);
-        let tokens = lexer.tokenize().unwrap();
-        println!("{:?}", tokens);
-    }
+	#[test]
+	fn equation() {
+		let tokens = Lexer::new("%%%\nE = mc^2\n%%%\n$$$\n%%%\n$$$").tokenize();
+		assert_eq!(
+			tokens,
+			vec![
+				Token::Equation(Cow::from("E = mc^2\n")),
+				Token::Codeblock(Cow::from("%%%\n")),
+				Token::EOF
+			]
+		);
+		assert_eq!(tokens[0].span, Span::new(4, 12, 2, 1));
+	}
 
 	#[test]
-	fn button() -> Result<(), fancy_regex::Error> {
-		let input = String::from("#![Button]{https://example.com}");
-		let lexer = Lexer::new(input);
-		let tokens = lexer.tokenize()?;
+	fn link_definition() {
+		let tokens = Lexer::new("[a]: https://a.org \"Title\"\n[b]: b.html").tokenize();
 		assert_eq!(
 			tokens,
 			vec![
-				Token::Button {
-					text: String::from("Button"),
-					link: String::from("https://example.com"),
+				Token::LinkDefinition {
+					label: Cow::from("a"),
+					url: Cow::from("https://a.org"),
+					title: Some(Cow::from("Title")),
 				},
+				Token::LinkDefinition { label: Cow::from("b"), url: Cow::from("b.html"), title: None },
+				Token::EOF
+			]
+		);
+		assert_eq!(tokens[1].span, Span::new(27, 38, 2, 1));
+	}
+
+	#[test]
+	fn footnote() {
+		let tokens = Lexer::new("Text[^1]\n[^1]: The *note*").tokenize();
+		assert_eq!(
+			tokens,
+			vec![
+				Token::Text(Cow::from("Text[^1]")),
+				Token::Footnote { label: Cow::from("1"), text: Cow::from("The *note*") },
 				Token::EOF
 			]
 		);
-		Ok(())
+		assert_eq!(tokens[1].span, Span::new(15, 25, 2, 7));
+	}
+
+	#[test]
+	fn sample_documents_round_trip_through_formatter() {
+		for sample in [BASIC_ALL, BASIC_ALL_WITH_NEWLINE, COMPLEX_ALL, COMPLEX_ALL_WITH_NEWLINE, MULTILINE_TABLE, SAMPLE_DOCUMENT] {
+			crate::formatter::assert_round_trip(sample);
+		}
 	}
 
-}
\ No newline at end of file
+	#[test]
+	fn syntax_tree_is_lossless() {
+		for sample in [BASIC_ALL, COMPLEX_ALL_WITH_NEWLINE, MULTILINE_TABLE, SAMPLE_DOCUMENT, "  @ Not a heading \r\n\r\n\r\n|x|\t\n"] {
+			let (tree, _) = Lexer::new(sample).syntax_tree();
+			assert_eq!(tree.to_string(), sample);
+		}
+	}
+}
@@ -1,17 +1,67 @@
-mod lexer;
-mod parser;
+pub mod lexer;
+pub mod parser;
+pub mod html;
+pub mod document;
+pub mod span;
+pub mod diagnostic;
+pub mod visit;
+pub mod filter;
+pub mod cst;
+pub mod formatter;
+pub mod math;
 mod html_impl;
-mod document;
+mod error;
+mod inline;
+#[cfg(test)]
 mod tests;
-mod html;
+
+pub use cst::{ SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken };
+pub use diagnostic::{ Diagnostic, Severity };
+pub use document::{ Document, Options };
+pub use error::Error;
+pub use formatter::{ format_body, format_source };
+pub use html::{ ToHtml, WriteHtml };
+pub use lexer::{ Lexer, Token };
+pub use parser::{ ParseArtefact, ParseError, Parser, Text, TextArtefact };
+pub use span::{ Span, Spanned };
+pub use visit::{ Visitor, VisitorMut };
 
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::{ prelude::*, BufWriter };
+
+/// Renders waygum source held in memory into a complete HTML page.
+pub fn render(input: &str, options: &Options) -> Result<String, Error> {
+	let mut doc = Document::new(input, &options.title, options.style.clone());
+	doc.convert_to_html()
+}
+
+/// Reads waygum source from `reader` and writes the rendered HTML page to `writer`.
+pub fn render_to_writer<R: Read, W: Write>(
+	mut reader: R,
+	mut writer: W,
+	options: &Options
+) -> Result<(), Error> {
+	let mut input = String::new();
+	reader.read_to_string(&mut input)?;
+	let mut doc = Document::new(input.as_str(), &options.title, options.style.clone());
+	doc.parse()?;
+	doc.write_html(&mut writer)?;
+	Ok(())
+}
+
+/// Formats waygum source canonically. Source with parse errors is refused, since
+/// formatting it could change what the author meant.
+pub fn format(input: &str) -> Result<String, Error> {
+	let mut doc = Document::new(input, "", None);
+	doc.parse()?;
+	Ok(format_source(input))
+}
 
-pub fn convert_to_html(input_file: &str, style_file: Option<&str>, output_file: &str) -> Result<(), Box<dyn std::error::Error>> {
-	let mut doc = document::Document::load(input_file, style_file)?;
-	let html = doc.convert_to_html()?;
-	let mut outfile = File::create(output_file)?;
-	outfile.write_all(html.as_bytes())?;
+pub fn convert_to_html(input_file: &str, style_file: Option<&str>, output_file: &str) -> Result<(), Error> {
+	let mut doc = Document::load(input_file, style_file)?;
+	doc.parse()?;
+	let mut outfile = BufWriter::new(File::create(output_file)?);
+	doc.write_html(&mut outfile)?;
+	outfile.flush()?;
 	Ok(())
-}
\ No newline at end of file
+}
@@ -1,21 +1,155 @@
-use clap::Parser;
-use waygum::convert_to_html;
+use clap::{ Parser, Subcommand, ValueEnum };
+use waygum::{ format_source, Document, Lexer, Severity, WriteHtml };
+use std::fs::{ self, File };
+use std::io::{ self, prelude::*, BufWriter };
 
 #[derive(Parser, Debug)]
 #[clap(name = "waygum", version = "0.1.0", author = "Ashwin Rohit")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-	#[arg(short, long)]
-	input: String,
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	#[arg(short, long, required = true)]
+	input: Option<String>,
 
+	/// Where to write the output. Writes to stdout if left out.
 	#[arg(short, long)]
-	output: String,
+	output: Option<String>,
 
 	#[arg(short, long)]
 	style: Option<String>,
+
+	/// What to write: the lexer's tokens or the parse tree as JSON, or the html page.
+	#[arg(long, value_enum, default_value_t = Emit::Html)]
+	emit: Emit,
+
+	/// What the input is: waygum source, or a parse tree saved with `--emit ast`.
+	#[arg(long, value_enum, default_value_t = Source::Waygum)]
+	from: Source,
+
+	/// A program, with any arguments, to pipe the parse tree through as JSON
+	/// before rendering. Can be given more than once; filters run in order.
+	#[arg(long = "filter", value_name = "COMMAND")]
+	filters: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Rewrites waygum files in canonical form.
+	Fmt {
+		/// Don't rewrite anything; list the files that aren't formatted and fail if there are any.
+		#[arg(long)]
+		check: bool,
+
+		#[arg(required = true)]
+		files: Vec<String>,
+	},
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Emit {
+	Tokens,
+	Ast,
+	Html,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Source {
+	Waygum,
+	Ast,
+}
+
+fn open_output(path: &Option<String>) -> io::Result<Box<dyn Write>> {
+	Ok(match path {
+		Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+		None => Box::new(BufWriter::new(io::stdout().lock())),
+	})
+}
+
+/// Formats each file in place, or with `check` only reports the ones that would change.
+/// Files that don't parse are left alone. Returns whether every file was fine.
+fn format_files(files: &[String], check: bool) -> Result<bool, Box<dyn std::error::Error>> {
+	let mut ok = true;
+	for path in files {
+		let mut doc = Document::load(path, None)?;
+		if let Err(err) = doc.parse() {
+			eprint!("{}", err.render(doc.source(), path));
+			ok = false;
+			continue;
+		}
+		let formatted = format_source(doc.source());
+		if formatted == doc.source() {
+			continue;
+		}
+		if check {
+			println!("{}", path);
+			ok = false;
+		} else {
+			fs::write(path, formatted)?;
+		}
+	}
+	Ok(ok)
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let args = Args::parse();
-	convert_to_html(&args.input, args.style.as_deref(), &args.output)?;
+	if let Some(Command::Fmt { check, files }) = &args.command {
+		if !format_files(files, *check)? {
+			std::process::exit(1);
+		}
+		return Ok(());
+	}
+	let input = args.input.as_deref().expect("clap requires --input without a subcommand");
+	if args.emit == Emit::Tokens {
+		if args.from == Source::Ast {
+			return Err("tokens can only be emitted from waygum source".into());
+		}
+		if !args.filters.is_empty() {
+			return Err("filters apply to the parse tree and can't be used with `--emit tokens`".into());
+		}
+		let doc = Document::load(input, None)?;
+		let (tokens, diagnostics) = Lexer::new(doc.source()).tokenize_with_diagnostics();
+		for diagnostic in &diagnostics {
+			eprint!("{}", diagnostic.render(doc.source(), input));
+		}
+		if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+			std::process::exit(1);
+		}
+		let mut output = open_output(&args.output)?;
+		serde_json::to_writer_pretty(&mut output, &tokens)?;
+		writeln!(output)?;
+		output.flush()?;
+		return Ok(());
+	}
+
+	let mut doc = match args.from {
+		Source::Waygum => {
+			let mut doc = Document::load(input, args.style.as_deref())?;
+			if let Err(err) = doc.parse() {
+				eprint!("{}", err.render(doc.source(), input));
+				std::process::exit(1);
+			}
+			for warning in doc.diagnostics() {
+				eprint!("{}", warning.render(doc.source(), input));
+			}
+			doc
+		}
+		Source::Ast => Document::load_ast(input, args.style.as_deref())?,
+	};
+	for filter in &args.filters {
+		if let Err(err) = doc.apply_filter(filter) {
+			eprint!("{}", err.render(doc.source(), input));
+			std::process::exit(1);
+		}
+	}
+	let mut output = open_output(&args.output)?;
+	if args.emit == Emit::Ast {
+		serde_json::to_writer_pretty(&mut output, doc.body())?;
+		writeln!(output)?;
+	} else {
+		doc.write_html(&mut output)?;
+	}
+	output.flush()?;
 	Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,903 @@
+//! Converts a subset of LaTeX math to MathML, so pages show equations without
+//! any client-side JavaScript.
+//!
+//! The subset covers letters, numbers and operators; `^`, `_` and `'` scripts; `{}`
+//! groups; `\frac`, `\binom` and `\sqrt`; `\text`, `\operatorname` and font commands
+//! such as `\mathbf`; accents such as `\hat`; `\left` and `\right`; Greek letters,
+//! the common symbols, big operators and function names; and the `matrix`,
+//! `pmatrix`, `bmatrix`, `Bmatrix`, `vmatrix`, `Vmatrix`, `cases` and `aligned`
+//! environments. `%` starts a comment, as in LaTeX.
+//!
+//! A display equation is numbered unless it contains `\notag`, and can be named
+//! with `\label{name}` so that `\eqref{name}` and `\ref{name}` can refer to it.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::ops::Range;
+use super::diagnostic::Diagnostic;
+use super::span::Span;
+
+/// A problem in a formula, pointing at the bytes of the formula it is about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MathError {
+    pub message: String,
+    pub range: Range<usize>,
+}
+
+/// A parsed formula, ready to be written as MathML.
+#[derive(Debug, PartialEq)]
+pub struct Formula<'a> {
+    nodes: Vec<Node<'a>>,
+    display: bool,
+    /// The name given with `\label`, and where it is in the formula.
+    pub label: Option<(&'a str, Range<usize>)>,
+    /// Whether the formula is a display equation without `\notag`.
+    pub numbered: bool,
+    /// The labels referred to with `\eqref` or `\ref`, and where.
+    pub references: Vec<(&'a str, Range<usize>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variant {
+    Normal,
+    Italic,
+    Bold,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    SansSerif,
+    Monospace,
+}
+
+#[derive(Debug, PartialEq)]
+enum Node<'a> {
+    /// A variable or a symbol such as `α` or `∞`.
+    Identifier(Cow<'a, str>),
+    Number(&'a str),
+    Operator(Cow<'a, str>),
+    /// An operator such as `∑` or `∫`. With `limits` its scripts go below and above it.
+    BigOperator(&'static str, bool),
+    /// A function name such as `sin`, set upright. With `limits` its scripts go below
+    /// and above it, as for `lim`.
+    Function(&'a str, bool),
+    Text(&'a str),
+    Space(&'static str),
+    Row(Vec<Node<'a>>),
+    /// A numerator and denominator, with or without a line between them.
+    Fraction(Box<Node<'a>>, Box<Node<'a>>, bool),
+    /// A radicand and an optional index.
+    Root(Box<Node<'a>>, Option<Box<Node<'a>>>),
+    Scripts {
+        base: Box<Node<'a>>,
+        sub: Option<Box<Node<'a>>>,
+        sup: Option<Box<Node<'a>>>,
+    },
+    /// A base with an accent above it, or below it if the flag is set.
+    Accent(Box<Node<'a>>, &'static str, bool),
+    Styled(Variant, Box<Node<'a>>),
+    /// Contents between stretchy delimiters. An empty delimiter is left out.
+    Fenced(&'static str, Vec<Node<'a>>, &'static str),
+    /// Rows of cells, with the `columnalign` of the table.
+    Table(Vec<Vec<Node<'a>>>, &'static str),
+    /// The number of a labelled equation, in parentheses if the flag is set.
+    Reference(&'a str, bool),
+}
+
+const GREEK: &[(&str, &str)] = &[
+    ("alpha", "α"), ("beta", "β"), ("gamma", "γ"), ("delta", "δ"), ("epsilon", "ϵ"), ("varepsilon", "ε"),
+    ("zeta", "ζ"), ("eta", "η"), ("theta", "θ"), ("vartheta", "ϑ"), ("iota", "ι"), ("kappa", "κ"),
+    ("lambda", "λ"), ("mu", "μ"), ("nu", "ν"), ("xi", "ξ"), ("omicron", "ο"), ("pi", "π"), ("varpi", "ϖ"),
+    ("rho", "ρ"), ("varrho", "ϱ"), ("sigma", "σ"), ("varsigma", "ς"), ("tau", "τ"), ("upsilon", "υ"),
+    ("phi", "ϕ"), ("varphi", "φ"), ("chi", "χ"), ("psi", "ψ"), ("omega", "ω"), ("Gamma", "Γ"),
+    ("Delta", "Δ"), ("Theta", "Θ"), ("Lambda", "Λ"), ("Xi", "Ξ"), ("Pi", "Π"), ("Sigma", "Σ"),
+    ("Upsilon", "Υ"), ("Phi", "Φ"), ("Psi", "Ψ"), ("Omega", "Ω"),
+];
+
+const SYMBOLS: &[(&str, &str)] = &[
+    ("infty", "∞"), ("partial", "∂"), ("nabla", "∇"), ("hbar", "ℏ"), ("ell", "ℓ"), ("emptyset", "∅"),
+    ("varnothing", "∅"), ("aleph", "ℵ"), ("Re", "ℜ"), ("Im", "ℑ"), ("wp", "℘"), ("imath", "ı"),
+    ("jmath", "ȷ"), ("angle", "∠"), ("triangle", "△"), ("top", "⊤"), ("bot", "⊥"),
+];
+
+const OPERATORS: &[(&str, &str)] = &[
+    ("pm", "±"), ("mp", "∓"), ("times", "×"), ("div", "÷"), ("cdot", "⋅"), ("ast", "∗"), ("star", "⋆"),
+    ("circ", "∘"), ("bullet", "∙"), ("oplus", "⊕"), ("ominus", "⊖"), ("otimes", "⊗"), ("odot", "⊙"),
+    ("cup", "∪"), ("cap", "∩"), ("setminus", "∖"), ("wedge", "∧"), ("land", "∧"), ("vee", "∨"),
+    ("lor", "∨"), ("neg", "¬"), ("lnot", "¬"), ("le", "≤"), ("leq", "≤"), ("ge", "≥"), ("geq", "≥"),
+    ("ne", "≠"), ("neq", "≠"), ("ll", "≪"), ("gg", "≫"), ("approx", "≈"), ("sim", "∼"), ("simeq", "≃"),
+    ("cong", "≅"), ("equiv", "≡"), ("propto", "∝"), ("in", "∈"), ("notin", "∉"), ("ni", "∋"),
+    ("subset", "⊂"), ("supset", "⊃"), ("subseteq", "⊆"), ("supseteq", "⊇"), ("mid", "∣"),
+    ("parallel", "∥"), ("perp", "⊥"), ("to", "→"), ("rightarrow", "→"), ("leftarrow", "←"), ("gets", "←"),
+    ("leftrightarrow", "↔"), ("Rightarrow", "⇒"), ("Leftarrow", "⇐"), ("Leftrightarrow", "⇔"),
+    ("implies", "⟹"), ("impliedby", "⟸"), ("iff", "⟺"), ("mapsto", "↦"), ("uparrow", "↑"),
+    ("downarrow", "↓"), ("longrightarrow", "⟶"), ("longleftarrow", "⟵"), ("forall", "∀"), ("exists", "∃"),
+    ("nexists", "∄"), ("ldots", "…"), ("dots", "…"), ("cdots", "⋯"), ("vdots", "⋮"), ("ddots", "⋱"),
+    ("langle", "⟨"), ("rangle", "⟩"), ("lfloor", "⌊"), ("rfloor", "⌋"), ("lceil", "⌈"), ("rceil", "⌉"),
+    ("vert", "|"), ("Vert", "‖"), ("colon", ":"), ("prime", "′"), ("{", "{"), ("}", "}"), ("|", "‖"),
+    ("%", "%"), ("&", "&"), ("#", "#"), ("$", "$"), ("_", "_"),
+];
+
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"), (":", "0.2222em"), (">", "0.2222em"), (";", "0.2778em"), ("!", "-0.1667em"),
+    (" ", "0.3333em"), ("quad", "1em"), ("qquad", "2em"),
+];
+
+const BIG_OPERATORS: &[(&str, &str, bool)] = &[
+    ("sum", "∑", true), ("prod", "∏", true), ("coprod", "∐", true), ("bigcup", "⋃", true),
+    ("bigcap", "⋂", true), ("bigoplus", "⨁", true), ("bigotimes", "⨂", true), ("bigvee", "⋁", true),
+    ("bigwedge", "⋀", true), ("int", "∫", false), ("iint", "∬", false), ("iiint", "∭", false),
+    ("oint", "∮", false),
+];
+
+const FUNCTIONS: &[(&str, bool)] = &[
+    ("lim", true), ("limsup", true), ("liminf", true), ("max", true), ("min", true), ("sup", true),
+    ("inf", true), ("det", true), ("gcd", true), ("Pr", true), ("sin", false), ("cos", false),
+    ("tan", false), ("cot", false), ("sec", false), ("csc", false), ("arcsin", false), ("arccos", false),
+    ("arctan", false), ("sinh", false), ("cosh", false), ("tanh", false), ("coth", false), ("log", false),
+    ("ln", false), ("lg", false), ("exp", false), ("deg", false), ("dim", false), ("ker", false),
+    ("arg", false), ("hom", false),
+];
+
+const FONTS: &[(&str, Variant)] = &[
+    ("mathrm", Variant::Normal), ("mathit", Variant::Italic), ("mathbf", Variant::Bold),
+    ("boldsymbol", Variant::Bold), ("mathbb", Variant::DoubleStruck), ("mathcal", Variant::Script),
+    ("mathscr", Variant::Script), ("mathfrak", Variant::Fraktur), ("mathsf", Variant::SansSerif),
+    ("mathtt", Variant::Monospace),
+];
+
+/// Accents, and whether they go below their base.
+const ACCENTS: &[(&str, &str, bool)] = &[
+    ("hat", "^", false), ("widehat", "^", false), ("bar", "¯", false), ("overline", "‾", false),
+    ("vec", "→", false), ("overrightarrow", "→", false), ("dot", "˙", false), ("ddot", "¨", false),
+    ("tilde", "~", false), ("widetilde", "~", false), ("check", "ˇ", false), ("breve", "˘", false),
+    ("acute", "´", false), ("grave", "`", false), ("overbrace", "⏞", false), ("underline", "_", true),
+    ("underbrace", "⏟", true),
+];
+
+/// Environments, with their delimiters and column alignment.
+const ENVIRONMENTS: &[(&str, &str, &str, &str)] = &[
+    ("matrix", "", "", "center"), ("pmatrix", "(", ")", "center"), ("bmatrix", "[", "]", "center"),
+    ("Bmatrix", "{", "}", "center"), ("vmatrix", "|", "|", "center"), ("Vmatrix", "‖", "‖", "center"),
+    ("cases", "{", "", "left left"), ("aligned", "", "", "right left right left right left"),
+];
+
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+    table.iter().find(|(key, _)| *key == name).map(|&(_, value)| value)
+}
+
+/// Parses `tex` as inline math or as a display equation. Only display equations
+/// can have a label or be left unnumbered.
+pub fn parse(tex: &str, display: bool) -> Result<Formula<'_>, MathError> {
+    let formula = Formula { nodes: vec![], display, label: None, numbered: display, references: vec![] };
+    let mut parser = MathParser { tex, pos: 0, formula };
+    let nodes = parser.parse_row(false)?;
+    let start = parser.pos;
+    match parser.peek() {
+        None => {}
+        Some('}') => return parser.error("unexpected `}` without a matching `{`", start..start + 1),
+        Some('&') => return parser.error("`&` can only separate cells of an environment such as `aligned`", start..start + 1),
+        _ => {
+            let command = parser.command().unwrap_or_default();
+            let message = match command {
+                "right" => "`\\right` without a matching `\\left`",
+                "end" => "`\\end` without a matching `\\begin`",
+                _ => "`\\\\` can only separate rows of an environment such as `aligned`",
+            };
+            return parser.error(message, start..start + 1 + command.len());
+        }
+    }
+    let mut formula = parser.formula;
+    if let Some((_, range)) = formula.label.as_ref().filter(|_| !formula.numbered) {
+        return Err(MathError { message: "an equation with `\\notag` has no number to label".into(), range: range.clone() });
+    }
+    formula.nodes = nodes;
+    Ok(formula)
+}
+
+/// Returns the span of `range` in `tex`, given the span where `tex` starts in the source.
+pub(crate) fn source_span(tex: &str, start: Span, range: Range<usize>) -> Span {
+    let before = &tex[..range.start];
+    let (line, column) = match before.rfind('\n') {
+        Some(newline) => (start.line + before.matches('\n').count(), before[newline + 1..].chars().count() + 1),
+        None => (start.line, start.column + before.chars().count()),
+    };
+    Span::new(start.start + range.start, start.start + range.end, line, column)
+}
+
+/// Reports `error` in `tex`, which starts at `start` in the source.
+pub(crate) fn diagnostic(tex: &str, start: Span, error: MathError) -> Diagnostic {
+    Diagnostic::error("E0009", format!("invalid math: {}", error.message), source_span(tex, start, error.range))
+}
+
+struct MathParser<'a> {
+    tex: &'a str,
+    pos: usize,
+    /// Collects the label and references; its nodes are filled in at the end.
+    formula: Formula<'a>,
+}
+
+impl<'a> MathParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.tex[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn error<T>(&self, message: impl Into<String>, range: Range<usize>) -> Result<T, MathError> {
+        Err(MathError { message: message.into(), range })
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_space(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with('%') {
+                break;
+            }
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    /// The name of the command at `pos` without its backslash: a run of letters, or
+    /// a single other character as in `\,` or `\\`.
+    fn command(&self) -> Option<&'a str> {
+        let rest = self.rest().strip_prefix('\\')?;
+        match rest.find(|ch: char| !ch.is_ascii_alphabetic()).unwrap_or(rest.len()) {
+            0 => rest.chars().next().map(|ch| &rest[..ch.len_utf8()]),
+            letters => Some(&rest[..letters]),
+        }
+    }
+
+    /// Whether a row ends here: at the end of the formula or a group, at a cell or
+    /// row separator, at `\right` or `\end`, or at `]` inside an optional argument.
+    fn at_row_end(&self, bracket: bool) -> bool {
+        match self.peek() {
+            None | Some('}' | '&') => true,
+            Some(']') => bracket,
+            Some('\\') => matches!(self.command(), Some("\\" | "right" | "end")),
+            _ => false,
+        }
+    }
+
+    fn parse_row(&mut self, bracket: bool) -> Result<Vec<Node<'a>>, MathError> {
+        let mut nodes = vec![];
+        loop {
+            self.skip_space();
+            if self.at_row_end(bracket) {
+                return Ok(nodes);
+            }
+            match self.peek() {
+                Some(marker @ ('^' | '_' | '\'')) => self.parse_script(marker, &mut nodes)?,
+                _ => nodes.extend(self.parse_atom()?),
+            }
+        }
+    }
+
+    /// Attaches a script to the node before it.
+    fn parse_script(&mut self, marker: char, nodes: &mut Vec<Node<'a>>) -> Result<(), MathError> {
+        let start = self.pos;
+        self.bump();
+        let script = match marker {
+            '\'' => Node::Operator(Cow::Borrowed("′")),
+            _ => self.parse_argument(start..self.pos)?,
+        };
+        let (base, mut sub, mut sup) = match nodes.pop() {
+            Some(Node::Scripts { base, sub, sup }) => (base, sub, sup),
+            Some(base) => (Box::new(base), None, None),
+            None => (Box::new(Node::Row(vec![])), None, None),
+        };
+        let (slot, name) = match marker {
+            '_' => (&mut sub, "subscript"),
+            _ => (&mut sup, "superscript"),
+        };
+        if slot.is_some() {
+            return self.error(format!("double {}: use braces to group it, as in `{{x^a}}^b`", name), start..self.pos);
+        }
+        *slot = Some(Box::new(script));
+        nodes.push(Node::Scripts { base, sub, sup });
+        Ok(())
+    }
+
+    /// Parses the argument of the command or script at `command`: a group, a
+    /// command with its own arguments, or a single character.
+    fn parse_argument(&mut self, command: Range<usize>) -> Result<Node<'a>, MathError> {
+        self.skip_space();
+        let missing = || format!("`{}` is missing its argument", &self.tex[command.clone()]);
+        match self.peek() {
+            Some('{') => self.parse_group(),
+            Some('^' | '_' | '\'') => self.error(missing(), command),
+            Some(ch) if ch.is_ascii_digit() => {
+                let start = self.pos;
+                self.bump();
+                Ok(Node::Number(&self.tex[start..self.pos]))
+            }
+            _ if self.at_row_end(false) => self.error(missing(), command),
+            _ => match self.parse_atom()? {
+                Some(node) => Ok(node),
+                None => self.error(missing(), command),
+            },
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node<'a>, MathError> {
+        let start = self.pos;
+        self.bump();
+        let nodes = self.parse_row(false)?;
+        if self.peek() != Some('}') {
+            return self.error("missing closing `}`", start..start + 1);
+        }
+        self.bump();
+        Ok(Node::Row(nodes))
+    }
+
+    /// Reads a group as plain text, for names and `\text`.
+    fn parse_braced_text(&mut self, command: &Range<usize>) -> Result<(&'a str, Range<usize>), MathError> {
+        self.skip_space();
+        let start = self.pos;
+        if self.peek() != Some('{') {
+            return self.error(format!("`{}` needs an argument in braces", &self.tex[command.clone()]), command.clone());
+        }
+        let mut depth = 0;
+        for (index, ch) in self.rest().char_indices() {
+            match ch {
+                '{' => depth += 1,
+                '}' if depth == 1 => {
+                    let range = start + 1..start + index;
+                    self.pos = start + index + 1;
+                    return Ok((&self.tex[range.clone()], range));
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        self.error("missing closing `}`", start..start + 1)
+    }
+
+    /// Parses a single node. Commands that only record something about the
+    /// formula, like `\label`, give no node.
+    fn parse_atom(&mut self) -> Result<Option<Node<'a>>, MathError> {
+        let start = self.pos;
+        let rest = self.rest();
+        let node = match self.peek() {
+            Some('{') => self.parse_group()?,
+            Some('\\') => return self.parse_command(),
+            Some(ch) if ch.is_ascii_digit() || ch == '.' => {
+                let len = rest.find(|ch: char| !ch.is_ascii_digit() && ch != '.').unwrap_or(rest.len());
+                // A trailing dot ends a sentence rather than the number
+                let len = rest[..len].trim_end_matches('.').len().max(1);
+                self.pos += len;
+                match &rest[..len] {
+                    "." => Node::Operator(Cow::Borrowed(".")),
+                    number => Node::Number(number),
+                }
+            }
+            Some(ch @ ('#' | '$')) => return self.error(format!("unexpected `{}`", ch), start..start + 1),
+            Some(ch) => {
+                self.bump();
+                let text = &rest[..ch.len_utf8()];
+                match ch {
+                    '-' => Node::Operator(Cow::Borrowed("−")),
+                    '*' => Node::Operator(Cow::Borrowed("∗")),
+                    '~' => Node::Space("0.3333em"),
+                    _ if ch.is_alphabetic() => Node::Identifier(Cow::Borrowed(text)),
+                    _ => Node::Operator(Cow::Borrowed(text)),
+                }
+            }
+            None => return Ok(None),
+        };
+        Ok(Some(node))
+    }
+
+    fn parse_command(&mut self) -> Result<Option<Node<'a>>, MathError> {
+        let start = self.pos;
+        let Some(name) = self.command() else {
+            return self.error("`\\` at the end of the formula", start..start + 1);
+        };
+        self.pos += 1 + name.len();
+        let range = start..self.pos;
+        let node = match name {
+            "frac" | "dfrac" | "tfrac" | "binom" => {
+                let numerator = self.parse_argument(range.clone())?;
+                let denominator = self.parse_argument(range)?;
+                let fraction = Node::Fraction(Box::new(numerator), Box::new(denominator), name != "binom");
+                match name {
+                    "binom" => Node::Fenced("(", vec![fraction], ")"),
+                    _ => fraction,
+                }
+            }
+            "sqrt" => {
+                self.skip_space();
+                let index = match self.peek() {
+                    Some('[') => {
+                        let open = self.pos;
+                        self.bump();
+                        let index = self.parse_row(true)?;
+                        if self.bump() != Some(']') {
+                            return self.error("missing closing `]`", open..open + 1);
+                        }
+                        Some(Box::new(Node::Row(index)))
+                    }
+                    _ => None,
+                };
+                Node::Root(Box::new(self.parse_argument(range)?), index)
+            }
+            "text" | "textrm" | "mbox" => Node::Text(self.parse_braced_text(&range)?.0),
+            "operatorname" => Node::Function(self.parse_braced_text(&range)?.0.trim(), false),
+            "left" => {
+                let open = self.parse_delimiter(&range)?;
+                let contents = self.parse_row(false)?;
+                if self.command() != Some("right") {
+                    return self.error("`\\left` without a matching `\\right`", range);
+                }
+                let right = self.pos..self.pos + "\\right".len();
+                self.pos = right.end;
+                Node::Fenced(open, contents, self.parse_delimiter(&right)?)
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl" | "biggr" | "Biggl" | "Biggr" =>
+                Node::Operator(Cow::Borrowed(self.parse_delimiter(&range)?)),
+            "label" => {
+                let (label, label_range) = self.parse_braced_text(&range)?;
+                let range = range.start..self.pos;
+                if !self.formula.display {
+                    return self.error("only display equations can have a label", range);
+                }
+                if self.formula.label.is_some() {
+                    return self.error("an equation can only have one label", range);
+                }
+                if label.trim().is_empty() {
+                    return self.error("the label is empty", range);
+                }
+                self.formula.label = Some((label.trim(), label_range));
+                return Ok(None);
+            }
+            "notag" | "nonumber" => {
+                self.formula.numbered = false;
+                return Ok(None);
+            }
+            "eqref" | "ref" => {
+                let (label, label_range) = self.parse_braced_text(&range)?;
+                self.formula.references.push((label.trim(), label_range));
+                Node::Reference(label.trim(), name == "eqref")
+            }
+            "begin" => self.parse_environment(range)?,
+            _ => {
+                if let Some(text) = lookup(GREEK, name).or_else(|| lookup(SYMBOLS, name)) {
+                    Node::Identifier(Cow::Borrowed(text))
+                } else if let Some(text) = lookup(OPERATORS, name) {
+                    Node::Operator(Cow::Borrowed(text))
+                } else if let Some(width) = lookup(SPACES, name) {
+                    Node::Space(width)
+                } else if let Some(&(_, text, limits)) = BIG_OPERATORS.iter().find(|(key, ..)| *key == name) {
+                    Node::BigOperator(text, limits)
+                } else if let Some(limits) = lookup(FUNCTIONS, name) {
+                    Node::Function(name, limits)
+                } else if let Some(variant) = lookup(FONTS, name) {
+                    Node::Styled(variant, Box::new(self.parse_argument(range)?))
+                } else if let Some(&(_, accent, under)) = ACCENTS.iter().find(|(key, ..)| *key == name) {
+                    Node::Accent(Box::new(self.parse_argument(range)?), accent, under)
+                } else {
+                    return self.error(format!("unknown command `\\{}`", name), range);
+                }
+            }
+        };
+        Ok(Some(node))
+    }
+
+    /// Parses the delimiter after `\left`, `\right` or `\big`. `.` stands for none.
+    fn parse_delimiter(&mut self, command: &Range<usize>) -> Result<&'static str, MathError> {
+        self.skip_space();
+        let delimiter = match self.command() {
+            Some(name) => {
+                self.pos += 1 + name.len();
+                match name {
+                    "{" | "lbrace" => Some("{"),
+                    "}" | "rbrace" => Some("}"),
+                    "|" | "Vert" | "lVert" | "rVert" => Some("‖"),
+                    "vert" | "lvert" | "rvert" => Some("|"),
+                    _ => ["langle", "rangle", "lfloor", "rfloor", "lceil", "rceil"]
+                        .contains(&name)
+                        .then(|| lookup(OPERATORS, name))
+                        .flatten(),
+                }
+            }
+            None => match self.bump() {
+                Some('(') => Some("("),
+                Some(')') => Some(")"),
+                Some('[') => Some("["),
+                Some(']') => Some("]"),
+                Some('|') => Some("|"),
+                Some('/') => Some("/"),
+                Some('<') => Some("⟨"),
+                Some('>') => Some("⟩"),
+                Some('.') => Some(""),
+                _ => None,
+            },
+        };
+        match delimiter {
+            Some(delimiter) => Ok(delimiter),
+            None => self.error(
+                format!("`{}` must be followed by a delimiter such as `(`, `[`, `\\{{` or `.`", &self.tex[command.clone()]),
+                command.start..self.pos
+            ),
+        }
+    }
+
+    /// Parses the rows of an environment up to its `\end`. Cells are separated by
+    /// `&` and rows by `\\`.
+    fn parse_environment(&mut self, begin: Range<usize>) -> Result<Node<'a>, MathError> {
+        let (name, _) = self.parse_braced_text(&begin)?;
+        let range = begin.start..self.pos;
+        let Some(&(_, open, close, align)) = ENVIRONMENTS.iter().find(|(key, ..)| *key == name) else {
+            return self.error(format!("unknown environment `{}`", name), range);
+        };
+        let mut rows = vec![];
+        let mut cells = vec![];
+        loop {
+            cells.push(Node::Row(self.parse_row(false)?));
+            match (self.peek(), self.command()) {
+                (Some('&'), _) => {
+                    self.bump();
+                }
+                (_, Some("\\")) => {
+                    self.pos += 2;
+                    rows.push(std::mem::take(&mut cells));
+                }
+                (_, Some("end")) => {
+                    let end = self.pos..self.pos + "\\end".len();
+                    self.pos = end.end;
+                    let (end_name, _) = self.parse_braced_text(&end)?;
+                    if end_name != name {
+                        return self.error(
+                            format!("`\\begin{{{}}}` is closed by `\\end{{{}}}`", name, end_name),
+                            end.start..self.pos
+                        );
+                    }
+                    break;
+                }
+                _ => return self.error(format!("`\\begin{{{}}}` without a matching `\\end{{{}}}`", name, name), range),
+            }
+        }
+        // A `\\` after the last row leaves an empty row behind it
+        if rows.is_empty() || cells.len() > 1 || cells[0] != Node::Row(vec![]) {
+            rows.push(cells);
+        }
+        let table = Node::Table(rows, align);
+        Ok(match (open, close) {
+            ("", "") => table,
+            _ => Node::Fenced(open, vec![table], close),
+        })
+    }
+}
+
+impl Formula<'_> {
+    /// Writes the formula as a `<math>` element. `number` gives the number of the
+    /// equation with a label; references to labels it doesn't know show as `??`.
+    pub fn to_mathml(&self, number: &dyn Fn(&str) -> Option<usize>) -> String {
+        let mut writer = MathWriter { output: String::new(), number };
+        writer.output.push_str(if self.display { "<math display=\"block\">" } else { "<math>" });
+        writer.row(&self.nodes, None);
+        writer.output.push_str("</math>");
+        writer.output
+    }
+
+    /// Writes an inline formula that is nothing but `\eqref{name}` or `\ref{name}` as an
+    /// html link to the equation, since MathML can't link. Returns `None` for any other formula.
+    pub fn to_link(&self, number: &dyn Fn(&str) -> Option<usize>) -> Option<String> {
+        match self.nodes.as_slice() {
+            [Node::Reference(label, parenthesised)] if !self.display => Some(
+                format!("<a href=\"#eq-{}\">{}</a>", escape(label), reference_text(number(label), *parenthesised))
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// The text of a reference to an equation: its number, `??` if there is none.
+fn reference_text(number: Option<usize>, parenthesised: bool) -> String {
+    let number = number.map_or_else(|| "??".to_string(), |number| number.to_string());
+    if parenthesised { format!("({})", number) } else { number }
+}
+
+/// Writes a formula that failed to parse as its source, marked as an error.
+pub fn invalid_mathml(tex: &str, display: bool) -> String {
+    let display = if display { " display=\"block\"" } else { "" };
+    format!("<math{}><merror><mtext>{}</mtext></merror></math>", display, escape(tex))
+}
+
+/// Escapes the characters that can't appear as themselves in html text or a quoted attribute.
+pub(crate) fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"']) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(ch),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Maps a letter or digit to its Unicode mathematical alphanumeric symbol.
+fn styled(variant: Variant, ch: char) -> char {
+    let exception = match (variant, ch) {
+        (Variant::Italic, 'h') => Some('ℎ'),
+        (Variant::DoubleStruck, 'C') => Some('ℂ'),
+        (Variant::DoubleStruck, 'H') => Some('ℍ'),
+        (Variant::DoubleStruck, 'N') => Some('ℕ'),
+        (Variant::DoubleStruck, 'P') => Some('ℙ'),
+        (Variant::DoubleStruck, 'Q') => Some('ℚ'),
+        (Variant::DoubleStruck, 'R') => Some('ℝ'),
+        (Variant::DoubleStruck, 'Z') => Some('ℤ'),
+        (Variant::Script, 'B') => Some('ℬ'),
+        (Variant::Script, 'E') => Some('ℰ'),
+        (Variant::Script, 'F') => Some('ℱ'),
+        (Variant::Script, 'H') => Some('ℋ'),
+        (Variant::Script, 'I') => Some('ℐ'),
+        (Variant::Script, 'L') => Some('ℒ'),
+        (Variant::Script, 'M') => Some('ℳ'),
+        (Variant::Script, 'R') => Some('ℛ'),
+        (Variant::Script, 'e') => Some('ℯ'),
+        (Variant::Script, 'g') => Some('ℊ'),
+        (Variant::Script, 'o') => Some('ℴ'),
+        (Variant::Fraktur, 'C') => Some('ℭ'),
+        (Variant::Fraktur, 'H') => Some('ℌ'),
+        (Variant::Fraktur, 'I') => Some('ℑ'),
+        (Variant::Fraktur, 'R') => Some('ℜ'),
+        (Variant::Fraktur, 'Z') => Some('ℨ'),
+        _ => None,
+    };
+    // The first capital letter, small letter and digit of each alphabet
+    let (upper, lower, digit) = match variant {
+        Variant::Normal => return ch,
+        Variant::Italic => (0x1D434, 0x1D44E, None),
+        Variant::Bold => (0x1D400, 0x1D41A, Some(0x1D7CE)),
+        Variant::DoubleStruck => (0x1D538, 0x1D552, Some(0x1D7D8)),
+        Variant::Script => (0x1D49C, 0x1D4B6, None),
+        Variant::Fraktur => (0x1D504, 0x1D51E, None),
+        Variant::SansSerif => (0x1D5A0, 0x1D5BA, Some(0x1D7E2)),
+        Variant::Monospace => (0x1D670, 0x1D68A, Some(0x1D7F6)),
+    };
+    let code = match ch {
+        'A'..='Z' => upper + (ch as u32 - 'A' as u32),
+        'a'..='z' => lower + (ch as u32 - 'a' as u32),
+        '0'..='9' => match digit {
+            Some(digit) => digit + (ch as u32 - '0' as u32),
+            None => return ch,
+        },
+        _ => return ch,
+    };
+    exception.or_else(|| char::from_u32(code)).unwrap_or(ch)
+}
+
+struct MathWriter<'n> {
+    output: String,
+    number: &'n dyn Fn(&str) -> Option<usize>,
+}
+
+impl MathWriter<'_> {
+    /// Writes nodes as a single element, in an `<mrow>` unless there is exactly one.
+    fn row(&mut self, nodes: &[Node], variant: Option<Variant>) {
+        match nodes {
+            [node] => self.node(node, variant),
+            _ => {
+                self.output.push_str("<mrow>");
+                for node in nodes {
+                    self.node(node, variant);
+                }
+                self.output.push_str("</mrow>");
+            }
+        }
+    }
+
+    fn leaf(&mut self, tag: &str, text: &str) {
+        write!(self.output, "<{tag}>{}</{tag}>", escape(text)).unwrap();
+    }
+
+    /// Writes an identifier or number in the letters of `variant`. Capital Greek
+    /// letters are upright, as in LaTeX.
+    fn token(&mut self, tag: &str, text: &str, variant: Option<Variant>) {
+        let upright_greek = text.chars().count() == 1 && text.chars().all(|ch| ('Α'..='Ω').contains(&ch));
+        match variant {
+            Some(Variant::Normal) => write!(self.output, "<{tag} mathvariant=\"normal\">{}</{tag}>", escape(text)).unwrap(),
+            Some(variant) => self.leaf(tag, &text.chars().map(|ch| styled(variant, ch)).collect::<String>()),
+            None if tag == "mi" && upright_greek => self.token(tag, text, Some(Variant::Normal)),
+            None => self.leaf(tag, text),
+        }
+    }
+
+    fn node(&mut self, node: &Node, variant: Option<Variant>) {
+        match node {
+            Node::Identifier(text) => self.token("mi", text, variant),
+            Node::Number(text) => self.token("mn", text, variant),
+            Node::Operator(text) => self.leaf("mo", text),
+            Node::BigOperator(text, _) => self.leaf("mo", text),
+            Node::Function(name, _) => self.leaf("mi", name),
+            Node::Text(text) => self.leaf("mtext", text),
+            Node::Space(width) => write!(self.output, "<mspace width=\"{}\"/>", width).unwrap(),
+            Node::Row(nodes) => self.row(nodes, variant),
+            Node::Fraction(numerator, denominator, line) => {
+                self.output.push_str(if *line { "<mfrac>" } else { "<mfrac linethickness=\"0\">" });
+                self.node(numerator, variant);
+                self.node(denominator, variant);
+                self.output.push_str("</mfrac>");
+            }
+            Node::Root(radicand, None) => {
+                self.output.push_str("<msqrt>");
+                self.node(radicand, variant);
+                self.output.push_str("</msqrt>");
+            }
+            Node::Root(radicand, Some(index)) => {
+                self.output.push_str("<mroot>");
+                self.node(radicand, variant);
+                self.node(index, variant);
+                self.output.push_str("</mroot>");
+            }
+            Node::Scripts { base, sub, sup } => {
+                let limits = matches!(**base, Node::BigOperator(_, true) | Node::Function(_, true));
+                let tag = match (limits, sub.is_some(), sup.is_some()) {
+                    (true, true, true) => "munderover",
+                    (true, true, false) => "munder",
+                    (true, false, _) => "mover",
+                    (false, true, true) => "msubsup",
+                    (false, true, false) => "msub",
+                    (false, false, _) => "msup",
+                };
+                write!(self.output, "<{tag}>").unwrap();
+                self.node(base, variant);
+                for script in [sub, sup].into_iter().flatten() {
+                    self.node(script, variant);
+                }
+                write!(self.output, "</{tag}>").unwrap();
+            }
+            Node::Accent(base, accent, under) => {
+                let (tag, attribute) = if *under { ("munder", "accentunder") } else { ("mover", "accent") };
+                write!(self.output, "<{tag} {attribute}=\"true\">").unwrap();
+                self.node(base, variant);
+                write!(self.output, "<mo>{}</mo></{tag}>", accent).unwrap();
+            }
+            Node::Styled(variant, node) => self.node(node, Some(*variant)),
+            Node::Fenced(open, nodes, close) => {
+                self.output.push_str("<mrow>");
+                let fence = |output: &mut String, delimiter: &str| {
+                    if !delimiter.is_empty() {
+                        write!(output, "<mo fence=\"true\" stretchy=\"true\">{}</mo>", delimiter).unwrap();
+                    }
+                };
+                fence(&mut self.output, open);
+                for node in nodes {
+                    self.node(node, variant);
+                }
+                fence(&mut self.output, close);
+                self.output.push_str("</mrow>");
+            }
+            Node::Table(rows, align) => {
+                write!(self.output, "<mtable columnalign=\"{}\">", align).unwrap();
+                for row in rows {
+                    self.output.push_str("<mtr>");
+                    for cell in row {
+                        self.output.push_str("<mtd>");
+                        self.node(cell, variant);
+                        self.output.push_str("</mtd>");
+                    }
+                    self.output.push_str("</mtr>");
+                }
+                self.output.push_str("</mtable>");
+            }
+            Node::Reference(label, parenthesised) => {
+                self.leaf("mtext", &reference_text((self.number)(label), *parenthesised));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mathml(tex: &str, display: bool) -> String {
+        parse(tex, display).unwrap().to_mathml(&|label| (label == "known").then_some(3))
+    }
+
+    #[test]
+    fn math_scripts_and_fractions() {
+        assert_eq!(
+            mathml("x_1^2 + \\frac{a}{b-1} = \\sqrt[3]{y'}", false),
+            "<math><mrow><msubsup><mi>x</mi><mn>1</mn><mn>2</mn></msubsup><mo>+</mo><mfrac><mi>a</mi><mrow><mi>b</mi><mo>−</mo><mn>1</mn></mrow></mfrac><mo>=</mo><mroot><msup><mi>y</mi><mo>′</mo></msup><mn>3</mn></mroot></mrow></math>"
+        );
+        assert_eq!(mathml("e^{i\\pi}", false), "<math><msup><mi>e</mi><mrow><mi>i</mi><mi>π</mi></mrow></msup></math>");
+        assert_eq!(mathml("x^12", false), "<math><mrow><msup><mi>x</mi><mn>1</mn></msup><mn>2</mn></mrow></math>");
+        assert_eq!(mathml("3.14 < 4.", false), "<math><mrow><mn>3.14</mn><mo>&lt;</mo><mn>4</mn><mo>.</mo></mrow></math>");
+    }
+
+    #[test]
+    fn math_operators_and_functions() {
+        assert_eq!(
+            mathml("\\sum_{i=1}^n i \\cdot \\sin x", true),
+            "<math display=\"block\"><mrow><munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>i</mi><mo>⋅</mo><mi>sin</mi><mi>x</mi></mrow></math>"
+        );
+        assert_eq!(
+            mathml("\\int_0^\\infty \\lim_{n \\to \\infty}", false),
+            "<math><mrow><msubsup><mo>∫</mo><mn>0</mn><mi>∞</mi></msubsup><munder><mi>lim</mi><mrow><mi>n</mi><mo>→</mo><mi>∞</mi></mrow></munder></mrow></math>"
+        );
+    }
+
+    #[test]
+    fn math_fonts_accents_and_delimiters() {
+        assert_eq!(
+            mathml("\\mathbb{R}^n \\mathbf{v} \\Gamma \\mathrm{d}x \\text{if } \\hat{x}", false),
+            "<math><mrow><msup><mi>ℝ</mi><mi>n</mi></msup><mi>𝐯</mi><mi mathvariant=\"normal\">Γ</mi><mi mathvariant=\"normal\">d</mi><mi>x</mi><mtext>if </mtext><mover accent=\"true\"><mi>x</mi><mo>^</mo></mover></mrow></math>"
+        );
+        assert_eq!(
+            mathml("\\left\\{ x \\right.", false),
+            "<math><mrow><mo fence=\"true\" stretchy=\"true\">{</mo><mi>x</mi></mrow></math>"
+        );
+    }
+
+    #[test]
+    fn math_environments() {
+        assert_eq!(
+            mathml("\\begin{pmatrix} a & b \\\\ c & d \\\\ \\end{pmatrix}", true),
+            "<math display=\"block\"><mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mtable columnalign=\"center\"><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr><mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">)</mo></mrow></math>"
+        );
+    }
+
+    #[test]
+    fn math_labels_and_references() {
+        let formula = parse("E = mc^2 % mass-energy\n\\label{ eq:energy }", true).unwrap();
+        assert_eq!(formula.label, Some(("eq:energy", 30..41)));
+        assert!(formula.numbered);
+        assert!(!parse("a \\notag", true).unwrap().numbered);
+        assert!(!parse("a", false).unwrap().numbered);
+        assert_eq!(
+            mathml("\\eqref{known} \\ref{missing}", false),
+            "<math><mrow><mtext>(3)</mtext><mtext>??</mtext></mrow></math>"
+        );
+        let link = |tex| parse(tex, false).unwrap().to_link(&|label| (label == "known").then_some(3));
+        assert_eq!(link("\\eqref{known}").as_deref(), Some("<a href=\"#eq-known\">(3)</a>"));
+        assert_eq!(link("\\ref{missing}").as_deref(), Some("<a href=\"#eq-missing\">??</a>"));
+        assert_eq!(link("x = \\ref{known}"), None);
+        assert_eq!(parse("\\eqref{known}", true).unwrap().to_link(&|_| Some(3)), None);
+        assert_eq!(parse("\\ref{a} + \\eqref{b}", false).unwrap().references, vec![("a", 5..6), ("b", 17..18)]);
+    }
+
+    #[test]
+    fn math_errors() {
+        for (tex, message, range) in [
+            ("\\frac{a}", "`\\frac` is missing its argument", 0..5),
+            ("{a", "missing closing `}`", 0..1),
+            ("a}", "unexpected `}` without a matching `{`", 1..2),
+            ("x^2^3", "double superscript: use braces to group it, as in `{x^a}^b`", 3..5),
+            ("\\foo", "unknown command `\\foo`", 0..4),
+            ("a & b", "`&` can only separate cells of an environment such as `aligned`", 2..3),
+            ("\\left( a", "`\\left` without a matching `\\right`", 0..5),
+            ("\\begin{matrix} a \\end{cases}", "`\\begin{matrix}` is closed by `\\end{cases}`", 17..28),
+            ("\\label{x}", "only display equations can have a label", 0..9),
+            ("$", "unexpected `$`", 0..1),
+        ] {
+            assert_eq!(parse(tex, false), Err(MathError { message: message.to_string(), range }), "{}", tex);
+        }
+        assert_eq!(parse("a \\notag \\label{x}", true).unwrap_err().range, 16..17);
+    }
+
+    #[test]
+    fn math_source_spans() {
+        let start = Span::new(10, 30, 4, 3);
+        assert_eq!(source_span("ab\\foo", start, 2..6), Span::new(12, 16, 4, 5));
+        assert_eq!(source_span("a\nbé\\foo", start, 5..9), Span::new(15, 19, 5, 3));
+    }
+}
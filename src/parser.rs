@@ -1,117 +1,364 @@
-use lazy_static::lazy_static;
-use std::{ sync::Mutex };
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use serde::{ Deserialize, Serialize };
 use super::lexer::*;
+use super::inline::InlineParser;
+use super::diagnostic::{ Diagnostic, Severity };
+use super::math;
+use super::span::{ Span, Spanned };
+use super::visit::Visitor;
 
 #[derive(Debug)]
 struct ListState {
     current_depth: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct SectionState {
     section_number: usize,
     subsection_number: usize,
     subsubsection_number: usize,
+    /// The labels of headings, with where they are defined.
+    labels: Vec<(String, Span)>,
 }
 
-lazy_static! {
-    static ref LIST_STATE: Mutex<ListState> = Mutex::new(ListState { current_depth: 1 });
-    static ref PARSE_LINE: Mutex<Vec<char>> = Mutex::new(vec![]);
-    static ref SECTION_STATE: Mutex<SectionState> = Mutex::new(SectionState {
-        section_number: 0,
-        subsection_number: 0,
-        subsubsection_number: 0,
-    });
+#[derive(Debug, Default)]
+struct EquationState {
+    equation_number: usize,
+    /// The labels of display equations, with where they are defined.
+    labels: Vec<(String, Span)>,
 }
 
-pub struct Parser {
-    pub tokens: Vec<Token>,
+/// A footnote definition: its label, text and where it is defined.
+type FootnoteDefinition<'a> = (Cow<'a, str>, Text<'a>, Span);
+
+pub struct Parser<'a> {
+    pub tokens: VecDeque<Spanned<Token<'a>>>,
+    list_state: ListState,
+    section_state: SectionState,
+    equation_state: EquationState,
+    /// The footnote definitions, in the order they are defined.
+    footnotes: Vec<FootnoteDefinition<'a>>,
+    /// The labels of link definitions, with where they are defined.
+    links: Vec<(String, Span)>,
+    eof: Span,
+    diagnostics: Vec<Diagnostic>,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum TextArtefact {
-    Raw(String),
-    Bold(String),
-    Italics(String),
-    Link(String, String),
-    Strikethrough(String),
-    Underline(String),
-    Code(String),
+/// An inline node. Styles and link texts hold the inline nodes inside them, so
+/// they can nest; code spans and math are literal.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum TextArtefact<'a> {
+    Raw(Cow<'a, str>),
+    Bold(Text<'a>),
+    Italics(Text<'a>),
+    Link(Text<'a>, Cow<'a, str>),
+    /// A link whose url is given by the link definition with this label.
+    LinkReference(Text<'a>, Cow<'a, str>),
+    /// A reference to the heading with this label.
+    HeadingReference(Cow<'a, str>),
+    Strikethrough(Text<'a>),
+    Underline(Text<'a>),
+    Code(Cow<'a, str>),
+    /// LaTeX source of inline math.
+    Math(Cow<'a, str>),
+    /// A reference to the footnote with this label.
+    FootnoteReference(Cow<'a, str>),
+    Superscript(Text<'a>),
+    Subscript(Text<'a>),
+    /// Highlighted text.
+    Mark(Text<'a>),
+    /// Keyboard keys, taken literally.
+    Kbd(Cow<'a, str>),
+    /// An image inside text, with its alt text and source.
+    Image(Cow<'a, str>, Cow<'a, str>),
+    Error(Cow<'a, str>),
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Text(pub Vec<TextArtefact>);
-
-#[derive(Debug, PartialEq)]
-pub enum ParseArtefact {
-    Section(usize, String),
-    Subsection(usize, usize, String),
-    Subsubsection(usize, usize, usize, String),
-    Paragraph(Vec<Text>),
-    List(Vec<ParseArtefact>),
-    ListItem(Text),
-    Quote(Text),
-    Table(Vec<ParseArtefact>),
-    TableRow(Vec<Text>),
-    Button(String, String),
-    Image(String, String),
-    Codeblock(String),
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Text<'a>(pub Vec<Spanned<TextArtefact<'a>>>);
+
+/// A block-level node. Text borrows from the source the tokens were lexed from;
+/// use `into_owned` to detach a tree from it.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParseArtefact<'a> {
+    /// A heading's numbers, title and optional label.
+    Section(usize, Text<'a>, Option<Cow<'a, str>>),
+    Subsection(usize, usize, Text<'a>, Option<Cow<'a, str>>),
+    Subsubsection(usize, usize, usize, Text<'a>, Option<Cow<'a, str>>),
+    Paragraph(Vec<Text<'a>>),
+    List(Vec<Spanned<ParseArtefact<'a>>>),
+    ListItem(Text<'a>),
+    Quote(Text<'a>),
+    Table(Vec<Spanned<ParseArtefact<'a>>>),
+    TableRow(Vec<Text<'a>>),
+    Button(Text<'a>, Cow<'a, str>),
+    Image(Text<'a>, Cow<'a, str>),
+    Codeblock(Cow<'a, str>),
+    /// LaTeX source of a display equation, with its number unless it has `\notag`.
+    Equation(Option<usize>, Cow<'a, str>),
+    /// The footnotes of the document, gathered at its end in the order they are numbered.
+    Footnotes(Vec<Spanned<ParseArtefact<'a>>>),
+    /// A footnote with its number and label. Footnotes that are never referred to have no number.
+    Footnote(Option<usize>, Cow<'a, str>, Text<'a>),
+    /// The label, url and title of a link definition.
+    LinkDefinition(Cow<'a, str>, Cow<'a, str>, Option<Cow<'a, str>>),
 	Newline,
+    Error(Cow<'a, str>),
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser {
-            tokens: tokens.into_iter().rev().collect(),
+fn owned(text: Cow<str>) -> Cow<'static, str> {
+    Cow::Owned(text.into_owned())
+}
+
+impl TextArtefact<'_> {
+    /// Copies any borrowed text so the artefact no longer depends on the source.
+    pub fn into_owned(self) -> TextArtefact<'static> {
+        match self {
+            TextArtefact::Raw(text) => TextArtefact::Raw(owned(text)),
+            TextArtefact::Bold(text) => TextArtefact::Bold(text.into_owned()),
+            TextArtefact::Italics(text) => TextArtefact::Italics(text.into_owned()),
+            TextArtefact::Link(text, url) => TextArtefact::Link(text.into_owned(), owned(url)),
+            TextArtefact::LinkReference(text, label) => TextArtefact::LinkReference(text.into_owned(), owned(label)),
+            TextArtefact::HeadingReference(label) => TextArtefact::HeadingReference(owned(label)),
+            TextArtefact::Strikethrough(text) => TextArtefact::Strikethrough(text.into_owned()),
+            TextArtefact::Underline(text) => TextArtefact::Underline(text.into_owned()),
+            TextArtefact::Code(text) => TextArtefact::Code(owned(text)),
+            TextArtefact::Math(tex) => TextArtefact::Math(owned(tex)),
+            TextArtefact::FootnoteReference(label) => TextArtefact::FootnoteReference(owned(label)),
+            TextArtefact::Superscript(text) => TextArtefact::Superscript(text.into_owned()),
+            TextArtefact::Subscript(text) => TextArtefact::Subscript(text.into_owned()),
+            TextArtefact::Mark(text) => TextArtefact::Mark(text.into_owned()),
+            TextArtefact::Kbd(keys) => TextArtefact::Kbd(owned(keys)),
+            TextArtefact::Image(alt, src) => TextArtefact::Image(owned(alt), owned(src)),
+            TextArtefact::Error(text) => TextArtefact::Error(owned(text)),
         }
     }
+}
 
-    fn next(&self) -> Option<&Token> {
-        self.tokens.last()
+impl Text<'_> {
+    pub fn into_owned(self) -> Text<'static> {
+        Text(self.0.into_iter().map(|artefact| artefact.map(TextArtefact::into_owned)).collect())
     }
+}
 
-    fn consume(&mut self) -> Option<Token> {
-        self.tokens.pop()
+/// Plain text without any styles, e.g. for link texts built by hand.
+impl<'a> From<&'a str> for Text<'a> {
+    fn from(text: &'a str) -> Self {
+        Text(vec![TextArtefact::Raw(Cow::Borrowed(text)).into()])
     }
+}
 
-    fn consume_while(&mut self, f: impl Fn(&Token) -> bool) -> Vec<Token> {
-        let mut result = Vec::new();
-        while let Some(token) = self.next() {
-            if f(token) {
-                result.push(self.consume().unwrap());
-            } else {
-                break;
+impl ParseArtefact<'_> {
+    /// Copies any borrowed text so the tree no longer depends on the source.
+    pub fn into_owned(self) -> ParseArtefact<'static> {
+        fn owned_nodes(nodes: Vec<Spanned<ParseArtefact>>) -> Vec<Spanned<ParseArtefact<'static>>> {
+            nodes.into_iter().map(|node| node.map(ParseArtefact::into_owned)).collect()
+        }
+        match self {
+            ParseArtefact::Section(number, title, label) =>
+                ParseArtefact::Section(number, title.into_owned(), label.map(owned)),
+            ParseArtefact::Subsection(section, number, title, label) =>
+                ParseArtefact::Subsection(section, number, title.into_owned(), label.map(owned)),
+            ParseArtefact::Subsubsection(section, subsection, number, title, label) =>
+                ParseArtefact::Subsubsection(section, subsection, number, title.into_owned(), label.map(owned)),
+            ParseArtefact::Paragraph(lines) =>
+                ParseArtefact::Paragraph(lines.into_iter().map(Text::into_owned).collect()),
+            ParseArtefact::List(items) => ParseArtefact::List(owned_nodes(items)),
+            ParseArtefact::ListItem(text) => ParseArtefact::ListItem(text.into_owned()),
+            ParseArtefact::Quote(text) => ParseArtefact::Quote(text.into_owned()),
+            ParseArtefact::Table(rows) => ParseArtefact::Table(owned_nodes(rows)),
+            ParseArtefact::TableRow(columns) =>
+                ParseArtefact::TableRow(columns.into_iter().map(Text::into_owned).collect()),
+            ParseArtefact::Button(text, link) => ParseArtefact::Button(text.into_owned(), owned(link)),
+            ParseArtefact::Image(caption, path) => ParseArtefact::Image(caption.into_owned(), owned(path)),
+            ParseArtefact::Codeblock(code) => ParseArtefact::Codeblock(owned(code)),
+            ParseArtefact::Equation(number, tex) => ParseArtefact::Equation(number, owned(tex)),
+            ParseArtefact::Footnotes(footnotes) => ParseArtefact::Footnotes(owned_nodes(footnotes)),
+            ParseArtefact::Footnote(number, label, text) =>
+                ParseArtefact::Footnote(number, owned(label), text.into_owned()),
+            ParseArtefact::LinkDefinition(label, url, title) =>
+                ParseArtefact::LinkDefinition(owned(label), owned(url), title.map(owned)),
+            ParseArtefact::Newline => ParseArtefact::Newline,
+            ParseArtefact::Error(text) => ParseArtefact::Error(owned(text)),
+        }
+    }
+}
+
+/// Finds `\eqref`s and `\ref`s to labels that no display equation defines.
+struct EquationReferences<'l> {
+    labels: &'l [(String, Span)],
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl EquationReferences<'_> {
+    /// Checks the references in `tex`, which starts at `start` in the source.
+    fn check(&mut self, tex: &str, display: bool, start: Span) {
+        let Ok(formula) = math::parse(tex, display) else {
+            return;
+        };
+        for (label, range) in formula.references {
+            if !self.labels.iter().any(|(defined, _)| defined == label) {
+                self.diagnostics.push(
+                    Diagnostic::warning(
+                        "W0003",
+                        format!("no equation is labelled `{}`", label),
+                        math::source_span(tex, start, range)
+                    ).with_note("display equations are labelled with `\\label{name}`")
+                );
+            }
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for EquationReferences<'_> {
+    fn visit_artefacts(&mut self, artefacts: &'ast [Spanned<ParseArtefact<'_>>]) {
+        for artefact in artefacts {
+            match &artefact.node {
+                ParseArtefact::Equation(_, tex) => self.check(tex, true, artefact.span),
+                node => self.visit_artefact(node),
             }
         }
-        result
     }
 
-    pub fn parse(&mut self) -> Result<Vec<ParseArtefact>, Box<dyn std::error::Error>> {
+    fn visit_text(&mut self, text: &'ast Text<'_>) {
+        for artefact in &text.0 {
+            match &artefact.node {
+                // The formula starts after the opening `\(`
+                TextArtefact::Math(tex) => {
+                    let span = artefact.span;
+                    self.check(tex, false, Span { start: span.start + 2, column: span.column + 2, ..span });
+                }
+                node => self.visit_text_artefact(node),
+            }
+        }
+    }
+}
+
+/// Finds the footnote references in a tree, in document order.
+#[derive(Default)]
+struct FootnoteReferences {
+    references: Vec<(String, Span)>,
+}
+
+impl<'ast> Visitor<'ast> for FootnoteReferences {
+    fn visit_text(&mut self, text: &'ast Text<'_>) {
+        for artefact in &text.0 {
+            match &artefact.node {
+                TextArtefact::FootnoteReference(label) => self.references.push((label.to_string(), artefact.span)),
+                node => self.visit_text_artefact(node),
+            }
+        }
+    }
+}
+
+/// Finds the references to link definitions and to headings in a tree.
+#[derive(Default)]
+struct References {
+    links: Vec<(String, Span)>,
+    headings: Vec<(String, Span)>,
+}
+
+impl<'ast> Visitor<'ast> for References {
+    fn visit_text(&mut self, text: &'ast Text<'_>) {
+        for artefact in &text.0 {
+            match &artefact.node {
+                TextArtefact::LinkReference(_, label) => self.links.push((label.to_string(), artefact.span)),
+                TextArtefact::HeadingReference(label) => self.headings.push((label.to_string(), artefact.span)),
+                _ => {}
+            }
+            self.visit_text_artefact(&artefact.node);
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: Vec<Spanned<Token<'a>>>) -> Parser<'a> {
+        let eof = tokens.last().map(|token| token.span).unwrap_or_default();
+        Parser {
+            tokens: tokens.into(),
+            list_state: ListState { current_depth: 1 },
+            section_state: SectionState::default(),
+            equation_state: EquationState::default(),
+            footnotes: vec![],
+            links: vec![],
+            eof,
+            diagnostics: vec![],
+        }
+    }
+
+    fn next(&self) -> Option<&Token<'a>> {
+        self.tokens.front().map(|token| &token.node)
+    }
+
+    fn consume(&mut self) -> Option<Spanned<Token<'a>>> {
+        self.tokens.pop_front()
+    }
+
+    /// Parses the whole token stream, failing with the first error found.
+    pub fn parse(&mut self) -> Result<Vec<Spanned<ParseArtefact<'a>>>, Diagnostic> {
+        let (artefacts, diagnostics) = self.parse_with_diagnostics();
+        match diagnostics.into_iter().find(|diagnostic| diagnostic.severity == Severity::Error) {
+            Some(error) => Err(error),
+            None => Ok(artefacts),
+        }
+    }
+
+    /// Parses the whole token stream, recovering from errors. Returns a best-effort
+    /// parse, with error artefacts in place of anything malformed, together with
+    /// every diagnostic found along the way.
+    pub fn parse_with_diagnostics(&mut self) -> (Vec<Spanned<ParseArtefact<'a>>>, Vec<Diagnostic>) {
+        self.list_state = ListState { current_depth: 1 };
+        self.section_state = SectionState::default();
+        self.equation_state = EquationState::default();
+        self.footnotes.clear();
+        self.links.clear();
+        self.diagnostics.clear();
         let mut result = vec![];
         while self.next().is_some() {
-            result.extend(self.parse_token()?);
+            match self.parse_token() {
+                Ok(artefacts) => result.extend(artefacts),
+                Err(diagnostic) => self.diagnostics.push(diagnostic),
+            }
         }
-        Ok(result)
+        // Footnotes, links and headings can be referred to before they are defined
+        result.extend(self.number_footnotes(&result));
+        self.check_references(&result);
+        // Equations can be referred to before they are defined
+        let mut references = EquationReferences { labels: &self.equation_state.labels, diagnostics: vec![] };
+        references.visit_artefacts(&result);
+        self.diagnostics.extend(references.diagnostics);
+        (result, std::mem::take(&mut self.diagnostics))
     }
 
-    fn parse_token(&mut self) -> Result<Vec<ParseArtefact>, Box<dyn std::error::Error>> {
+    fn parse_token(&mut self) -> Result<Vec<Spanned<ParseArtefact<'a>>>, Diagnostic> {
 		match self.next() {
-            Some(Token::Section(_)) => self.parse_section(),
-            Some(Token::Subsection(_)) => self.parse_subsection(),
-            Some(Token::Subsubsection(_)) => self.parse_subsubsection(),
+            Some(Token::Section(..)) => self.parse_section(),
+            Some(Token::Subsection(..)) => self.parse_subsection(),
+            Some(Token::Subsubsection(..)) => self.parse_subsubsection(),
             Some(Token::List { .. }) => {
-                LIST_STATE.lock()?.current_depth = 1;
-                self.parse_list()
+                self.list_state.current_depth = 1;
+                Ok(vec![self.parse_list()?])
             }
             Some(Token::Quote(_)) => self.parse_quote(),
             Some(Token::Table(_)) => Ok(vec![self.parse_table()?]),
             Some(Token::Codeblock(_)) => Ok(vec![self.parse_codeblock()?]),
+            Some(Token::Equation(_)) => Ok(vec![self.parse_equation()?]),
+            Some(Token::Footnote { .. }) => self.parse_footnote(),
+            Some(Token::LinkDefinition { .. }) => Ok(vec![self.parse_link_definition()?]),
             Some(Token::Image { .. }) => Ok(vec![self.parse_image()?]),
             Some(Token::Button { .. }) => Ok(vec![self.parse_button()?]),
             Some(Token::Text(_)) => Ok(vec![self.parse_paragraph()?]),
+            Some(Token::Error(_)) => {
+                match self.consume() {
+                    Some(Spanned { node: Token::Error(text), span }) =>
+                        Ok(vec![Spanned::new(ParseArtefact::Error(text), span)]),
+                    token => Err(self.unexpected_token("document", Token::Error(Cow::Borrowed("")), token)),
+                }
+            }
 			Some(Token::Newline) | None => {
-				self.consume();
-				Ok(vec![ParseArtefact::Newline])
+				let span = self.consume().map_or(self.eof, |token| token.span);
+				Ok(vec![Spanned::new(ParseArtefact::Newline, span)])
 			}
 			Some(Token::EOF) => {
 				self.consume();
@@ -120,390 +367,614 @@ impl Parser {
         }
     }
 
-    fn parse_text_artefacts(
-        &mut self,
-        text: String
-    ) -> Result<Vec<TextArtefact>, Box<dyn std::error::Error>> {
-        let mut artefacts = vec![];
-        *PARSE_LINE.lock()? = text.chars().rev().collect();
-        let next_char = || -> Result<Option<char>, Box<dyn std::error::Error>> {
-            Ok(PARSE_LINE.lock()?.last().cloned())
-        };
-        let consume_char = || -> Result<Option<char>, Box<dyn std::error::Error>> {
-            Ok(PARSE_LINE.lock()?.pop())
-        };
-        let consume_chars_while = |
-            f: fn(char) -> bool
-        | -> Result<String, Box<dyn std::error::Error>> {
-            let mut result = String::new();
-            while let Some(ch) = next_char()? {
-                if f(ch) {
-                    result.push(consume_char()?.unwrap());
-                } else {
-                    break;
-                }
-            }
-            Ok(result)
-        };
-        let mut current_string = String::new();
-        while let Some(ch) = next_char()? {
-            match ch {
-                '*' => {
-                    if current_string.len() > 0 {
-                        artefacts.push(TextArtefact::Raw(current_string));
-                        current_string = String::new();
-                    }
-                    consume_char()?;
-                    let bold_contents = consume_chars_while(|ch| ch != '*')?;
-                    consume_char()?;
-                    artefacts.push(TextArtefact::Bold(bold_contents));
-                }
-                '/' => {
-                    if current_string.len() > 0 {
-                        artefacts.push(TextArtefact::Raw(current_string));
-                        current_string = String::new();
-                    }
-                    consume_char()?;
-                    let italics_contents = consume_chars_while(|ch| ch != '/')?;
-                    consume_char()?;
-                    artefacts.push(TextArtefact::Italics(italics_contents));
-                }
-                '$' => {
-                    if current_string.len() > 0 {
-                        artefacts.push(TextArtefact::Raw(current_string));
-                        current_string = String::new();
-                    }
-                    consume_char()?;
-                    let code_contents = consume_chars_while(|ch| ch != '$')?;
-                    consume_char()?;
-                    artefacts.push(TextArtefact::Code(code_contents));
-                }
-                '!' => {
-                    consume_char()?;
-                    if let Some(ch) = next_char()? {
-                        if ch == '[' {
-                            if current_string.len() > 0 {
-                                artefacts.push(TextArtefact::Raw(current_string));
-                                current_string = String::new();
-                            }
-                            consume_char()?;
-                            let link_contents = consume_chars_while(|ch| ch != ']')?;
-                            consume_char()?;
-                            match consume_char()? {
-                                Some('(') => (),
-                                Some(ch) => {
-                                    return Err(
-                                        Box::new(
-                                            ParseError::UnexpectedChar(
-                                                String::from("INLINE_LINK"),
-                                                '(',
-                                                ch
-                                            )
-                                        )
-                                    );
-                                }
-                                None => {
-                                    return Err(Box::new(ParseError::UnexpectedEOL));
-                                }
-                            }
-                            let link_url = consume_chars_while(|ch| ch != ')')?;
-                            consume_char()?;
-                            artefacts.push(TextArtefact::Link(link_contents, link_url));
-                        } else {
-                            current_string.push('!');
-                        }
-                    } else {
-                        current_string.push('!');
-                    }
-                }
-                '~' => {
-                    if current_string.len() > 0 {
-                        artefacts.push(TextArtefact::Raw(current_string));
-                        current_string = String::new();
-                    }
-                    consume_char()?;
-                    let strikethrough_contents = consume_chars_while(|ch| ch != '~')?;
-                    consume_char()?;
-                    artefacts.push(TextArtefact::Strikethrough(strikethrough_contents));
-                }
-                '_' => {
-                    if current_string.len() > 0 {
-                        artefacts.push(TextArtefact::Raw(current_string));
-                        current_string = String::new();
-                    }
-                    consume_char()?;
-                    let underline_contents = consume_chars_while(|ch| ch != '_')?;
-                    consume_char()?;
-                    artefacts.push(TextArtefact::Underline(underline_contents));
-                }
-                _ => {
-                    current_string.push(consume_char()?.unwrap());
-                }
-            }
+    fn parse_text(&mut self, text: Cow<'a, str>, span: Span) -> Text<'a> {
+        match text {
+            Cow::Borrowed(text) => Text(InlineParser::new(text, span, &mut self.diagnostics).parse()),
+            Cow::Owned(text) => Text(InlineParser::new(&text, span, &mut self.diagnostics).parse()).into_owned(),
         }
-        if current_string.len() > 0 {
-            artefacts.push(TextArtefact::Raw(current_string));
+    }
+
+    fn unexpected_token(
+        &self,
+        location: &str,
+        expected: Token<'static>,
+        found: Option<Spanned<Token>>
+    ) -> Diagnostic {
+        match found {
+            Some(token) =>
+                ParseError::UnexpectedToken(location.to_string(), expected, token.node.into_owned(), token.span).into(),
+            None => ParseError::UnexpectedEOF(self.eof).into(),
         }
-        Ok(artefacts)
     }
 
-    fn parse_text(&mut self, text: String) -> Result<Text, Box<dyn std::error::Error>> {
-        Ok(Text(self.parse_text_artefacts(text)?))
+    /// Records the label of a heading, reporting labels that are already taken.
+    fn label_heading(&mut self, label: &Option<Cow<'a, str>>, span: Span) {
+        let Some(label) = label else {
+            return;
+        };
+        match self.section_state.labels.iter().find(|(defined, _)| defined == label) {
+            Some((_, first)) => self.diagnostics.push(
+                Diagnostic::error("E0015", format!("heading `{}` is defined more than once", label), span).with_note(
+                    format!("it is first defined on line {}", first.line)
+                )
+            ),
+            None => self.section_state.labels.push((label.to_string(), span)),
+        }
     }
 
-    fn parse_section(&mut self) -> Result<Vec<ParseArtefact>, Box<dyn std::error::Error>> {
-        let section_name = match self.consume() {
-            Some(Token::Section(name)) => name,
-            Some(token) => {
+    fn parse_section(&mut self) -> Result<Vec<Spanned<ParseArtefact<'a>>>, Diagnostic> {
+        let (section_name, label, span) = match self.consume() {
+            Some(Spanned { node: Token::Section(name, label), span }) => (name, label, span),
+            token => {
                 return Err(
-                    Box::new(
-                        ParseError::UnexpectedToken(
-                            "SECTION".to_string(),
-                            Token::Section("_section_header_".to_string()),
-                            token
-                        )
-                    )
+                    self.unexpected_token("section", Token::Section("_section_header_".into(), None), token)
                 );
             }
-            None => {
-                return Err(Box::new(ParseError::UnexpectedEOF));
-            }
         };
-        SECTION_STATE.lock()?.section_number += 1;
-        let section_number = SECTION_STATE.lock()?.section_number;
-        SECTION_STATE.lock()?.subsection_number = 0;
-        SECTION_STATE.lock()?.subsubsection_number = 0;
-        Ok(vec![ParseArtefact::Section(section_number, section_name)])
+        self.label_heading(&label, span);
+        let section_name = self.parse_text(section_name, span);
+        let state = &mut self.section_state;
+        state.section_number += 1;
+        state.subsection_number = 0;
+        state.subsubsection_number = 0;
+        Ok(vec![Spanned::new(ParseArtefact::Section(state.section_number, section_name, label), span)])
     }
 
-    fn parse_subsection(&mut self) -> Result<Vec<ParseArtefact>, Box<dyn std::error::Error>> {
-        let subsection_name = match self.consume() {
-            Some(Token::Subsection(name)) => name,
-            Some(token) => {
+    fn parse_subsection(&mut self) -> Result<Vec<Spanned<ParseArtefact<'a>>>, Diagnostic> {
+        let (subsection_name, label, span) = match self.consume() {
+            Some(Spanned { node: Token::Subsection(name, label), span }) => (name, label, span),
+            token => {
                 return Err(
-                    Box::new(
-                        ParseError::UnexpectedToken(
-                            "SUBSECTION".to_string(),
-                            Token::Subsection("_subsection_header_".to_string()),
-                            token
-                        )
+                    self.unexpected_token(
+                        "subsection",
+                        Token::Subsection("_subsection_header_".into(), None),
+                        token
                     )
                 );
             }
-            None => {
-                return Err(Box::new(ParseError::UnexpectedEOF));
-            }
         };
-        SECTION_STATE.lock()?.subsection_number += 1;
-        SECTION_STATE.lock()?.subsubsection_number = 0;
-		let section_number = SECTION_STATE.lock()?.section_number;
-        let subsection_number = SECTION_STATE.lock()?.subsection_number;
-        Ok(vec![ParseArtefact::Subsection(section_number, subsection_number, subsection_name)])
+        self.label_heading(&label, span);
+        let subsection_name = self.parse_text(subsection_name, span);
+        let state = &mut self.section_state;
+        // A subsection before any section is numbered as if it belonged to section 1
+        state.section_number = state.section_number.max(1);
+        state.subsection_number += 1;
+        state.subsubsection_number = 0;
+        Ok(
+            vec![
+                Spanned::new(
+                    ParseArtefact::Subsection(
+                        state.section_number,
+                        state.subsection_number,
+                        subsection_name,
+                        label
+                    ),
+                    span
+                )
+            ]
+        )
     }
 
-    fn parse_subsubsection(&mut self) -> Result<Vec<ParseArtefact>, Box<dyn std::error::Error>> {
-        let subsubsection_name = match self.consume() {
-            Some(Token::Subsubsection(name)) => name,
-            Some(token) => {
+    fn parse_subsubsection(&mut self) -> Result<Vec<Spanned<ParseArtefact<'a>>>, Diagnostic> {
+        let (subsubsection_name, label, span) = match self.consume() {
+            Some(Spanned { node: Token::Subsubsection(name, label), span }) => (name, label, span),
+            token => {
                 return Err(
-                    Box::new(
-                        ParseError::UnexpectedToken(
-                            "SUBSUBSECTION".to_string(),
-                            Token::Subsubsection("_subsubsection_header_".to_string()),
-                            token
-                        )
+                    self.unexpected_token(
+                        "subsubsection",
+                        Token::Subsubsection("_subsubsection_header_".into(), None),
+                        token
                     )
                 );
             }
-            None => {
-                return Err(Box::new(ParseError::UnexpectedEOF));
-            }
         };
-        SECTION_STATE.lock()?.subsubsection_number += 1;
-		let section_number = SECTION_STATE.lock()?.section_number;
-		let subsection_number = SECTION_STATE.lock()?.subsection_number;
-        let subsubsection_number = SECTION_STATE.lock()?.subsubsection_number;
-		Ok(vec![ParseArtefact::Subsubsection(section_number, subsection_number, subsubsection_number, subsubsection_name)])
+        self.label_heading(&label, span);
+        let subsubsection_name = self.parse_text(subsubsection_name, span);
+        let state = &mut self.section_state;
+        state.section_number = state.section_number.max(1);
+        state.subsection_number = state.subsection_number.max(1);
+        state.subsubsection_number += 1;
+        Ok(
+            vec![
+                Spanned::new(
+                    ParseArtefact::Subsubsection(
+                        state.section_number,
+                        state.subsection_number,
+                        state.subsubsection_number,
+                        subsubsection_name,
+                        label
+                    ),
+                    span
+                )
+            ]
+        )
     }
 
-    fn parse_list(&mut self) -> Result<Vec<ParseArtefact>, Box<dyn std::error::Error>> {
+    fn parse_list(&mut self) -> Result<Spanned<ParseArtefact<'a>>, Diagnostic> {
         let list_items = self.parse_list_items()?;
-        Ok(vec![ParseArtefact::List(list_items)])
+        let span = match (list_items.first(), list_items.last()) {
+            (Some(first), Some(last)) => first.span.to(last.span),
+            _ => self.eof,
+        };
+        Ok(Spanned::new(ParseArtefact::List(list_items), span))
     }
 
-    fn parse_list_items(&mut self) -> Result<Vec<ParseArtefact>, Box<dyn std::error::Error>> {
+    fn parse_list_items(&mut self) -> Result<Vec<Spanned<ParseArtefact<'a>>>, Diagnostic> {
         let mut list_items = vec![];
-        while let Some(Token::List { depth, text }) = self.next() {
-            let current_list_depth = LIST_STATE.lock()?.current_depth;
+        while let Some(Token::List { depth, .. }) = self.next() {
+            let current_list_depth = self.list_state.current_depth;
             if &current_list_depth == depth {
-                if let Some(Token::List { depth, text }) = self.consume() {
-                    list_items.push(ParseArtefact::ListItem(self.parse_text(text)?));
+                if let Some(Spanned { node: Token::List { text, .. }, span }) = self.consume() {
+                    list_items.push(Spanned::new(ParseArtefact::ListItem(self.parse_text(text, span)), span));
                 } else {
-                    return Err(Box::new(ParseError::UnexpectedEOF));
+                    return Err(ParseError::UnexpectedEOF(self.eof).into());
                 }
             } else if &current_list_depth < depth {
-                LIST_STATE.lock()?.current_depth += 1;
-                list_items.extend(self.parse_list()?);
+                self.list_state.current_depth += 1;
+                list_items.push(self.parse_list()?);
             } else {
-                LIST_STATE.lock()?.current_depth -= 1;
+                self.list_state.current_depth -= 1;
                 break;
             }
         }
         Ok(list_items)
     }
 
-    fn parse_quote(&mut self) -> Result<Vec<ParseArtefact>, Box<dyn std::error::Error>> {
-        let quote = match self.consume() {
-            Some(Token::Quote(quote)) => quote,
-            Some(token) => {
-                return Err(
-                    Box::new(
-                        ParseError::UnexpectedToken(
-                            "QUOTE".to_string(),
-                            Token::Quote("_quote_".to_string()),
-                            token
-                        )
-                    )
-                );
-            }
-            None => {
-                return Err(Box::new(ParseError::UnexpectedEOF));
+    fn parse_quote(&mut self) -> Result<Vec<Spanned<ParseArtefact<'a>>>, Diagnostic> {
+        let (quote, span) = match self.consume() {
+            Some(Spanned { node: Token::Quote(quote), span }) => (quote, span),
+            token => {
+                return Err(self.unexpected_token("quote", Token::Quote("_quote_".into()), token));
             }
         };
-        let text_contents = self.parse_text(quote)?;
-        Ok(vec![ParseArtefact::Quote(text_contents)])
+        let text_contents = self.parse_text(quote, span);
+        Ok(vec![Spanned::new(ParseArtefact::Quote(text_contents), span)])
     }
 
-    fn parse_table(&mut self) -> Result<ParseArtefact, Box<dyn std::error::Error>> {
-        let mut table_rows = vec![];
+    fn parse_table(&mut self) -> Result<Spanned<ParseArtefact<'a>>, Diagnostic> {
+        let mut table_rows: Vec<Spanned<ParseArtefact>> = vec![];
+        let mut header_columns = None;
         while let Some(Token::Table(_)) = self.next() {
-            if let Token::Table(columns) = self.consume().unwrap() {
+            if let Some(Spanned { node: Token::Table(columns), span }) = self.consume() {
+                let expected_columns = *header_columns.get_or_insert(columns.len());
+                if columns.len() != expected_columns {
+                    self.diagnostics.push(
+                        Diagnostic::warning(
+                            "W0001",
+                            format!(
+                                "table row has {} columns but the header row has {}",
+                                columns.len(),
+                                expected_columns
+                            ),
+                            span
+                        )
+                    );
+                }
                 let mut table_row = vec![];
                 for column in columns {
-                    table_row.push(self.parse_text(column)?);
+                    table_row.push(self.parse_text(column.node, column.span));
                 }
-                table_rows.push(ParseArtefact::TableRow(table_row));
+                table_rows.push(Spanned::new(ParseArtefact::TableRow(table_row), span));
             } else {
-                return Err(Box::new(ParseError::UnexpectedEOF));
+                return Err(ParseError::UnexpectedEOF(self.eof).into());
             }
         }
-        Ok(ParseArtefact::Table(table_rows))
+        let span = match (table_rows.first(), table_rows.last()) {
+            (Some(first), Some(last)) => first.span.to(last.span),
+            _ => self.eof,
+        };
+        Ok(Spanned::new(ParseArtefact::Table(table_rows), span))
     }
 
-    fn parse_paragraph(&mut self) -> Result<ParseArtefact, Box<dyn std::error::Error>> {
+    fn parse_paragraph(&mut self) -> Result<Spanned<ParseArtefact<'a>>, Diagnostic> {
         let mut text_contents = vec![];
-        while let Some(Token::Text(_)) = self.next() {
-            if let Token::Text(text) = self.consume().unwrap() {
-                text_contents.push(self.parse_text(text)?);
+        let mut paragraph_span: Option<Span> = None;
+        let mut last_line = None;
+        while let Some(Spanned { node: Token::Text(_), span }) = self.tokens.front() {
+            // A blank line between two lines of text starts a new paragraph
+            if last_line.is_some_and(|line| span.line > line + 1) {
+                break;
+            }
+            if let Some(Spanned { node: Token::Text(text), span }) = self.consume() {
+                last_line = Some(span.line);
+                paragraph_span = Some(paragraph_span.map_or(span, |start| start.to(span)));
+                text_contents.push(self.parse_text(text, span));
             } else {
-                return Err(Box::new(ParseError::UnexpectedEOF));
+                return Err(ParseError::UnexpectedEOF(self.eof).into());
             }
         }
-        Ok(ParseArtefact::Paragraph(text_contents))
+        Ok(Spanned::new(ParseArtefact::Paragraph(text_contents), paragraph_span.unwrap_or(self.eof)))
     }
 
-    fn parse_codeblock(&mut self) -> Result<ParseArtefact, Box<dyn std::error::Error>> {
+    fn parse_codeblock(&mut self) -> Result<Spanned<ParseArtefact<'a>>, Diagnostic> {
         match self.consume() {
-            Some(Token::Codeblock(codeblock_contents)) =>
-                Ok(ParseArtefact::Codeblock(codeblock_contents)),
-            Some(token) =>
-                Err(
-                    Box::new(
-                        ParseError::UnexpectedToken(
-                            "CODEBLOCK".to_string(),
-                            Token::Codeblock("_codeblock_".to_string()),
-                            token
-                        )
+            Some(Spanned { node: Token::Codeblock(codeblock_contents), span }) =>
+                Ok(Spanned::new(ParseArtefact::Codeblock(codeblock_contents), span)),
+            token => Err(self.unexpected_token("codeblock", Token::Codeblock("_codeblock_".into()), token)),
+        }
+    }
+
+    /// Parses a display equation, numbering it unless it has `\notag`.
+    fn parse_equation(&mut self) -> Result<Spanned<ParseArtefact<'a>>, Diagnostic> {
+        let (tex, span) = match self.consume() {
+            Some(Spanned { node: Token::Equation(tex), span }) => (tex, span),
+            token => {
+                return Err(self.unexpected_token("display equation", Token::Equation("_equation_".into()), token));
+            }
+        };
+        let state = &mut self.equation_state;
+        let numbered = match math::parse(&tex, true) {
+            Ok(formula) => {
+                if let Some((label, range)) = formula.label {
+                    let label_span = math::source_span(&tex, span, range);
+                    match state.labels.iter().find(|(defined, _)| defined == label) {
+                        Some((_, first)) => self.diagnostics.push(
+                            Diagnostic::error(
+                                "E0010",
+                                format!("equation label `{}` is defined more than once", label),
+                                label_span
+                            ).with_note(format!("it is first defined on line {}", first.line))
+                        ),
+                        None => state.labels.push((label.to_string(), label_span)),
+                    }
+                }
+                formula.numbered
+            }
+            Err(error) => {
+                self.diagnostics.push(math::diagnostic(&tex, span, error));
+                true
+            }
+        };
+        let number = numbered.then(|| {
+            state.equation_number += 1;
+            state.equation_number
+        });
+        Ok(Spanned::new(ParseArtefact::Equation(number, tex), span))
+    }
+
+    /// Parses a footnote definition. Definitions are kept aside until the whole
+    /// document is parsed, when they are numbered and gathered at its end.
+    fn parse_footnote(&mut self) -> Result<Vec<Spanned<ParseArtefact<'a>>>, Diagnostic> {
+        let (label, text, span) = match self.consume() {
+            Some(Spanned { node: Token::Footnote { label, text }, span }) => (label, text, span),
+            token => {
+                return Err(
+                    self.unexpected_token(
+                        "footnote",
+                        Token::Footnote {
+                            label: "_label_".into(),
+                            text: "_text_".into(),
+                        },
+                        token
+                    )
+                );
+            }
+        };
+        let text = self.parse_text(text, span);
+        match self.footnotes.iter().find(|(defined, _, _)| *defined == label) {
+            Some((_, _, first)) => self.diagnostics.push(
+                Diagnostic::error("E0012", format!("footnote `{}` is defined more than once", label), span).with_note(
+                    format!("it is first defined on line {}", first.line)
+                )
+            ),
+            None => self.footnotes.push((label, text, span)),
+        }
+        Ok(vec![])
+    }
+
+    /// Numbers the footnotes in the order they are first referred to, from the
+    /// document or from another footnote, and gathers them for the end of the document.
+    fn number_footnotes(&mut self, body: &[Spanned<ParseArtefact<'a>>]) -> Option<Spanned<ParseArtefact<'a>>> {
+        let definitions = std::mem::take(&mut self.footnotes);
+        let mut references = FootnoteReferences::default();
+        references.visit_artefacts(body);
+        let mut order = vec![];
+        let mut next = 0;
+        while let Some((label, span)) = references.references.get(next).cloned() {
+            next += 1;
+            match definitions.iter().position(|(defined, _, _)| *defined == label) {
+                Some(index) if order.contains(&index) => {}
+                Some(index) => {
+                    order.push(index);
+                    references.visit_text(&definitions[index].1);
+                }
+                None => self.diagnostics.push(
+                    Diagnostic::error("E0011", format!("footnote `{}` is never defined", label), span).with_note(
+                        format!("footnotes are defined on a line of their own, as `[^{}]: text`", label)
                     )
                 ),
-            None => Err(Box::new(ParseError::UnexpectedEOF)),
+            }
+        }
+        let span = definitions.first()?.2.to(definitions.last()?.2);
+        let mut numbered = vec![None; definitions.len()];
+        for (number, &index) in order.iter().enumerate() {
+            numbered[index] = Some(number + 1);
         }
+        let mut footnotes = definitions
+            .into_iter()
+            .zip(numbered)
+            .map(|((label, text, span), number)| (number, Spanned::new(ParseArtefact::Footnote(number, label, text), span)))
+            .collect::<Vec<_>>();
+        for (_, footnote) in footnotes.iter().filter(|(number, _)| number.is_none()) {
+            if let ParseArtefact::Footnote(_, label, _) = &footnote.node {
+                self.diagnostics.push(
+                    Diagnostic::warning("W0004", format!("footnote `{}` is never referred to", label), footnote.span)
+                        .with_note(format!("refer to it in the text with `[^{}]`", label))
+                );
+            }
+        }
+        // Unused footnotes go last, in the order they are defined
+        footnotes.sort_by_key(|(number, _)| number.unwrap_or(usize::MAX));
+        let footnotes = footnotes.into_iter().map(|(_, footnote)| footnote).collect();
+        Some(Spanned::new(ParseArtefact::Footnotes(footnotes), span))
+    }
+
+    fn parse_link_definition(&mut self) -> Result<Spanned<ParseArtefact<'a>>, Diagnostic> {
+        let (label, url, title, span) = match self.consume() {
+            Some(Spanned { node: Token::LinkDefinition { label, url, title }, span }) => (label, url, title, span),
+            token => {
+                return Err(
+                    self.unexpected_token(
+                        "link definition",
+                        Token::LinkDefinition {
+                            label: "_label_".into(),
+                            url: "_url_".into(),
+                            title: None,
+                        },
+                        token
+                    )
+                );
+            }
+        };
+        match self.links.iter().find(|(defined, _)| *defined == label) {
+            Some((_, first)) => self.diagnostics.push(
+                Diagnostic::error("E0014", format!("link `{}` is defined more than once", label), span).with_note(
+                    format!("it is first defined on line {}", first.line)
+                )
+            ),
+            None => self.links.push((label.to_string(), span)),
+        }
+        Ok(Spanned::new(ParseArtefact::LinkDefinition(label, url, title), span))
     }
 
-    fn parse_image(&mut self) -> Result<ParseArtefact, Box<dyn std::error::Error>> {
+    /// Reports references to links and headings that are never defined, and link
+    /// definitions that are never used.
+    fn check_references(&mut self, body: &[Spanned<ParseArtefact<'a>>]) {
+        let mut references = References::default();
+        references.visit_artefacts(body);
+        for (label, span) in &references.headings {
+            if !self.section_state.labels.iter().any(|(defined, _)| defined == label) {
+                self.diagnostics.push(
+                    Diagnostic::error("E0016", format!("heading `{}` is never defined", label), *span).with_note(
+                        format!("headings are labelled at the end of their title, as `@ Title {{#{}}}`", label)
+                    )
+                );
+            }
+        }
+        for (label, span) in &references.links {
+            if !self.links.iter().any(|(defined, _)| defined == label) {
+                self.diagnostics.push(
+                    Diagnostic::error("E0013", format!("link `{}` is never defined", label), *span).with_note(
+                        format!("links are defined on a line of their own, as `[{}]: url`", label)
+                    )
+                );
+            }
+        }
+        for (label, span) in &self.links {
+            if !references.links.iter().any(|(used, _)| used == label) {
+                self.diagnostics.push(
+                    Diagnostic::warning("W0005", format!("link `{}` is never referred to", label), *span)
+                        .with_note(format!("refer to it in the text with `![text][{}]`", label))
+                );
+            }
+        }
+    }
+
+    fn parse_image(&mut self) -> Result<Spanned<ParseArtefact<'a>>, Diagnostic> {
         match self.consume() {
-            Some(Token::Image { caption, path }) => Ok(ParseArtefact::Image(caption, path)),
-            Some(token) =>
+            Some(Spanned { node: Token::Image { caption, path }, span }) => {
+                let caption = self.parse_text(caption.node, caption.span);
+                Ok(Spanned::new(ParseArtefact::Image(caption, path), span))
+            }
+            token =>
                 Err(
-                    Box::new(
-                        ParseError::UnexpectedToken(
-                            "IMAGE".to_string(),
-                            Token::Image {
-                                caption: String::from("_caption_"),
-                                path: String::from("_path_"),
-                            },
-                            token
-                        )
+                    self.unexpected_token(
+                        "image",
+                        Token::Image {
+                            caption: Cow::from("_caption_").into(),
+                            path: "_path_".into(),
+                        },
+                        token
                     )
                 ),
-            None => Err(Box::new(ParseError::UnexpectedEOF)),
         }
     }
 
-    fn parse_button(&mut self) -> Result<ParseArtefact, Box<dyn std::error::Error>> {
+    fn parse_button(&mut self) -> Result<Spanned<ParseArtefact<'a>>, Diagnostic> {
         match self.consume() {
-            Some(Token::Button { text, link }) => Ok(ParseArtefact::Button(text, link)),
-            Some(token) =>
+            Some(Spanned { node: Token::Button { text, link }, span }) => {
+                let text = self.parse_text(text.node, text.span);
+                Ok(Spanned::new(ParseArtefact::Button(text, link), span))
+            }
+            token =>
                 Err(
-                    Box::new(
-                        ParseError::UnexpectedToken(
-                            "BUTTON".to_string(),
-                            Token::Button {
-                                text: String::from("_caption_"),
-                                link: String::from("_path_"),
-                            },
-                            token
-                        )
+                    self.unexpected_token(
+                        "button",
+                        Token::Button {
+                            text: Cow::from("_caption_").into(),
+                            link: "_path_".into(),
+                        },
+                        token
                     )
                 ),
-            None => Err(Box::new(ParseError::UnexpectedEOF)),
         }
     }
 }
 
 #[derive(Debug)]
 pub enum ParseError {
-    UnexpectedToken(String, Token, Token),
-    UnexpectedChar(String, char, char),
-    UnexpectedEOL,
-    UnexpectedEOF,
+    UnexpectedToken(String, Token<'static>, Token<'static>, Span),
+    UnexpectedChar(String, char, char, Span),
+    UnexpectedEOL(Span),
+    UnexpectedEOF(Span),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedToken(_, _, _, span) |
+            ParseError::UnexpectedChar(_, _, _, span) |
+            ParseError::UnexpectedEOL(span) |
+            ParseError::UnexpectedEOF(span) => *span,
+        }
+    }
 }
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            ParseError::UnexpectedChar(location, expected, found) =>
+            ParseError::UnexpectedChar(location, expected, found, _) =>
+                write!(f, "expected `{}` while parsing {}, found `{}`", expected, location, found),
+            ParseError::UnexpectedToken(location, expected, found, _) =>
                 write!(
                     f,
-                    "Unexpected char while parsing {}: expected {:?}, found {:?}",
+                    "expected {} while parsing {}, found {}",
+                    expected.describe(),
                     location,
-                    expected,
-                    found
+                    found.describe()
                 ),
-            ParseError::UnexpectedToken(location, expected, found) =>
-                write!(
-                    f,
-                    "Unexpected token while parsing {}: expected {:?}, found {:?}",
-                    location,
-                    expected,
-                    found
-                ),
-            ParseError::UnexpectedEOL => write!(f, "Unexpected end of line"),
-            ParseError::UnexpectedEOF => write!(f, "Unexpected EOF while parsing"),
+            ParseError::UnexpectedEOL(_) => write!(f, "unexpected end of line"),
+            ParseError::UnexpectedEOF(_) => write!(f, "unexpected end of file"),
+        }
+    }
+}
+
+impl ParseError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::UnexpectedToken(..) => "E0001",
+            ParseError::UnexpectedChar(..) => "E0002",
+            ParseError::UnexpectedEOL(_) => "E0003",
+            ParseError::UnexpectedEOF(_) => "E0004",
         }
     }
 }
 
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        Diagnostic::error(error.code(), error.to_string(), error.span())
+    }
+}
+
 impl std::error::Error for ParseError {}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::inline::INLINE_LINK_NOTE;
+
+    const MULTILEVEL_LIST: &str = "- Hello world!\n-- Hello world!\n-Hello world!";
+
+    const MULTILEVEL_LIST_WITH_PARAGRAPH: &str = "- Hello world!\n-- Hello world!\n-Hello world!\n\nHello world!";
+
+    const MULTILINE_TABLE: &str = "| Hello world! | Hello world! |\n| Hello world! | Hello world! |";
+
+    const MULTILINE_TABLE_WITH_PARAGRAPH: &str = "| Hello world! | Hello world! |\n| Hello world! | Hello world! |\n\nHello world!";
+
+    const MULTILINE_TABLE_WITH_LIST: &str = "| Hello world! | Hello world! |\n| Hello world! | Hello world! |\n\n- Hello world!";
+
+    const SAMPLE_DOCUMENT: &str = r#"@ Learning C++
+/ Learning Objectives: Learn the basics of C++ /
+
+@@ History of C++
+C++ was developed by Bjarne Stroustrup at Bell Labs in the 1980s. It was originally called C with Classes, but was renamed C++ in 1983. C++ is a superset of C, which means that all valid C programs are also valid C++ programs. C++ is an object-oriented language, which means that it is designed to allow programmers to create their own data types. C++ is a compiled language, which means that it must be translated into machine code before it can be run. C++ is a statically typed language, which means that the type of a variable must be known at compile time. C++ is a strongly typed language, which means that the type of a variable cannot be changed. C++ is a case-sensitive language, which means that the names of variables, functions, and classes are case-sensitive. C++ is a free-form language, which means that the programmer can choose how to format the code. C++ is a multi-paradigm language, which means that it supports multiple programming styles. C++ is a general-purpose language, which means that it can be used to write any type of program.
+
+> C++ was developed by Bjarne Stroustrup
+
+!bjarne-stroustrup.jpg!Bjarne Stroustrup at the 2012 C++Now conference.
+
+@@ Some Characterisitcs of C++
+- Object oriented
+- Compiled
+-- Translated into machine code
+- Statically typed
+- Strongly typed
+- Case-sensitive
+- Free-form
+- Multi-paradigm
+- General-purpose
+
+@@ Sample C++ Program
+$$$
+#include <iostream>
+
+int main() {
+	std::cout << "Hello, World!" << std::endl;
+	return 0;
+}
+$$$
+
+/ This program prints "Hello, World!" to the screen. /
+
+@@ Compiling C++ Programs
+C++ programs must be compiled before they can be run. The compiler translates the C++ code into machine code. The compiler is usually called g++ on Linux and Mac OS X, and cl on Windows. The compiler is usually invoked with the following command:
+$$$
+g++ -o program program.cpp
+$$$
+/ This command compiles the C++ program program.cpp and creates an executable program. /
+
+@@ Running C++ Programs
+C++ programs are run by executing the executable file that was created by the compiler. The executable file is usually called a.out on Linux and Mac OS X, and program.exe on Windows. The executable file is usually invoked with the following command:
+$$$
+./a.out
+$$$
+/ This command runs the C++ program a.out. /
+
+@@ Keywords
+C++ has a number of keywords that are reserved and cannot be used as variable names. The following is a list of the most commonly used keywords:
+| name | description |
+| int | integer |
+| float | floating-point number |
+| double | double-precision floating-point number |
+| char | character |
+| bool | boolean |
+| void | no return value |
+| true | boolean true |
+| false | boolean false |
+| if | if statement |
+| else | else statement |
+| while | while loop |
+| for | for loop |
+| break | break statement |
+| continue | continue statement |
+| return | return statement |"#;
+
+    const CODE_BLOCK_DOCUMENT: &str = r#"@ Code Block
+$$$
+// Sample javascript express server
+const express = require('express');
+const app = express();
+const port = 3000;
+
+app.use(express.json());
+
+app.get('/', (req, res) => {
+	res.send('Hello World!');
+});
+
+app.listen(port, () => {
+	console.log(`Example app listening at http://localhost:${port}`);
+});
+
+$$$
+"#;
+
+    const NESTED_HEADINGS: &str = "@ One\n@@ One.One\n@@@ One.One.One\n@ Two\n@@ Two.One\n- Item\n-- Nested";
 
     #[test]
     fn parse_empty() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from(""));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         assert_eq!(parse_result, Vec::<ParseArtefact>::new());
@@ -512,8 +983,8 @@ mod tests {
 
     #[test]
     fn parse_text() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("Hello world!"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("Hello world!");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -521,7 +992,7 @@ mod tests {
             parse_result,
             vec![
                 ParseArtefact::Paragraph(
-                    vec![Text(vec![TextArtefact::Raw(String::from("Hello world!"))])]
+                    vec![Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])]
                 )
             ]
         );
@@ -531,8 +1002,8 @@ mod tests {
     // Style tests
     #[test]
     fn parse_bold() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("*Hello world!*"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("*Hello world!*");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -540,7 +1011,7 @@ mod tests {
             parse_result,
             vec![
                 ParseArtefact::Paragraph(
-                    vec![Text(vec![TextArtefact::Bold(String::from("Hello world!"))])]
+                    vec![Text(vec![TextArtefact::Bold(Text::from("Hello world!")).into()])]
                 )
             ]
         );
@@ -549,8 +1020,8 @@ mod tests {
 
     #[test]
     fn parse_italics() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("/Hello world!/"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("/Hello world!/");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -558,7 +1029,7 @@ mod tests {
             parse_result,
             vec![
                 ParseArtefact::Paragraph(
-                    vec![Text(vec![TextArtefact::Italics(String::from("Hello world!"))])]
+                    vec![Text(vec![TextArtefact::Italics(Text::from("Hello world!")).into()])]
                 )
             ]
         );
@@ -567,8 +1038,8 @@ mod tests {
 
     #[test]
     fn parse_underline() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("_Hello world!_"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("_Hello world!_");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -576,7 +1047,7 @@ mod tests {
             parse_result,
             vec![
                 ParseArtefact::Paragraph(
-                    vec![Text(vec![TextArtefact::Underline(String::from("Hello world!"))])]
+                    vec![Text(vec![TextArtefact::Underline(Text::from("Hello world!")).into()])]
                 )
             ]
         );
@@ -585,8 +1056,8 @@ mod tests {
 
     #[test]
     fn parse_strikethrough() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("~Hello world!~"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("~Hello world!~");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -594,7 +1065,7 @@ mod tests {
             parse_result,
             vec![
                 ParseArtefact::Paragraph(
-                    vec![Text(vec![TextArtefact::Strikethrough(String::from("Hello world!"))])]
+                    vec![Text(vec![TextArtefact::Strikethrough(Text::from("Hello world!")).into()])]
                 )
             ]
         );
@@ -603,8 +1074,8 @@ mod tests {
 
     #[test]
     fn parse_code() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("$Hello world!$"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("$Hello world!$");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -612,7 +1083,7 @@ mod tests {
             parse_result,
             vec![
                 ParseArtefact::Paragraph(
-                    vec![Text(vec![TextArtefact::Code(String::from("Hello world!"))])]
+                    vec![Text(vec![TextArtefact::Code(Cow::from("Hello world!")).into()])]
                 )
             ]
         );
@@ -621,8 +1092,8 @@ mod tests {
 
     #[test]
     fn parse_link() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("![Hello world!](https://example.com)"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("![Hello world!](https://example.com)");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -634,9 +1105,9 @@ mod tests {
                         Text(
                             vec![
                                 TextArtefact::Link(
-                                    String::from("Hello world!"),
-                                    String::from("https://example.com")
-                                )
+                                    Text::from("Hello world!"),
+                                    Cow::from("https://example.com")
+                                ).into()
                             ]
                         )
                     ]
@@ -648,8 +1119,8 @@ mod tests {
 
     #[test]
     fn parse_image() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("#[Hello world!](https://example.com)"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("#[Hello world!](https://example.com)");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -657,8 +1128,8 @@ mod tests {
             parse_result,
             vec![
                 ParseArtefact::Image(
-                    String::from("Hello world!"),
-                    String::from("https://example.com")
+                    Text::from("Hello world!"),
+                    Cow::from("https://example.com")
                 )
             ]
         );
@@ -667,44 +1138,62 @@ mod tests {
 
     #[test]
     fn parse_section() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("@ Hello world!"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("@ Hello world!");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
-        assert_eq!(parse_result, vec![ParseArtefact::Section(1, String::from("Hello world!"))]);
+        assert_eq!(parse_result, vec![ParseArtefact::Section(1, Text::from("Hello world!"), None)]);
         Ok(())
     }
 
     #[test]
     fn parse_subsection() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("@@ Hello world!"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("@@ Hello world!");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
-        assert_eq!(parse_result, vec![ParseArtefact::Subsection(1, 1, String::from("Hello world!"))]);
+        assert_eq!(parse_result, vec![ParseArtefact::Subsection(1, 1, Text::from("Hello world!"), None)]);
         Ok(())
     }
 
     #[test]
     fn parse_subsubsection() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("@@@ Hello world!"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("@@@ Hello world!");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
         assert_eq!(
             parse_result,
-            vec![ParseArtefact::Subsubsection(1, 1, 1, String::from("Hello world!"))]
+            vec![ParseArtefact::Subsubsection(1, 1, 1, Text::from("Hello world!"), None)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_headings_before_first_section() -> Result<(), Box<dyn std::error::Error>> {
+        let lexer = Lexer::new("@@ Intro\n@@@ Scope\n@ First\n@@ Details");
+        let tokens = lexer.tokenize();
+        let mut parser = Parser::new(tokens);
+        let parse_result = parser.parse()?;
+        assert_eq!(
+            parse_result,
+            vec![
+                ParseArtefact::Subsection(1, 1, Text::from("Intro"), None),
+                ParseArtefact::Subsubsection(1, 1, 1, Text::from("Scope"), None),
+                ParseArtefact::Section(2, Text::from("First"), None),
+                ParseArtefact::Subsection(2, 1, Text::from("Details"), None)
+            ]
         );
         Ok(())
     }
 
     #[test]
     fn parse_list() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("- Hello world!"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("- Hello world!");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -714,8 +1203,8 @@ mod tests {
                 ParseArtefact::List(
                     vec![
                         ParseArtefact::ListItem(
-                            Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
-                        )
+                            Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
+                        ).into()
                     ]
                 )
             ]
@@ -725,8 +1214,8 @@ mod tests {
 
     #[test]
     fn parse_multilevel_list() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("- Hello world!\n-- Hello world!\n-Hello world!"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new(MULTILEVEL_LIST);
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -736,18 +1225,18 @@ mod tests {
                 ParseArtefact::List(
                     vec![
                         ParseArtefact::ListItem(
-                            Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
-                        ),
+                            Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
+                        ).into(),
                         ParseArtefact::List(
                             vec![
                                 ParseArtefact::ListItem(
-                                    Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
-                                )
+                                    Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
+                                ).into()
                             ]
-                        ),
+                        ).into(),
                         ParseArtefact::ListItem(
-                            Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
-                        )
+                            Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
+                        ).into()
                     ]
                 )
             ]
@@ -757,10 +1246,8 @@ mod tests {
 
     #[test]
     fn parse_multilevel_list_with_paragraph() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(
-            String::from("- Hello world!\n-- Hello world!\n-Hello world!\n\nHello world!")
-        );
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new(MULTILEVEL_LIST_WITH_PARAGRAPH);
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -770,22 +1257,22 @@ mod tests {
                 ParseArtefact::List(
                     vec![
                         ParseArtefact::ListItem(
-                            Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
-                        ),
+                            Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
+                        ).into(),
                         ParseArtefact::List(
                             vec![
                                 ParseArtefact::ListItem(
-                                    Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
-                                )
+                                    Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
+                                ).into()
                             ]
-                        ),
+                        ).into(),
                         ParseArtefact::ListItem(
-                            Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
-                        )
+                            Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
+                        ).into()
                     ]
                 ),
                 ParseArtefact::Paragraph(
-                    vec![Text(vec![TextArtefact::Raw(String::from("Hello world!"))])]
+                    vec![Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])]
                 )
             ]
         );
@@ -794,8 +1281,8 @@ mod tests {
 
     #[test]
     fn parse_table() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(String::from("| Hello world! | Hello world! |"));
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new("| Hello world! | Hello world! |");
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -806,10 +1293,10 @@ mod tests {
                     vec![
                         ParseArtefact::TableRow(
                             vec![
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))]),
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()]),
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
                             ]
-                        )
+                        ).into()
                     ]
                 )
             ]
@@ -819,10 +1306,8 @@ mod tests {
 
     #[test]
     fn parse_multiline_table() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(
-            String::from("| Hello world! | Hello world! |\n| Hello world! | Hello world! |")
-        );
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new(MULTILINE_TABLE);
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -833,16 +1318,16 @@ mod tests {
                     vec![
                         ParseArtefact::TableRow(
                             vec![
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))]),
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()]),
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
                             ]
-                        ),
+                        ).into(),
                         ParseArtefact::TableRow(
                             vec![
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))]),
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()]),
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
                             ]
-                        )
+                        ).into()
                     ]
                 )
             ]
@@ -852,12 +1337,8 @@ mod tests {
 
     #[test]
     fn parse_multiline_table_with_paragraph() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(
-            String::from(
-                "| Hello world! | Hello world! |\n| Hello world! | Hello world! |\n\nHello world!"
-            )
-        );
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new(MULTILINE_TABLE_WITH_PARAGRAPH);
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -868,20 +1349,20 @@ mod tests {
                     vec![
                         ParseArtefact::TableRow(
                             vec![
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))]),
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()]),
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
                             ]
-                        ),
+                        ).into(),
                         ParseArtefact::TableRow(
                             vec![
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))]),
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()]),
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
                             ]
-                        )
+                        ).into()
                     ]
                 ),
                 ParseArtefact::Paragraph(
-                    vec![Text(vec![TextArtefact::Raw(String::from("Hello world!"))])]
+                    vec![Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])]
                 )
             ]
         );
@@ -890,12 +1371,8 @@ mod tests {
 
     #[test]
     fn parse_multiline_table_with_list() -> Result<(), Box<dyn std::error::Error>> {
-        let mut lexer = Lexer::new(
-            String::from(
-                "| Hello world! | Hello world! |\n| Hello world! | Hello world! |\n\n- Hello world!"
-            )
-        );
-        let mut tokens = lexer.tokenize()?;
+        let lexer = Lexer::new(MULTILINE_TABLE_WITH_LIST);
+        let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let parse_result = parser.parse()?;
         println!("{:?}", parse_result);
@@ -906,23 +1383,23 @@ mod tests {
                     vec![
                         ParseArtefact::TableRow(
                             vec![
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))]),
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()]),
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
                             ]
-                        ),
+                        ).into(),
                         ParseArtefact::TableRow(
                             vec![
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))]),
-                                Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()]),
+                                Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
                             ]
-                        )
+                        ).into()
                     ]
                 ),
                 ParseArtefact::List(
                     vec![
                         ParseArtefact::ListItem(
-                            Text(vec![TextArtefact::Raw(String::from("Hello world!"))])
-                        )
+                            Text(vec![TextArtefact::Raw(Cow::from("Hello world!")).into()])
+                        ).into()
                     ]
                 )
             ]
@@ -932,114 +1409,439 @@ mod tests {
 
     #[test]
     fn sample_document_no_assert() {
-        let mut lexer = Lexer::new(
-            String::from(
-                r#"@ Learning C++
-/ Learning Objectives: Learn the basics of C++ /
+        let lexer = Lexer::new(SAMPLE_DOCUMENT);
+        let tokens = lexer.tokenize();
+        let mut parser = Parser::new(tokens);
+        let parse_result = parser.parse().unwrap();
+        println!("{:?}", parse_result);
+    }
 
-@@ History of C++
-C++ was developed by Bjarne Stroustrup at Bell Labs in the 1980s. It was originally called C with Classes, but was renamed C++ in 1983. C++ is a superset of C, which means that all valid C programs are also valid C++ programs. C++ is an object-oriented language, which means that it is designed to allow programmers to create their own data types. C++ is a compiled language, which means that it must be translated into machine code before it can be run. C++ is a statically typed language, which means that the type of a variable must be known at compile time. C++ is a strongly typed language, which means that the type of a variable cannot be changed. C++ is a case-sensitive language, which means that the names of variables, functions, and classes are case-sensitive. C++ is a free-form language, which means that the programmer can choose how to format the code. C++ is a multi-paradigm language, which means that it supports multiple programming styles. C++ is a general-purpose language, which means that it can be used to write any type of program.
+    #[test]
+    fn sample_document() {
+        let lexer = Lexer::new(CODE_BLOCK_DOCUMENT);
+        let tokens = lexer.tokenize();
+        let mut parser = Parser::new(tokens);
+        let parse_result = parser.parse().unwrap();
+        println!("{:?}", parse_result);
+    }
 
-> C++ was developed by Bjarne Stroustrup
+	#[test]
+	fn italics() {
+		let lexer = Lexer::new("@ Learning C++\n/ This is italics /\n");
+		let tokens = lexer.tokenize();
+		let mut parser = Parser::new(tokens);
+		let parse_result = parser.parse().unwrap();
+		println!("{:?}", parse_result);
+	}
 
-!bjarne-stroustrup.jpg!Bjarne Stroustrup at the 2012 C++Now conference.
+	#[test]
+	fn parse_is_reentrant() -> Result<(), Box<dyn std::error::Error>> {
+		let source = NESTED_HEADINGS;
+		let mut parser = Parser::new(Lexer::new(source).tokenize());
+		let first = parser.parse()?;
+		let mut parser = Parser::new(Lexer::new(source).tokenize());
+		let second = parser.parse()?;
+		assert_eq!(first, second);
+		assert_eq!(first[3], ParseArtefact::Section(2, Text::from("Two"), None));
+		Ok(())
+	}
 
-@@ Some Characterisitcs of C++
-- Object oriented
-- Compiled
--- Translated into machine code
-- Statically typed
-- Strongly typed
-- Case-sensitive
-- Free-form
-- Multi-paradigm
-- General-purpose
+	#[test]
+	fn parse_concurrently() {
+		let expected = vec![
+			ParseArtefact::Section(1, Text::from("Intro"), None),
+			ParseArtefact::Subsection(1, 1, Text::from("Background"), None),
+			ParseArtefact::Subsubsection(1, 1, 1, Text::from("Details"), None),
+			ParseArtefact::Section(2, Text::from("Usage"), None),
+			ParseArtefact::Subsection(2, 1, Text::from("Setup"), None),
+			ParseArtefact::Paragraph(
+				vec![
+					Text(
+						vec![
+							TextArtefact::Raw(Cow::from("Some ")).into(),
+							TextArtefact::Bold(Text::from("bold")).into(),
+							TextArtefact::Raw(Cow::from(" text")).into()
+						]
+					)
+				]
+			)
+		];
+		let source = "@ Intro\n@@ Background\n@@@ Details\n@ Usage\n@@ Setup\nSome *bold* text";
+		let handles = (0..16)
+			.map(|_| {
+				std::thread::spawn(move || {
+					(0..50)
+						.map(|_| {
+							let tokens = Lexer::new(source).tokenize();
+							Parser::new(tokens).parse().unwrap()
+						})
+						.collect::<Vec<Vec<Spanned<ParseArtefact>>>>()
+				})
+			})
+			.collect::<Vec<_>>();
+		for handle in handles {
+			for parsed in handle.join().unwrap() {
+				assert_eq!(parsed, expected);
+			}
+		}
+	}
 
-@@ Sample C++ Program
-$$$
-#include <iostream>
+	#[test]
+	fn parse_spans() -> Result<(), Box<dyn std::error::Error>> {
+		let tokens = Lexer::new("@ Title\n> Say *hi* ![there](x)").tokenize();
+		let parse_result = Parser::new(tokens).parse()?;
+		assert_eq!(parse_result[0].span, Span::new(2, 7, 1, 3));
+		if let ParseArtefact::Quote(Text(artefacts)) = &parse_result[1].node {
+			assert_eq!(parse_result[1].span, Span::new(10, 30, 2, 3));
+			assert_eq!(artefacts[0].span, Span::new(10, 14, 2, 3));
+			assert_eq!(artefacts[1].span, Span::new(14, 18, 2, 7));
+			assert_eq!(artefacts[3].span, Span::new(19, 30, 2, 12));
+		} else {
+			panic!("expected a quote, found {:?}", parse_result[1]);
+		}
+		Ok(())
+	}
 
-int main() {
-	std::cout << "Hello, World!" << std::endl;
-	return 0;
-}
-$$$
+	#[test]
+	fn parse_error_span() -> Result<(), Box<dyn std::error::Error>> {
+		let tokens = Lexer::new("Intro\nSee ![text]x").tokenize();
+		let error = Parser::new(tokens).parse().unwrap_err();
+		assert_eq!(error.code, "E0002");
+		assert_eq!(error.span, Span::new(17, 18, 2, 12));
+		assert_eq!(error.message, "expected `(` while parsing inline link, found `x`");
+		assert_eq!(error.notes, vec![INLINE_LINK_NOTE]);
+		Ok(())
+	}
 
-/ This program prints "Hello, World!" to the screen. /
+	#[test]
+	fn parse_recovers_from_errors() -> Result<(), Box<dyn std::error::Error>> {
+		let tokens = Lexer::new("*open bold\nSee ![text]x and ![y](z)\n| a | b |\n| c |").tokenize();
+		let (parse_result, diagnostics) = Parser::new(tokens).parse_with_diagnostics();
+		assert_eq!(
+			parse_result,
+			vec![
+				ParseArtefact::Paragraph(
+					vec![
+						Text(vec![TextArtefact::Raw(Cow::from("*open bold")).into()]),
+						Text(
+							vec![
+								TextArtefact::Raw(Cow::from("See ")).into(),
+								TextArtefact::Error(Cow::from("![text]")).into(),
+								TextArtefact::Raw(Cow::from("x and ")).into(),
+								TextArtefact::Link(Text::from("y"), Cow::from("z")).into()
+							]
+						)
+					]
+				),
+				ParseArtefact::Table(
+					vec![
+						ParseArtefact::TableRow(
+							vec![
+								Text(vec![TextArtefact::Raw(Cow::from("a")).into()]),
+								Text(vec![TextArtefact::Raw(Cow::from("b")).into()])
+							]
+						).into(),
+						ParseArtefact::TableRow(
+							vec![Text(vec![TextArtefact::Raw(Cow::from("c")).into()])]
+						).into()
+					]
+				)
+			]
+		);
+		let codes = diagnostics
+			.iter()
+			.map(|diagnostic| (diagnostic.code, diagnostic.span.line, diagnostic.span.column))
+			.collect::<Vec<_>>();
+		assert_eq!(codes, vec![("W0002", 1, 1), ("E0002", 2, 12), ("W0001", 4, 1)]);
+		Ok(())
+	}
 
-@@ Compiling C++ Programs
-C++ programs must be compiled before they can be run. The compiler translates the C++ code into machine code. The compiler is usually called g++ on Linux and Mac OS X, and cl on Windows. The compiler is usually invoked with the following command:
-$$$
-g++ -o program program.cpp
-$$$
-/ This command compiles the C++ program program.cpp and creates an executable program. /
+	#[test]
+	fn parse_paragraphs_split_on_blank_lines() {
+		let tokens = Lexer::new("First line\nstill first\n\n\nSecond").tokenize();
+		let parse_result = Parser::new(tokens).parse().unwrap();
+		assert_eq!(
+			parse_result,
+			vec![
+				ParseArtefact::Paragraph(
+					vec![
+						Text(vec![TextArtefact::Raw(Cow::from("First line")).into()]),
+						Text(vec![TextArtefact::Raw(Cow::from("still first")).into()])
+					]
+				),
+				ParseArtefact::Paragraph(vec![Text(vec![TextArtefact::Raw(Cow::from("Second")).into()])])
+			]
+		);
+	}
 
-@@ Running C++ Programs
-C++ programs are run by executing the executable file that was created by the compiler. The executable file is usually called a.out on Linux and Mac OS X, and program.exe on Windows. The executable file is usually invoked with the following command:
-$$$
-./a.out
-$$$
-/ This command runs the C++ program a.out. /
+	#[test]
+	fn parse_into_owned() {
+		let owned: Vec<ParseArtefact<'static>> = {
+			let source = String::from("@ Title\n> A *bold* quote");
+			let tokens = Lexer::new(&source).tokenize();
+			let parsed = Parser::new(tokens).parse().unwrap();
+			assert!(
+				matches!(
+					&parsed[0].node,
+					ParseArtefact::Section(_, Text(title), _) if matches!(title[0].node, TextArtefact::Raw(Cow::Borrowed(_)))
+				)
+			);
+			parsed.into_iter().map(|artefact| artefact.node.into_owned()).collect()
+		};
+		assert_eq!(
+			owned,
+			vec![
+				ParseArtefact::Section(1, Text::from("Title"), None),
+				ParseArtefact::Quote(
+					Text(
+						vec![
+							TextArtefact::Raw(Cow::from("A ")).into(),
+							TextArtefact::Bold(Text::from("bold")).into(),
+							TextArtefact::Raw(Cow::from(" quote")).into()
+						]
+					)
+				)
+			]
+		);
+	}
 
-@@ Keywords
-C++ has a number of keywords that are reserved and cannot be used as variable names. The following is a list of the most commonly used keywords:
-| name | description |
-| int | integer |
-| float | floating-point number |
-| double | double-precision floating-point number |
-| char | character |
-| bool | boolean |
-| void | no return value |
-| true | boolean true |
-| false | boolean false |
-| if | if statement |
-| else | else statement |
-| while | while loop |
-| for | for loop |
-| break | break statement |
-| continue | continue statement |
-| return | return statement |"#
-            )
-        );
-        let mut tokens = lexer.tokenize().unwrap();
-        let mut parser = Parser::new(tokens);
-        let parse_result = parser.parse().unwrap();
-        println!("{:?}", parse_result);
-    }
+	#[test]
+	fn sample_documents_round_trip_through_formatter() {
+		for sample in [
+			MULTILEVEL_LIST,
+			MULTILEVEL_LIST_WITH_PARAGRAPH,
+			MULTILINE_TABLE,
+			MULTILINE_TABLE_WITH_PARAGRAPH,
+			MULTILINE_TABLE_WITH_LIST,
+			SAMPLE_DOCUMENT,
+			CODE_BLOCK_DOCUMENT,
+			NESTED_HEADINGS,
+		] {
+			crate::formatter::assert_round_trip(sample);
+		}
+	}
 
-    #[test]
-    fn sample_document() {
-        let mut lexer = Lexer::new(
-            String::from(
-                r#"@ Code Block
-$$$
-// Sample javascript express server
-const express = require('express');
-const app = express();
-const port = 3000;
+	#[test]
+	fn parse_escaped_block_markers() {
+		let source = "\\@ Not a heading\n\\- not a list\n\\> not a quote\n\\#[not](an image)\n| a \\| b | c |";
+		let (body, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		assert!(diagnostics.is_empty());
+		let line = |text| Text(vec![TextArtefact::Raw(Cow::from(text)).into()]);
+		assert_eq!(
+			body,
+			vec![
+				ParseArtefact::Paragraph(
+					vec![line("@ Not a heading"), line("- not a list"), line("> not a quote"), line("#[not](an image)")]
+				),
+				ParseArtefact::Table(vec![ParseArtefact::TableRow(vec![line("a | b"), line("c")]).into()])
+			]
+		);
+		let (_, diagnostics) = Lexer::new("| a \\|").tokenize_with_diagnostics();
+		assert_eq!(diagnostics[0].code, "E0007");
+	}
 
-app.use(express.json());
+	#[test]
+	fn parse_equations() {
+		let source = "%%%\nE = mc^2 \\label{energy}\n%%%\n%%%\nx \\notag\n%%%\n%%%\na^2 + b^2 = c^2\n%%%\nBy \\(\\eqref{energy}\\).";
+		let (body, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		assert!(diagnostics.is_empty());
+		let numbers = body
+			.iter()
+			.filter_map(|artefact| match &artefact.node {
+				ParseArtefact::Equation(number, _) => Some(*number),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(numbers, vec![Some(1), None, Some(2)]);
+		assert_eq!(
+			body[3].node,
+			ParseArtefact::Paragraph(
+				vec![
+					Text(
+						vec![
+							TextArtefact::Raw(Cow::from("By ")).into(),
+							TextArtefact::Math(Cow::from("\\eqref{energy}")).into(),
+							TextArtefact::Raw(Cow::from(".")).into()
+						]
+					)
+				]
+			)
+		);
+	}
 
-app.get('/', (req, res) => {
-	res.send('Hello World!');
-});
+	#[test]
+	fn parse_equation_diagnostics() {
+		let source = "%%%\na \\label{x}\n%%%\n%%%\nb \\label{x}\n%%%\nSee \\(\\ref{y}\\) and \\(\\frac{1}\\)";
+		let (_, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		let codes = diagnostics
+			.iter()
+			.map(|diagnostic| (diagnostic.code, diagnostic.span.line, diagnostic.span.column))
+			.collect::<Vec<_>>();
+		assert_eq!(codes, vec![("E0010", 5, 10), ("E0009", 7, 23), ("W0003", 7, 12)]);
+	}
 
-app.listen(port, () => {
-	console.log(`Example app listening at http://localhost:${port}`);
-});
+	#[test]
+	fn parse_footnotes() {
+		let source = "See[^b] and[^a], then[^b].\n[^a]: First[^c]\n\n[^b]: Second\n[^unused]: Unused\n[^c]: Nested";
+		let (body, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		let codes = diagnostics.iter().map(|diagnostic| (diagnostic.code, diagnostic.span.line)).collect::<Vec<_>>();
+		assert_eq!(codes, vec![("W0004", 5)]);
+		let footnote = |number, label, text| ParseArtefact::Footnote(number, Cow::from(label), text);
+		assert_eq!(
+			body.last().unwrap().node,
+			ParseArtefact::Footnotes(
+				vec![
+					footnote(Some(1), "b", Text::from("Second")).into(),
+					footnote(
+						Some(2),
+						"a",
+						Text(
+							vec![
+								TextArtefact::Raw(Cow::from("First")).into(),
+								TextArtefact::FootnoteReference(Cow::from("c")).into()
+							]
+						)
+					).into(),
+					footnote(Some(3), "c", Text::from("Nested")).into(),
+					footnote(None, "unused", Text::from("Unused")).into()
+				]
+			)
+		);
+		assert_eq!(body.len(), 2);
+	}
 
-$$$
-"#
-            )
-        );
-    }
+	#[test]
+	fn parse_footnote_errors() {
+		let source = "Text[^missing]\n[^a]: One[^a]\n[^a]: Two";
+		let (_, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		let codes = diagnostics
+			.iter()
+			.map(|diagnostic| (diagnostic.code, diagnostic.span.line, diagnostic.span.column))
+			.collect::<Vec<_>>();
+		assert_eq!(codes, vec![("E0012", 3, 7), ("E0011", 1, 5), ("W0004", 2, 7)]);
+	}
 
 	#[test]
-	fn italics() {
-		let mut lexer = Lexer::new(String::from("@ Learning C++\n/ This is italics /\n"));
-		let mut tokens = lexer.tokenize().unwrap();
-		let mut parser = Parser::new(tokens);
-		let parse_result = parser.parse().unwrap();
-		println!("{:?}", parse_result);
+	fn parse_heading_labels() {
+		let source = "See [@setup].\n@ Intro {#intro}\n@@ Setup {#setup}\n@@@ Plain";
+		let (body, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		assert!(diagnostics.is_empty());
+		let nodes = body.into_iter().map(|artefact| artefact.node).collect::<Vec<_>>();
+		assert_eq!(
+			nodes,
+			vec![
+				ParseArtefact::Paragraph(
+					vec![
+						Text(
+							vec![
+								TextArtefact::Raw(Cow::from("See ")).into(),
+								TextArtefact::HeadingReference(Cow::from("setup")).into(),
+								TextArtefact::Raw(Cow::from(".")).into()
+							]
+						)
+					]
+				),
+				ParseArtefact::Section(1, Text::from("Intro"), Some(Cow::from("intro"))),
+				ParseArtefact::Subsection(1, 1, Text::from("Setup"), Some(Cow::from("setup"))),
+				ParseArtefact::Subsubsection(1, 1, 1, Text::from("Plain"), None)
+			]
+		);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn parse_markup_in_headings_captions_and_buttons() {
+		let source = "@ Using *Rust* /today/\n#[A $cat$](cat.png)\n#![Go ~now~]{/go}\n@@ Un *closed";
+		let (body, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		let codes = diagnostics
+			.iter()
+			.map(|diagnostic| (diagnostic.code, diagnostic.span.line, diagnostic.span.column))
+			.collect::<Vec<_>>();
+		assert_eq!(codes, vec![("W0002", 4, 7)]);
+		let nodes = body.into_iter().map(|artefact| artefact.node).collect::<Vec<_>>();
+		assert_eq!(
+			nodes,
+			vec![
+				ParseArtefact::Section(
+					1,
+					Text(
+						vec![
+							TextArtefact::Raw(Cow::from("Using ")).into(),
+							TextArtefact::Bold(Text::from("Rust")).into(),
+							TextArtefact::Raw(Cow::from(" ")).into(),
+							TextArtefact::Italics(Text::from("today")).into()
+						]
+					),
+					None
+				),
+				ParseArtefact::Image(
+					Text(
+						vec![
+							TextArtefact::Raw(Cow::from("A ")).into(),
+							TextArtefact::Code(Cow::from("cat")).into()
+						]
+					),
+					Cow::from("cat.png")
+				),
+				ParseArtefact::Button(
+					Text(
+						vec![
+							TextArtefact::Raw(Cow::from("Go ")).into(),
+							TextArtefact::Strikethrough(Text::from("now")).into()
+						]
+					),
+					Cow::from("/go")
+				),
+				ParseArtefact::Subsection(1, 1, Text::from("Un *closed"), None)
+			]
+		);
+	}
+
+	#[test]
+	fn parse_heading_label_errors() {
+		let source = "@ One {#a}\n@@ Two {#a}\nSee [@b].";
+		let (_, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		let codes = diagnostics
+			.iter()
+			.map(|diagnostic| (diagnostic.code, diagnostic.span.line, diagnostic.span.column))
+			.collect::<Vec<_>>();
+		assert_eq!(codes, vec![("E0015", 2, 4), ("E0016", 3, 5)]);
+	}
+
+	#[test]
+	fn parse_link_definitions() {
+		let source = "See ![the docs][docs].\n[docs]: https://a.org \"The docs\"";
+		let (body, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		assert!(diagnostics.is_empty());
+		assert_eq!(
+			body[0].node,
+			ParseArtefact::Paragraph(
+				vec![
+					Text(
+						vec![
+							TextArtefact::Raw(Cow::from("See ")).into(),
+							TextArtefact::LinkReference(Text::from("the docs"), Cow::from("docs")).into(),
+							TextArtefact::Raw(Cow::from(".")).into()
+						]
+					)
+				]
+			)
+		);
+		assert_eq!(
+			body[1].node,
+			ParseArtefact::LinkDefinition(Cow::from("docs"), Cow::from("https://a.org"), Some(Cow::from("The docs")))
+		);
+		assert_eq!(body.len(), 2);
+	}
+
+	#[test]
+	fn parse_link_definition_errors() {
+		let source = "A ![b][missing]\n[a]: /one\n[a]: /two";
+		let (_, diagnostics) = Parser::new(Lexer::new(source).tokenize()).parse_with_diagnostics();
+		let codes = diagnostics
+			.iter()
+			.map(|diagnostic| (diagnostic.code, diagnostic.span.line, diagnostic.span.column))
+			.collect::<Vec<_>>();
+		assert_eq!(codes, vec![("E0014", 3, 1), ("E0013", 1, 3), ("W0005", 2, 1)]);
+	}
+}
+
@@ -0,0 +1,91 @@
+use std::fmt;
+use serde::{ Deserialize, Serialize };
+use std::ops::Deref;
+
+/// A region of the source text: byte offsets `start..end`, plus the 1-based
+/// line and column (counted in characters) of `start`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+	pub start: usize,
+	pub end: usize,
+	pub line: usize,
+	pub column: usize,
+}
+
+impl Span {
+	pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+		Self { start, end, line, column }
+	}
+
+	/// Returns a span starting where `self` starts and ending where `other` ends.
+	pub fn to(&self, other: Span) -> Span {
+		Span { end: other.end, ..*self }
+	}
+
+	pub fn len(&self) -> usize {
+		self.end - self.start
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.start == self.end
+	}
+}
+
+impl fmt::Display for Span {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}", self.line, self.column)
+	}
+}
+
+/// A node together with the span of source it was built from.
+///
+/// Spans are ignored when comparing, so the same document laid out
+/// differently in the source still compares equal.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Spanned<T> {
+	pub node: T,
+	pub span: Span,
+}
+
+impl<T> Spanned<T> {
+	pub fn new(node: T, span: Span) -> Self {
+		Self { node, span }
+	}
+
+	pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+		Spanned { node: f(self.node), span: self.span }
+	}
+}
+
+impl<T> From<T> for Spanned<T> {
+	fn from(node: T) -> Self {
+		Self { node, span: Span::default() }
+	}
+}
+
+impl<T> Deref for Spanned<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.node
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for Spanned<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.node.fmt(f)?;
+		write!(f, " @ {}..{}", self.span.start, self.span.end)
+	}
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+	fn eq(&self, other: &Self) -> bool {
+		self.node == other.node
+	}
+}
+
+impl<T: PartialEq> PartialEq<T> for Spanned<T> {
+	fn eq(&self, other: &T) -> bool {
+		&self.node == other
+	}
+}
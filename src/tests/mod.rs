@@ -9,17 +9,270 @@ fn test_load() -> Result<(), Box<dyn std::error::Error>> {
 	Ok(())
 }
 
+#[test]
+fn test_load_titles_page_from_file_name() -> Result<(), Error> {
+	let dir = std::env::temp_dir().join(format!("waygum-titles-{}.d", std::process::id()));
+	std::fs::create_dir_all(&dir)?;
+	for (name, title) in [("notes.wg", "notes"), ("notes", "notes"), ("notes.v2.wg", "notes.v2")] {
+		let path = dir.join(name);
+		std::fs::write(&path, "Text")?;
+		let path = path.to_string_lossy().into_owned();
+		let html = document::Document::load(&path, None)?.convert_to_html()?;
+		assert!(html.contains(&format!("<title>{}</title>", title)), "{}: {}", name, html);
+	}
+	std::fs::remove_dir_all(dir)?;
+	Ok(())
+}
+
 #[test]
 fn test_lexer() {
-	let mut lexer = lexer::Lexer::new(String::from(r#"@ Learning C++
+	let lexer = lexer::Lexer::new(r#"@ Learning C++
 
 This is a sample learning C++ document. 
 
-/ This is an italics comment. /"#));
-	let tokens = lexer.tokenize().unwrap();
+/ This is an italics comment. /"#);
+	let tokens = lexer.tokenize();
 	println!("{:?}", tokens);
 	println!();
 	let mut parser = parser::Parser::new(tokens);
 	let parsed = parser.parse().unwrap();
 	println!("{:?}", parsed);
-}
\ No newline at end of file
+}
+#[test]
+fn test_render() -> Result<(), Error> {
+	let options = Options {
+		title: String::from("Post"),
+		style: Some(String::from("body { margin: 0; }")),
+	};
+	let html = render("*bold* text", &options)?;
+	assert!(html.contains("<title>Post</title>"));
+	assert!(html.contains("body { margin: 0; }"));
+	assert!(html.contains("<p><strong>bold</strong> text</p>"));
+	Ok(())
+}
+
+#[test]
+fn test_render_nested_styles() -> Result<(), Error> {
+	let html = render("*bold /and italic/* ![the _docs_](docs.html)", &Options::default())?;
+	assert!(
+		html.contains(
+			"<p><strong>bold <em>and italic</em></strong> <a href=\"docs.html\">the <u>docs</u></a></p>"
+		)
+	);
+	Ok(())
+}
+
+#[test]
+fn test_render_math() -> Result<(), Error> {
+	let html = render("%%%\nE = mc^2 \\label{energy}\n%%%\nBy \\(\\eqref{energy}\\)", &Options::default())?;
+	assert!(
+		html.contains(
+			"<div class=\"equation\" id=\"eq-energy\"><math display=\"block\"><mrow><mi>E</mi><mo>=</mo><mi>m</mi><msup><mi>c</mi><mn>2</mn></msup></mrow></math><span class=\"equation-number\">(1)</span></div>"
+		)
+	);
+	assert!(html.contains("<p>By <a href=\"#eq-energy\">(1)</a></p>"));
+	Ok(())
+}
+
+#[test]
+fn test_render_footnotes() -> Result<(), Error> {
+	let html = render("Text[^1] and[^1].\n\n[^1]: A *note*", &Options::default())?;
+	assert!(
+		html.contains(
+			"<p>Text<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1\">1</a></sup> and<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1-2\">1</a></sup>.</p>"
+		)
+	);
+	assert!(
+		html.contains(
+			"<section class=\"footnotes\"><ol><li id=\"fn-1\">A <strong>note</strong> <a href=\"#fnref-1\" class=\"footnote-backref\">↩</a> <a href=\"#fnref-1-2\" class=\"footnote-backref\">↩</a></li></ol></section></div>"
+		)
+	);
+	Ok(())
+}
+
+#[test]
+fn test_render_superscript_subscript_mark_and_kbd() -> Result<(), Error> {
+	let html = render("x^2^ H,,2,,O ==new== ++Ctrl+C++", &Options::default())?;
+	assert!(html.contains("<p>x<sup>2</sup> H<sub>2</sub>O <mark>new</mark> <kbd>Ctrl+C</kbd></p>"));
+	Ok(())
+}
+
+#[test]
+fn test_render_markup_in_headings_captions_and_buttons() -> Result<(), Error> {
+	let html = render("@ Using *Rust* {#rust}\n@@ /Cargo/, briefly {#cargo}\n#[A $cat$](cat.png)\n#![Go ~now~]{/go}\nSee [@rust] and [@cargo].", &Options::default())?;
+	assert!(html.contains("<h1 id=\"sec-rust\">1 Using <strong>Rust</strong></h1>"));
+	assert!(html.contains("<h2 id=\"sec-cargo\">1.1 <em>Cargo</em>, briefly</h2>"));
+	assert!(html.contains("<img src=\"cat.png\" alt=\"A cat\" /><p class=\"img-caption\">A <code>cat</code></p>"));
+	assert!(html.contains("<a href=\"/go\" class=\"md-button\">Go <del>now</del></a>"));
+	assert!(html.contains("<a href=\"#sec-rust\">1 Using Rust</a> and <a href=\"#sec-cargo\">1.1 Cargo, briefly</a>.</p>"));
+	Ok(())
+}
+
+#[test]
+fn test_render_heading_references() -> Result<(), Error> {
+	let html = render("@ Intro\n@@ Getting started {#start}\nAs [@start] shows.\n\nSee [@start], or ([@start]).", &Options::default())?;
+	assert!(html.contains("<h1>1 Intro</h1><h2 id=\"sec-start\">1.1 Getting started</h2>"));
+	assert!(html.contains("<p>As <a href=\"#sec-start\">1.1 Getting started</a> shows.</p>"));
+	assert!(
+		html.contains(
+			"<p>See <a href=\"#sec-start\">1.1 Getting started</a>, or (<a href=\"#sec-start\">1.1 Getting started</a>).</p>"
+		)
+	);
+	let html = render("@ Vec<T> & $Box<T>$ {#boxes}\nSee [@boxes].", &Options::default())?;
+	assert!(html.contains("<p>See <a href=\"#sec-boxes\">1 Vec&lt;T&gt; &amp; Box&lt;T&gt;</a>.</p>"));
+	Ok(())
+}
+
+#[test]
+fn test_render_link_references() -> Result<(), Error> {
+	let html = render("Read ![the docs][docs] and ![this][d].\n\n[docs]: https://a.org \"Docs\"\n[d]: /d", &Options::default())?;
+	assert!(
+		html.contains(
+			"<p>Read <a href=\"https://a.org\" title=\"Docs\">the docs</a> and <a href=\"/d\">this</a>.</p>"
+		)
+	);
+	assert!(!html.contains("[docs]"));
+	let html = render("See ![this][q].\n\n[q]: /q?a=1&b=2 \"Say \"hi\" <now>\"", &Options::default())?;
+	assert!(html.contains("<a href=\"/q?a=1&amp;b=2\" title=\"Say &quot;hi&quot; &lt;now&gt;\">this</a>"));
+	Ok(())
+}
+
+#[test]
+fn test_render_inline_images_and_autolinks() -> Result<(), Error> {
+	let html = render("Built #[passing](ci.svg) by a@b.org, see https://b.org.", &Options::default())?;
+	assert!(
+		html.contains(
+			"<p>Built <img src=\"ci.svg\" alt=\"passing\" /> by <a href=\"mailto:a@b.org\">a@b.org</a>, see <a href=\"https://b.org\">https://b.org</a>.</p>"
+		)
+	);
+	Ok(())
+}
+
+#[test]
+fn test_render_to_writer() -> Result<(), Error> {
+	let mut output = Vec::new();
+	render_to_writer("> Quote".as_bytes(), &mut output, &Options::default())?;
+	let html = String::from_utf8(output).unwrap();
+	assert!(html.contains("<title>Untitled</title>"));
+	assert!(html.contains("<blockquote>Quote</blockquote>"));
+	Ok(())
+}
+
+#[test]
+fn test_render_reports_every_error() {
+	match render("*open bold\n\n| a | b\n\nSee ![text]x", &Options::default()) {
+		Err(Error::Parse(diagnostics)) => {
+			let codes = diagnostics.iter().map(|diagnostic| diagnostic.code).collect::<Vec<_>>();
+			assert_eq!(codes, vec!["W0002", "E0007", "E0002"]);
+		}
+		result => panic!("expected parse errors, got {:?}", result),
+	}
+}
+
+#[test]
+fn test_format() -> Result<(), Error> {
+	let source = std::fs::read_to_string("./src/tests/test.wg")?;
+	let formatted = format(&source)?;
+	assert_eq!(format(&formatted)?, formatted);
+	let options = Options::default();
+	assert_eq!(render(&formatted, &options)?, render(&source, &options)?);
+	assert!(matches!(format("See ![text]x"), Err(Error::Parse(_))));
+	assert_eq!(format("*open bold")?, "\\*open bold\n");
+	assert_eq!(format("$$$ rust\nx\n$$$\n[^a]: A\nSee[^a]")?, "$$$ rust\nx\n$$$\n\n[^a]: A\n\nSee[^a]\n");
+	Ok(())
+}
+
+#[test]
+fn test_write_html_streams_document() -> Result<(), Error> {
+	let source = "@ Title\n- *One*\n- Two\n\n| a | b |\n| c | d |";
+	let mut doc = Document::new(source, "Streamed", None);
+	doc.parse()?;
+	let mut output = Vec::new();
+	doc.write_html(&mut output)?;
+	let html = String::from_utf8(output).unwrap();
+	assert_eq!(html, doc.to_html());
+	assert!(
+		html.contains(
+			"<h1>1 Title</h1><ol><li><strong>One</strong></li><li>Two</li></ol><table><thead><tr><th>a</th><th>b</th></tr><thead><tbody><tr><td>c</td><td>d</td></tr></tbody></table>"
+		)
+	);
+	Ok(())
+}
+
+#[test]
+fn test_json_round_trip() -> Result<(), Error> {
+	let source = "@ Title\n- *One* ![link](url)\n\n| a | b |\n$$$\ncode\n$$$";
+	let tokens = lexer::Lexer::new(source).tokenize();
+	let json = serde_json::to_string(&tokens)?;
+	let read_tokens: Vec<Spanned<Token>> = serde_json::from_str(&json)?;
+	assert_eq!(read_tokens, tokens);
+	assert_eq!(read_tokens[0].span, tokens[0].span);
+
+	let mut doc = Document::new(source, "Post", None);
+	doc.parse()?;
+	let json = serde_json::to_string(doc.body())?;
+	let body: Vec<Spanned<ParseArtefact>> = serde_json::from_str(&json)?;
+	assert_eq!(body, doc.body());
+	assert_eq!(Document::from_body(body, "Post", None).to_html(), doc.to_html());
+	Ok(())
+}
+
+#[test]
+fn test_invalid_json_ast() {
+	match serde_json::from_str::<Vec<Spanned<ParseArtefact>>>(r#"[{"node": {"Chapter": "One"}}]"#) {
+		Err(err) => assert!(Error::from(err).to_string().starts_with("invalid JSON: unknown variant `Chapter`")),
+		Ok(body) => panic!("expected an error, got {:?}", body),
+	}
+}
+
+/// Writes an executable shell script to a temporary file and returns its path.
+#[cfg(unix)]
+fn filter_script(name: &str, body: &str) -> String {
+	use std::os::unix::fs::PermissionsExt;
+	let path = std::env::temp_dir().join(format!("waygum-{}-{}.sh", name, std::process::id()));
+	std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+	std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+	path.to_string_lossy().into_owned()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_filters() -> Result<(), Error> {
+	let link_tickets = filter_script("tickets", r#"sed 's/"Raw":"WG-\([0-9]*\)"/"Link":[[{"node":{"Raw":"WG-\1"},"span":{"start":0,"end":0,"line":1,"column":1}}],"https:\/\/tracker\/\1"]/g'"#);
+	let mut doc = Document::new("> WG-42", "Post", None);
+	doc.parse()?;
+	doc.apply_filter("cat")?;
+	doc.apply_filter(&link_tickets)?;
+	doc.apply_filter(r#"sed -e 's/tracker\/42/tracker\/43/'"#)?;
+	assert_eq!(
+		doc.body(),
+		vec![ParseArtefact::Quote(Text(vec![TextArtefact::Link("WG-42".into(), "https://tracker/43".into()).into()]))]
+	);
+	std::fs::remove_file(link_tickets).unwrap();
+	Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_filter_errors() {
+	let mut doc = Document::new("Text", "Post", None);
+	doc.parse().unwrap();
+	let failing = filter_script("failing", "exit 3");
+	let cases = [
+		("./no-such-filter", "could not be started"),
+		("sed 's/a/b/", "is not a valid command line"),
+		("", "is not a valid command line"),
+		(failing.as_str(), "failed with exit status: 3"),
+		("echo", "wrote an invalid parse tree"),
+	];
+	for (command, message) in cases {
+		match doc.apply_filter(command) {
+			Err(err @ Error::Filter { .. }) => {
+				let err = err.to_string();
+				assert!(err.starts_with(&format!("filter `{}` {}", command, message)), "{}", err);
+			}
+			result => panic!("expected a filter error, got {:?}", result),
+		}
+	}
+	std::fs::remove_file(failing).unwrap();
+}
@@ -0,0 +1,504 @@
+//! Traversal of the parse tree.
+//!
+//! `Visitor` walks a tree by shared reference and `VisitorMut` by mutable
+//! reference. Every method has a default that visits the node's children (or
+//! does nothing for leaves), so a pass only overrides the nodes it cares about.
+//! An override can still reach the children by calling the matching `walk_*`
+//! function.
+
+use std::borrow::Cow;
+use super::parser::{ ParseArtefact, Text, TextArtefact };
+use super::span::Spanned;
+
+pub trait Visitor<'ast> {
+    fn visit_artefacts(&mut self, artefacts: &'ast [Spanned<ParseArtefact<'_>>]) {
+        walk_artefacts(self, artefacts)
+    }
+
+    fn visit_artefact(&mut self, artefact: &'ast ParseArtefact<'_>) {
+        walk_artefact(self, artefact)
+    }
+
+    fn visit_section(&mut self, _number: usize, title: &'ast Text<'_>, _label: Option<&'ast str>) {
+        self.visit_text(title)
+    }
+
+    fn visit_subsection(&mut self, _numbers: (usize, usize), title: &'ast Text<'_>, _label: Option<&'ast str>) {
+        self.visit_text(title)
+    }
+
+    fn visit_subsubsection(
+        &mut self,
+        _numbers: (usize, usize, usize),
+        title: &'ast Text<'_>,
+        _label: Option<&'ast str>
+    ) {
+        self.visit_text(title)
+    }
+
+    fn visit_paragraph(&mut self, lines: &'ast [Text<'_>]) {
+        walk_texts(self, lines)
+    }
+
+    fn visit_list(&mut self, items: &'ast [Spanned<ParseArtefact<'_>>]) {
+        self.visit_artefacts(items)
+    }
+
+    fn visit_list_item(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_quote(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_table(&mut self, rows: &'ast [Spanned<ParseArtefact<'_>>]) {
+        self.visit_artefacts(rows)
+    }
+
+    fn visit_table_row(&mut self, columns: &'ast [Text<'_>]) {
+        walk_texts(self, columns)
+    }
+
+    fn visit_button(&mut self, text: &'ast Text<'_>, _link: &'ast str) {
+        self.visit_text(text)
+    }
+
+    fn visit_image(&mut self, caption: &'ast Text<'_>, _path: &'ast str) {
+        self.visit_text(caption)
+    }
+
+    fn visit_codeblock(&mut self, _code: &'ast str) {}
+
+    fn visit_equation(&mut self, _number: Option<usize>, _tex: &'ast str) {}
+
+    fn visit_footnotes(&mut self, footnotes: &'ast [Spanned<ParseArtefact<'_>>]) {
+        self.visit_artefacts(footnotes)
+    }
+
+    fn visit_footnote(&mut self, _number: Option<usize>, _label: &'ast str, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_link_definition(&mut self, _label: &'ast str, _url: &'ast str, _title: Option<&'ast str>) {}
+
+    fn visit_newline(&mut self) {}
+
+    fn visit_error(&mut self, _text: &'ast str) {}
+
+    fn visit_text(&mut self, text: &'ast Text<'_>) {
+        walk_text(self, text)
+    }
+
+    fn visit_text_artefact(&mut self, artefact: &'ast TextArtefact<'_>) {
+        walk_text_artefact(self, artefact)
+    }
+
+    fn visit_raw(&mut self, _text: &'ast str) {}
+
+    fn visit_bold(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_italics(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_link(&mut self, text: &'ast Text<'_>, _url: &'ast str) {
+        self.visit_text(text)
+    }
+
+    fn visit_link_reference(&mut self, text: &'ast Text<'_>, _label: &'ast str) {
+        self.visit_text(text)
+    }
+
+    fn visit_strikethrough(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_underline(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_code(&mut self, _text: &'ast str) {}
+
+    fn visit_math(&mut self, _tex: &'ast str) {}
+
+    fn visit_footnote_reference(&mut self, _label: &'ast str) {}
+
+    fn visit_heading_reference(&mut self, _label: &'ast str) {}
+
+    fn visit_superscript(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_subscript(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_mark(&mut self, text: &'ast Text<'_>) {
+        self.visit_text(text)
+    }
+
+    fn visit_kbd(&mut self, _keys: &'ast str) {}
+
+    fn visit_inline_image(&mut self, _alt: &'ast str, _src: &'ast str) {}
+
+    fn visit_text_error(&mut self, _text: &'ast str) {}
+}
+
+pub fn walk_artefacts<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    artefacts: &'ast [Spanned<ParseArtefact<'_>>]
+) {
+    for artefact in artefacts {
+        visitor.visit_artefact(&artefact.node);
+    }
+}
+
+pub fn walk_texts<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, texts: &'ast [Text<'_>]) {
+    for text in texts {
+        visitor.visit_text(text);
+    }
+}
+
+/// Dispatches to the `visit_*` method for the artefact's variant.
+pub fn walk_artefact<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, artefact: &'ast ParseArtefact<'_>) {
+    match artefact {
+        ParseArtefact::Section(number, title, label) => visitor.visit_section(*number, title, label.as_deref()),
+        ParseArtefact::Subsection(section, number, title, label) =>
+            visitor.visit_subsection((*section, *number), title, label.as_deref()),
+        ParseArtefact::Subsubsection(section, subsection, number, title, label) =>
+            visitor.visit_subsubsection((*section, *subsection, *number), title, label.as_deref()),
+        ParseArtefact::Paragraph(lines) => visitor.visit_paragraph(lines),
+        ParseArtefact::List(items) => visitor.visit_list(items),
+        ParseArtefact::ListItem(text) => visitor.visit_list_item(text),
+        ParseArtefact::Quote(text) => visitor.visit_quote(text),
+        ParseArtefact::Table(rows) => visitor.visit_table(rows),
+        ParseArtefact::TableRow(columns) => visitor.visit_table_row(columns),
+        ParseArtefact::Button(text, link) => visitor.visit_button(text, link),
+        ParseArtefact::Image(caption, path) => visitor.visit_image(caption, path),
+        ParseArtefact::Codeblock(code) => visitor.visit_codeblock(code),
+        ParseArtefact::Equation(number, tex) => visitor.visit_equation(*number, tex),
+        ParseArtefact::Footnotes(footnotes) => visitor.visit_footnotes(footnotes),
+        ParseArtefact::Footnote(number, label, text) => visitor.visit_footnote(*number, label, text),
+        ParseArtefact::LinkDefinition(label, url, title) => visitor.visit_link_definition(label, url, title.as_deref()),
+        ParseArtefact::Newline => visitor.visit_newline(),
+        ParseArtefact::Error(text) => visitor.visit_error(text),
+    }
+}
+
+pub fn walk_text<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, text: &'ast Text<'_>) {
+    for artefact in &text.0 {
+        visitor.visit_text_artefact(&artefact.node);
+    }
+}
+
+/// Dispatches to the `visit_*` method for the text artefact's variant.
+pub fn walk_text_artefact<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, artefact: &'ast TextArtefact<'_>) {
+    match artefact {
+        TextArtefact::Raw(text) => visitor.visit_raw(text),
+        TextArtefact::Bold(text) => visitor.visit_bold(text),
+        TextArtefact::Italics(text) => visitor.visit_italics(text),
+        TextArtefact::Link(text, url) => visitor.visit_link(text, url),
+        TextArtefact::LinkReference(text, label) => visitor.visit_link_reference(text, label),
+        TextArtefact::Strikethrough(text) => visitor.visit_strikethrough(text),
+        TextArtefact::Underline(text) => visitor.visit_underline(text),
+        TextArtefact::Code(text) => visitor.visit_code(text),
+        TextArtefact::Math(tex) => visitor.visit_math(tex),
+        TextArtefact::FootnoteReference(label) => visitor.visit_footnote_reference(label),
+        TextArtefact::HeadingReference(label) => visitor.visit_heading_reference(label),
+        TextArtefact::Superscript(text) => visitor.visit_superscript(text),
+        TextArtefact::Subscript(text) => visitor.visit_subscript(text),
+        TextArtefact::Mark(text) => visitor.visit_mark(text),
+        TextArtefact::Kbd(keys) => visitor.visit_kbd(keys),
+        TextArtefact::Image(alt, src) => visitor.visit_inline_image(alt, src),
+        TextArtefact::Error(text) => visitor.visit_text_error(text),
+    }
+}
+
+/// Like `Visitor`, but with mutable access to the tree. `visit_artefacts_mut`
+/// and `visit_text_mut` get the containing `Vec`, so a pass can remove or
+/// insert nodes as well as edit them in place.
+pub trait VisitorMut<'a> {
+    fn visit_artefacts_mut(&mut self, artefacts: &mut Vec<Spanned<ParseArtefact<'a>>>) {
+        walk_artefacts_mut(self, artefacts)
+    }
+
+    fn visit_artefact_mut(&mut self, artefact: &mut ParseArtefact<'a>) {
+        walk_artefact_mut(self, artefact)
+    }
+
+    fn visit_section_mut(&mut self, _number: &mut usize, title: &mut Text<'a>, _label: &mut Option<Cow<'a, str>>) {
+        self.visit_text_mut(title)
+    }
+
+    fn visit_subsection_mut(
+        &mut self,
+        _numbers: (&mut usize, &mut usize),
+        title: &mut Text<'a>,
+        _label: &mut Option<Cow<'a, str>>
+    ) {
+        self.visit_text_mut(title)
+    }
+
+    fn visit_subsubsection_mut(
+        &mut self,
+        _numbers: (&mut usize, &mut usize, &mut usize),
+        title: &mut Text<'a>,
+        _label: &mut Option<Cow<'a, str>>
+    ) {
+        self.visit_text_mut(title)
+    }
+
+    fn visit_paragraph_mut(&mut self, lines: &mut Vec<Text<'a>>) {
+        walk_texts_mut(self, lines)
+    }
+
+    fn visit_list_mut(&mut self, items: &mut Vec<Spanned<ParseArtefact<'a>>>) {
+        self.visit_artefacts_mut(items)
+    }
+
+    fn visit_list_item_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_quote_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_table_mut(&mut self, rows: &mut Vec<Spanned<ParseArtefact<'a>>>) {
+        self.visit_artefacts_mut(rows)
+    }
+
+    fn visit_table_row_mut(&mut self, columns: &mut Vec<Text<'a>>) {
+        walk_texts_mut(self, columns)
+    }
+
+    fn visit_button_mut(&mut self, text: &mut Text<'a>, _link: &mut Cow<'a, str>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_image_mut(&mut self, caption: &mut Text<'a>, _path: &mut Cow<'a, str>) {
+        self.visit_text_mut(caption)
+    }
+
+    fn visit_codeblock_mut(&mut self, _code: &mut Cow<'a, str>) {}
+
+    fn visit_equation_mut(&mut self, _number: &mut Option<usize>, _tex: &mut Cow<'a, str>) {}
+
+    fn visit_footnotes_mut(&mut self, footnotes: &mut Vec<Spanned<ParseArtefact<'a>>>) {
+        self.visit_artefacts_mut(footnotes)
+    }
+
+    fn visit_footnote_mut(&mut self, _number: &mut Option<usize>, _label: &mut Cow<'a, str>, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_link_definition_mut(
+        &mut self,
+        _label: &mut Cow<'a, str>,
+        _url: &mut Cow<'a, str>,
+        _title: &mut Option<Cow<'a, str>>
+    ) {}
+
+    fn visit_newline_mut(&mut self) {}
+
+    fn visit_error_mut(&mut self, _text: &mut Cow<'a, str>) {}
+
+    fn visit_text_mut(&mut self, text: &mut Text<'a>) {
+        walk_text_mut(self, text)
+    }
+
+    fn visit_text_artefact_mut(&mut self, artefact: &mut TextArtefact<'a>) {
+        walk_text_artefact_mut(self, artefact)
+    }
+
+    fn visit_raw_mut(&mut self, _text: &mut Cow<'a, str>) {}
+
+    fn visit_bold_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_italics_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_link_mut(&mut self, text: &mut Text<'a>, _url: &mut Cow<'a, str>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_link_reference_mut(&mut self, text: &mut Text<'a>, _label: &mut Cow<'a, str>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_strikethrough_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_underline_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_code_mut(&mut self, _text: &mut Cow<'a, str>) {}
+
+    fn visit_math_mut(&mut self, _tex: &mut Cow<'a, str>) {}
+
+    fn visit_footnote_reference_mut(&mut self, _label: &mut Cow<'a, str>) {}
+
+    fn visit_heading_reference_mut(&mut self, _label: &mut Cow<'a, str>) {}
+
+    fn visit_superscript_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_subscript_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_mark_mut(&mut self, text: &mut Text<'a>) {
+        self.visit_text_mut(text)
+    }
+
+    fn visit_kbd_mut(&mut self, _keys: &mut Cow<'a, str>) {}
+
+    fn visit_inline_image_mut(&mut self, _alt: &mut Cow<'a, str>, _src: &mut Cow<'a, str>) {}
+
+    fn visit_text_error_mut(&mut self, _text: &mut Cow<'a, str>) {}
+}
+
+pub fn walk_artefacts_mut<'a, V: VisitorMut<'a> + ?Sized>(
+    visitor: &mut V,
+    artefacts: &mut Vec<Spanned<ParseArtefact<'a>>>
+) {
+    for artefact in artefacts {
+        visitor.visit_artefact_mut(&mut artefact.node);
+    }
+}
+
+pub fn walk_texts_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, texts: &mut Vec<Text<'a>>) {
+    for text in texts {
+        visitor.visit_text_mut(text);
+    }
+}
+
+/// Dispatches to the `visit_*_mut` method for the artefact's variant.
+pub fn walk_artefact_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, artefact: &mut ParseArtefact<'a>) {
+    match artefact {
+        ParseArtefact::Section(number, title, label) => visitor.visit_section_mut(number, title, label),
+        ParseArtefact::Subsection(section, number, title, label) =>
+            visitor.visit_subsection_mut((section, number), title, label),
+        ParseArtefact::Subsubsection(section, subsection, number, title, label) =>
+            visitor.visit_subsubsection_mut((section, subsection, number), title, label),
+        ParseArtefact::Paragraph(lines) => visitor.visit_paragraph_mut(lines),
+        ParseArtefact::List(items) => visitor.visit_list_mut(items),
+        ParseArtefact::ListItem(text) => visitor.visit_list_item_mut(text),
+        ParseArtefact::Quote(text) => visitor.visit_quote_mut(text),
+        ParseArtefact::Table(rows) => visitor.visit_table_mut(rows),
+        ParseArtefact::TableRow(columns) => visitor.visit_table_row_mut(columns),
+        ParseArtefact::Button(text, link) => visitor.visit_button_mut(text, link),
+        ParseArtefact::Image(caption, path) => visitor.visit_image_mut(caption, path),
+        ParseArtefact::Codeblock(code) => visitor.visit_codeblock_mut(code),
+        ParseArtefact::Equation(number, tex) => visitor.visit_equation_mut(number, tex),
+        ParseArtefact::Footnotes(footnotes) => visitor.visit_footnotes_mut(footnotes),
+        ParseArtefact::Footnote(number, label, text) => visitor.visit_footnote_mut(number, label, text),
+        ParseArtefact::LinkDefinition(label, url, title) => visitor.visit_link_definition_mut(label, url, title),
+        ParseArtefact::Newline => visitor.visit_newline_mut(),
+        ParseArtefact::Error(text) => visitor.visit_error_mut(text),
+    }
+}
+
+pub fn walk_text_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, text: &mut Text<'a>) {
+    for artefact in &mut text.0 {
+        visitor.visit_text_artefact_mut(&mut artefact.node);
+    }
+}
+
+/// Dispatches to the `visit_*_mut` method for the text artefact's variant.
+pub fn walk_text_artefact_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, artefact: &mut TextArtefact<'a>) {
+    match artefact {
+        TextArtefact::Raw(text) => visitor.visit_raw_mut(text),
+        TextArtefact::Bold(text) => visitor.visit_bold_mut(text),
+        TextArtefact::Italics(text) => visitor.visit_italics_mut(text),
+        TextArtefact::Link(text, url) => visitor.visit_link_mut(text, url),
+        TextArtefact::LinkReference(text, label) => visitor.visit_link_reference_mut(text, label),
+        TextArtefact::Strikethrough(text) => visitor.visit_strikethrough_mut(text),
+        TextArtefact::Underline(text) => visitor.visit_underline_mut(text),
+        TextArtefact::Code(text) => visitor.visit_code_mut(text),
+        TextArtefact::Math(tex) => visitor.visit_math_mut(tex),
+        TextArtefact::FootnoteReference(label) => visitor.visit_footnote_reference_mut(label),
+        TextArtefact::HeadingReference(label) => visitor.visit_heading_reference_mut(label),
+        TextArtefact::Superscript(text) => visitor.visit_superscript_mut(text),
+        TextArtefact::Subscript(text) => visitor.visit_subscript_mut(text),
+        TextArtefact::Mark(text) => visitor.visit_mark_mut(text),
+        TextArtefact::Kbd(keys) => visitor.visit_kbd_mut(keys),
+        TextArtefact::Image(alt, src) => visitor.visit_inline_image_mut(alt, src),
+        TextArtefact::Error(text) => visitor.visit_text_error_mut(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Vec<Spanned<ParseArtefact<'_>>> {
+        Parser::new(Lexer::new(source).tokenize()).parse().unwrap()
+    }
+
+    #[derive(Default)]
+    struct Links<'ast> {
+        urls: Vec<&'ast str>,
+        words: usize,
+    }
+
+    impl<'ast> Visitor<'ast> for Links<'ast> {
+        fn visit_link(&mut self, text: &'ast Text<'_>, url: &'ast str) {
+            self.urls.push(url);
+            self.visit_text(text);
+        }
+
+        fn visit_raw(&mut self, text: &'ast str) {
+            self.words += text.split_whitespace().count();
+        }
+    }
+
+    #[test]
+    fn visitor_collects_nested_nodes() {
+        let body = parse("Read ![the docs](docs.html) first\n- Item ![one](1.html)\n-- ![two](2.html)\n| ![three](3.html) |");
+        let mut links = Links::default();
+        links.visit_artefacts(&body);
+        assert_eq!(links.urls, vec!["docs.html", "1.html", "2.html", "3.html"]);
+        assert_eq!(links.words, 8);
+    }
+
+    struct Publish;
+
+    impl<'a> VisitorMut<'a> for Publish {
+        fn visit_artefacts_mut(&mut self, artefacts: &mut Vec<Spanned<ParseArtefact<'a>>>) {
+            artefacts.retain(|artefact| {
+                !matches!(&artefact.node, ParseArtefact::Quote(Text(text))
+                    if matches!(text.first().map(|raw| &raw.node), Some(TextArtefact::Raw(raw)) if raw.starts_with("DRAFT")))
+            });
+            walk_artefacts_mut(self, artefacts)
+        }
+
+        fn visit_image_mut(&mut self, _caption: &mut Text<'a>, path: &mut Cow<'a, str>) {
+            *path = Cow::Owned(format!("/static/{}", path));
+        }
+    }
+
+    #[test]
+    fn visitor_mut_rewrites_and_drops_nodes() {
+        let mut body = parse("> DRAFT: check this\n#[Logo](logo.png)\n> Published");
+        Publish.visit_artefacts_mut(&mut body);
+        assert_eq!(
+            body,
+            vec![
+                ParseArtefact::Image(Text::from("Logo"), Cow::from("/static/logo.png")),
+                ParseArtefact::Quote(Text(vec![TextArtefact::Raw(Cow::from("Published")).into()]))
+            ]
+        );
+    }
+}